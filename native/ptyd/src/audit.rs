@@ -0,0 +1,322 @@
+//! Optional high-assurance syscall auditing via `ptrace`, enabled with
+//! `--audit-syscalls`. Unlike `--trace-exec` (which only sees `execve` via the
+//! process-events connector), this traces `open`/`openat`/`connect`/`execve` on the
+//! child itself by single-stepping it through every syscall with `PTRACE_SYSCALL`,
+//! at real performance cost — it's meant for reviewing exactly what an untrusted
+//! command touched, not for routine sessions.
+//!
+//! `ptrace` tracing requires one thread to own every `waitpid`/`ptrace` call for the
+//! traced pid from the moment it's attached — and, more specifically, it must be the
+//! exact thread that forked the child (Linux records the tracer as the forking task,
+//! not just "some thread in this process"; a `ptrace` request from any other thread
+//! fails with `ESRCH`). So the fork itself happens inside a dedicated background
+//! thread, which then owns the entire ptrace step loop for that pid's lifetime,
+//! including reaping its real exit, and hands the exit code back to `relay_loop`
+//! over a pipe it can poll like any other fd instead of calling `waitpid` itself.
+//! While audit mode is active `relay_loop` must not call `waitpid` on the child at
+//! all; `main.rs` skips `pidfd_open` entirely for an audited session to keep that
+//! exit path out of the picture.
+//!
+//! `libc`'s `ptrace()` wrapper is C-variadic, which is easy to call wrong from Rust,
+//! so every request here goes through `libc::syscall(libc::SYS_ptrace, ...)`
+//! directly instead (the same reasoning behind `pty::pidfd_open`'s raw syscall use).
+//! String syscall arguments (paths) are read from `/proc/<pid>/mem` rather than via
+//! `PTRACE_PEEKTEXT`, since the raw syscall's PEEKTEXT ABI returns the word through
+//! an out-pointer rather than as the call's return value like the glibc wrapper
+//! does, and `/proc/<pid>/mem` sidesteps that distinction entirely (consistent with
+//! this codebase's general preference for `/proc` introspection over ptrace peeks).
+
+use libc::{c_int, pid_t};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::protocol::write_all_fd;
+use crate::pty::fork_and_exec_with_stderr;
+use crate::session::SessionContext;
+
+/// Not exposed by `libc` for any target; value is stable across architectures.
+const PTRACE_O_TRACESYSGOOD: libc::c_long = 1;
+
+/// An active ptrace-based syscall audit of one child, as seen by `relay_loop`: just
+/// a pollable fd that becomes readable with a 4-byte exit code once the child exits.
+pub struct SyscallAuditor {
+    exit_read_fd: c_int,
+}
+
+impl SyscallAuditor {
+    /// Forks `argv` onto the given pty/pipe fds from a dedicated background thread
+    /// (so that thread — not the caller's — becomes the child's ptrace tracer),
+    /// attaches, and if that all lands spawns the same thread into the step loop.
+    /// Returns the child pid alongside the auditor on success. Returns `None` on any
+    /// failure (fork failure, missing privilege, non-x86_64 target, the handshake not
+    /// landing where expected, ...) so the caller can fall back to an untraced fork.
+    #[cfg(target_arch = "x86_64")]
+    pub fn spawn(
+        argv: Vec<String>,
+        master_fd: c_int,
+        slave_fd: c_int,
+        stderr_pipe_write: Option<c_int>,
+        session: SessionContext,
+    ) -> Option<(pid_t, Self)> {
+        let (exit_read_fd, exit_write_fd) = crate::pty::open_stderr_pipe()?;
+        let (pid_tx, pid_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let pid = match fork_and_exec_with_stderr(&argv, master_fd, slave_fd, stderr_pipe_write, true, None, None) {
+                Some(pid) => pid,
+                None => {
+                    let _ = pid_tx.send(None);
+                    unsafe { libc::close(exit_write_fd) };
+                    return;
+                }
+            };
+
+            let mut status: c_int = 0;
+            let attached = unsafe { libc::waitpid(pid, &mut status, 0) } == pid
+                && libc::WIFSTOPPED(status)
+                && ptrace_request(libc::PTRACE_SETOPTIONS, pid, 0, PTRACE_O_TRACESYSGOOD) == 0;
+            if !attached {
+                // The fork already happened; leaving it stopped-and-abandoned would
+                // hang forever since no one else will ever resume it, so it's killed
+                // here rather than returned to the caller, which just forks a fresh
+                // (untraced) child instead.
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                    libc::waitpid(pid, &mut status, 0);
+                }
+                let _ = pid_tx.send(None);
+                unsafe { libc::close(exit_write_fd) };
+                return;
+            }
+
+            let _ = pid_tx.send(Some(pid));
+            run_supervisor(pid, exit_write_fd, session);
+        });
+
+        let pid = pid_rx.recv().ok().flatten()?;
+        Some((pid, SyscallAuditor { exit_read_fd }))
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn spawn(
+        _argv: Vec<String>,
+        _master_fd: c_int,
+        _slave_fd: c_int,
+        _stderr_pipe_write: Option<c_int>,
+        _session: SessionContext,
+    ) -> Option<(pid_t, Self)> {
+        None
+    }
+
+    pub fn fd(&self) -> c_int {
+        self.exit_read_fd
+    }
+
+    /// Call once `fd()` is reported readable. The write side always sends exactly 4
+    /// bytes in one `write`, so a short read here means the supervisor thread died
+    /// without reporting an exit; treat that the same as "not ready yet".
+    pub fn read_exit_code(&self) -> Option<i32> {
+        let mut buf = [0_u8; 4];
+        let n = unsafe { libc::read(self.exit_read_fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if n == 4 {
+            Some(i32::from_ne_bytes(buf))
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for SyscallAuditor {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.exit_read_fd) };
+    }
+}
+
+fn ptrace_request(request: libc::c_uint, pid: pid_t, addr: libc::c_long, data: libc::c_long) -> libc::c_long {
+    unsafe { libc::syscall(libc::SYS_ptrace, request as libc::c_long, pid as libc::c_long, addr, data) as libc::c_long }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn resume_to_next_syscall_stop(pid: pid_t) -> Result<(), ()> {
+    if ptrace_request(libc::PTRACE_SYSCALL, pid, 0, 0) == 0 {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn get_regs(pid: pid_t) -> Option<libc::user_regs_struct> {
+    let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+    let rc = ptrace_request(libc::PTRACE_GETREGS, pid, 0, std::ptr::addr_of_mut!(regs) as libc::c_long);
+    if rc != 0 {
+        return None;
+    }
+    Some(regs)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn syscall_name(nr: u64) -> Option<&'static str> {
+    match nr as i64 {
+        n if n == libc::SYS_open => Some("open"),
+        n if n == libc::SYS_openat => Some("openat"),
+        n if n == libc::SYS_connect => Some("connect"),
+        n if n == libc::SYS_execve => Some("execve"),
+        _ => None,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn describe_args(pid: pid_t, nr: u64, regs: &libc::user_regs_struct) -> String {
+    match nr as i64 {
+        n if n == libc::SYS_open || n == libc::SYS_execve => read_cstring(pid, regs.rdi).unwrap_or_default(),
+        n if n == libc::SYS_openat => read_cstring(pid, regs.rsi).unwrap_or_default(),
+        n if n == libc::SYS_connect => describe_sockaddr(pid, regs.rsi, regs.rdx),
+        _ => String::new(),
+    }
+}
+
+/// Single background thread that owns every subsequent `waitpid`/`PTRACE_SYSCALL`
+/// call for `pid`: steps it syscall-stop by syscall-stop, decodes entry/exit pairs
+/// for the syscalls this cares about, emits one audit event per completed call, and
+/// on the child's real exit reports the code over `exit_write_fd` instead of letting
+/// `relay_loop` reap it.
+#[cfg(target_arch = "x86_64")]
+fn run_supervisor(pid: pid_t, exit_write_fd: c_int, session: SessionContext) {
+    if resume_to_next_syscall_stop(pid).is_err() {
+        let _ = write_all_fd(exit_write_fd, &1_i32.to_ne_bytes());
+        unsafe { libc::close(exit_write_fd) };
+        return;
+    }
+
+    let mut entering = true;
+    let mut pending: Option<(u64, String)> = None;
+    loop {
+        let mut status: c_int = 0;
+        let waited = unsafe { libc::waitpid(pid, &mut status, 0) };
+        if waited != pid {
+            let _ = write_all_fd(exit_write_fd, &1_i32.to_ne_bytes());
+            break;
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            let code = crate::pty::child_exit_code(status);
+            let _ = write_all_fd(exit_write_fd, &code.to_ne_bytes());
+            break;
+        }
+        if !libc::WIFSTOPPED(status) {
+            let _ = resume_to_next_syscall_stop(pid);
+            continue;
+        }
+
+        // A syscall-stop is `SIGTRAP | 0x80` thanks to `PTRACE_O_TRACESYSGOOD`; any
+        // other stop is a real signal that would otherwise reach the child, so it's
+        // re-injected on resume rather than swallowed.
+        let stopsig = libc::WSTOPSIG(status);
+        if stopsig != (libc::SIGTRAP | 0x80) {
+            let deliver = if stopsig == libc::SIGTRAP { 0 } else { stopsig };
+            if ptrace_request(libc::PTRACE_SYSCALL, pid, 0, deliver as libc::c_long) != 0 {
+                let _ = write_all_fd(exit_write_fd, &1_i32.to_ne_bytes());
+                break;
+            }
+            continue;
+        }
+
+        if entering {
+            pending = get_regs(pid).and_then(|regs| {
+                syscall_name(regs.orig_rax).map(|_| (regs.orig_rax, describe_args(pid, regs.orig_rax, &regs)))
+            });
+            entering = false;
+        } else {
+            if let Some((nr, detail)) = pending.take() {
+                let retval = get_regs(pid).map(|regs| regs.rax as i64).unwrap_or(0);
+                emit_audit_event(&session, syscall_name(nr).unwrap_or("?"), &detail, retval);
+            }
+            entering = true;
+        }
+
+        if resume_to_next_syscall_stop(pid).is_err() {
+            let _ = write_all_fd(exit_write_fd, &1_i32.to_ne_bytes());
+            break;
+        }
+    }
+    unsafe { libc::close(exit_write_fd) };
+}
+
+#[cfg(target_arch = "x86_64")]
+fn emit_audit_event(session: &SessionContext, syscall: &str, detail: &str, retval: i64) {
+    let line = format!(
+        "{{{},\"event\":\"syscall-audit\",\"syscall\":{},\"detail\":{},\"retval\":{retval}}}\n",
+        session.fields_json(),
+        crate::lifecycle::json_escape(syscall),
+        crate::lifecycle::json_escape(detail),
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+#[cfg(target_arch = "x86_64")]
+fn read_cstring(pid: pid_t, addr: u64) -> Option<String> {
+    if addr == 0 {
+        return None;
+    }
+    let mut file = std::fs::OpenOptions::new().read(true).open(format!("/proc/{pid}/mem")).ok()?;
+    file.seek(SeekFrom::Start(addr)).ok()?;
+    let mut out = Vec::new();
+    let mut chunk = [0_u8; 256];
+    loop {
+        let n = file.read(&mut chunk).ok()?;
+        if n == 0 {
+            break;
+        }
+        match chunk[..n].iter().position(|&b| b == 0) {
+            Some(pos) => {
+                out.extend_from_slice(&chunk[..pos]);
+                break;
+            }
+            None => out.extend_from_slice(&chunk[..n]),
+        }
+        if out.len() > 4096 {
+            break;
+        }
+    }
+    Some(String::from_utf8_lossy(&out).into_owned())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn read_bytes(pid: pid_t, addr: u64, len: usize) -> Vec<u8> {
+    let Ok(mut file) = std::fs::OpenOptions::new().read(true).open(format!("/proc/{pid}/mem")) else {
+        return Vec::new();
+    };
+    if file.seek(SeekFrom::Start(addr)).is_err() {
+        return Vec::new();
+    }
+    let mut out = vec![0_u8; len];
+    match file.read_exact(&mut out) {
+        Ok(()) => out,
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Decodes the `struct sockaddr` a traced `connect` points at well enough to be
+/// useful in an audit log: family plus, for `AF_INET`/`AF_INET6`, address and port.
+#[cfg(target_arch = "x86_64")]
+fn describe_sockaddr(pid: pid_t, addr: u64, len: u64) -> String {
+    let bytes = read_bytes(pid, addr, (len as usize).min(128));
+    if bytes.len() < 2 {
+        return String::new();
+    }
+    let family = u16::from_ne_bytes([bytes[0], bytes[1]]);
+    match family as c_int {
+        libc::AF_INET if bytes.len() >= 8 => {
+            let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+            format!("{}.{}.{}.{}:{port}", bytes[4], bytes[5], bytes[6], bytes[7])
+        }
+        libc::AF_INET6 if bytes.len() >= 24 => {
+            let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+            let groups = bytes[8..24].chunks(2).map(|c| format!("{:02x}{:02x}", c[0], c[1])).collect::<Vec<_>>();
+            format!("[{}]:{port}", groups.join(":"))
+        }
+        libc::AF_UNIX => "unix socket".to_string(),
+        other => format!("family {other}"),
+    }
+}
@@ -0,0 +1,57 @@
+//! Tracks whether the child has asked the terminal for bracketed paste mode (DECSET/
+//! DECRST 2004, `ESC[?2004h`/`ESC[?2004l`) by watching for those sequences in its output
+//! stream — the same way `commands.rs` watches for OSC 133/7 — so `OPCODE_PASTE` knows
+//! whether to wrap a paste in `ESC[200~`/`ESC[201~` before handing it to the child.
+
+const ENABLE: &[u8] = b"\x1b[?2004h";
+const DISABLE: &[u8] = b"\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+#[derive(Default)]
+pub struct BracketedPasteTracker {
+    enabled: bool,
+}
+
+impl BracketedPasteTracker {
+    /// Call once per chunk of master output, in order, the same way
+    /// `CommandTracker::observe` is called — whichever of `ENABLE`/`DISABLE` appears
+    /// last in the chunk wins, since an application can toggle the mode more than once
+    /// in a single write (entering then leaving an editor inside the same flush, say).
+    pub fn observe(&mut self, chunk: &[u8]) {
+        let last_enable = last_index_of(chunk, ENABLE);
+        let last_disable = last_index_of(chunk, DISABLE);
+        match (last_enable, last_disable) {
+            (Some(e), Some(d)) => self.enabled = e > d,
+            (Some(_), None) => self.enabled = true,
+            (None, Some(_)) => self.enabled = false,
+            (None, None) => {}
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// Wraps `payload` in `PASTE_START`/`PASTE_END` if bracketed paste is currently
+/// enabled, so a client never has to special-case whether the application it's typing
+/// into asked for bracketing — `OPCODE_PASTE` always gets the right wire bytes from
+/// the same payload either way.
+pub fn frame_for_paste(payload: &[u8], bracketed: bool) -> Vec<u8> {
+    if !bracketed {
+        return payload.to_vec();
+    }
+    let mut wrapped = Vec::with_capacity(PASTE_START.len() + payload.len() + PASTE_END.len());
+    wrapped.extend_from_slice(PASTE_START);
+    wrapped.extend_from_slice(payload);
+    wrapped.extend_from_slice(PASTE_END);
+    wrapped
+}
+
+fn last_index_of(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    (0..=haystack.len() - needle.len()).rev().find(|&start| &haystack[start..start + needle.len()] == needle)
+}
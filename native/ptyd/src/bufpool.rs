@@ -0,0 +1,34 @@
+//! A small stack of reusable scratch buffers for the relay hot path. Default mode is
+//! one process per session and draws a single buffer from its pool for the whole
+//! relay loop; `serve` mode (daemon managing several sessions) does not use the pool.
+//! Nothing in the pool is tied to one session, so buffers checked back in with
+//! `release` can be picked up by whichever caller acquires next.
+
+pub struct BufPool {
+    buf_size: usize,
+    free: Vec<Vec<u8>>,
+}
+
+impl BufPool {
+    pub fn new(buf_size: usize) -> Self {
+        BufPool { buf_size, free: Vec::new() }
+    }
+
+    /// Hands out a zero-filled buffer of `buf_size`, reusing one returned via
+    /// `release` when available instead of allocating.
+    pub fn acquire(&mut self) -> Vec<u8> {
+        match self.free.pop() {
+            Some(mut buf) => {
+                buf.clear();
+                buf.resize(self.buf_size, 0);
+                buf
+            }
+            None => vec![0_u8; self.buf_size],
+        }
+    }
+
+    /// Returns `buf` to the pool so a later `acquire` can hand it out again.
+    pub fn release(&mut self, buf: Vec<u8>) {
+        self.free.push(buf);
+    }
+}
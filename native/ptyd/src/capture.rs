@@ -0,0 +1,248 @@
+//! Renders a byte stream of raw pty output (escape sequences and all) into one of the
+//! formats `OPCODE_CAPTURE_SCROLLBACK` can ask for: plain text with every escape
+//! sequence stripped, the bytes as-is (ANSI preserved, left to the caller), or HTML
+//! with SGR color/style codes translated to inline `<span style="...">` runs. Only SGR
+//! (`...m`) CSI sequences carry meaning in a static capture — cursor movement, erase,
+//! and other CSI/OSC sequences are dropped rather than replayed against any buffer.
+
+const ANSI_COLORS: [&str; 8] = [
+    "#000000", "#cc0000", "#4e9a06", "#c4a000", "#3465a4", "#75507b", "#06989a", "#d3d7cf",
+];
+const ANSI_BRIGHT_COLORS: [&str; 8] = [
+    "#555753", "#ef2929", "#8ae234", "#fce94f", "#729fcf", "#ad7fa8", "#34e2e2", "#eeeeec",
+];
+
+/// Strips every escape sequence, leaving only the printable text and newlines a
+/// reader would see.
+pub fn plain_text(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            skip_escape_sequence(&mut chars);
+            continue;
+        }
+        if c == '\r' {
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Translates SGR color/style codes into inline-styled `<span>` runs; every other
+/// escape sequence is dropped. The result is a single self-contained `<pre>` block.
+pub fn html(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut out = String::from("<pre style=\"background:#000;color:#eee;font-family:monospace\">");
+    let mut open_span = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            if chars.peek() == Some(&'[') {
+                chars.next();
+                let mut params = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_ascii_digit() || d == ';' {
+                        params.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let mut terminator = chars.next();
+                // Private-mode/intermediate bytes (`?`, `>`, ...) can sit before the
+                // final byte; consume through it so none of them leak into the text.
+                while let Some(t) = terminator {
+                    if t.is_ascii_alphabetic() || "~@".contains(t) {
+                        break;
+                    }
+                    terminator = chars.next();
+                }
+                if terminator == Some('m') {
+                    if open_span {
+                        out.push_str("</span>");
+                        open_span = false;
+                    }
+                    let style = sgr_to_css(&params);
+                    if !style.is_empty() {
+                        out.push_str(&format!("<span style=\"{style}\">"));
+                        open_span = true;
+                    }
+                }
+            } else {
+                skip_escape_sequence(&mut chars);
+            }
+            continue;
+        }
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    if open_span {
+        out.push_str("</span>");
+    }
+    out.push_str("</pre>");
+    out
+}
+
+/// Consumes one escape sequence already past the leading ESC: a CSI sequence up to its
+/// final byte, an OSC sequence up to its BEL/ST terminator, or (for anything else) a
+/// single following byte.
+fn skip_escape_sequence(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    match chars.peek() {
+        Some('[') => {
+            chars.next();
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() || "~@".contains(c) {
+                    break;
+                }
+            }
+        }
+        Some(']') => {
+            chars.next();
+            while let Some(c) = chars.next() {
+                if c == '\u{7}' {
+                    break;
+                }
+                if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                    chars.next();
+                    break;
+                }
+            }
+        }
+        Some(_) => {
+            chars.next();
+        }
+        None => {}
+    }
+}
+
+const CHAR_WIDTH_PX: u32 = 8;
+const LINE_HEIGHT_PX: u32 = 16;
+
+/// Renders to a self-contained SVG: one `<text>` row per line, with colored runs as
+/// `<tspan fill="...">` children. Sized to the longest line and the line count so the
+/// artifact doesn't need an external viewport to look right embedded in a doc.
+pub fn svg(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut lines: Vec<String> = vec![String::new()];
+    let mut cols_in_line = 0usize;
+    let mut max_cols = 0usize;
+    let mut current_fill: Option<String> = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            if chars.peek() == Some(&'[') {
+                chars.next();
+                let mut params = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_ascii_digit() || d == ';' {
+                        params.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let mut terminator = chars.next();
+                // Private-mode/intermediate bytes (`?`, `>`, ...) can sit before the
+                // final byte; consume through it so none of them leak into the text.
+                while let Some(t) = terminator {
+                    if t.is_ascii_alphabetic() || "~@".contains(t) {
+                        break;
+                    }
+                    terminator = chars.next();
+                }
+                if terminator == Some('m') {
+                    current_fill = sgr_foreground(&params, current_fill);
+                }
+            } else {
+                skip_escape_sequence(&mut chars);
+            }
+            continue;
+        }
+        if c == '\n' {
+            max_cols = max_cols.max(cols_in_line);
+            cols_in_line = 0;
+            lines.push(String::new());
+            continue;
+        }
+        if c == '\r' {
+            continue;
+        }
+        let escaped = match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            c => c.to_string(),
+        };
+        let fill = current_fill.as_deref().unwrap_or("#eee");
+        lines
+            .last_mut()
+            .unwrap()
+            .push_str(&format!("<tspan fill=\"{fill}\">{escaped}</tspan>"));
+        cols_in_line += 1;
+    }
+    max_cols = max_cols.max(cols_in_line);
+
+    let width = (max_cols.max(1) as u32) * CHAR_WIDTH_PX;
+    let height = (lines.len() as u32) * LINE_HEIGHT_PX;
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" font-family=\"monospace\" font-size=\"{LINE_HEIGHT_PX}\">\
+         <rect width=\"100%\" height=\"100%\" fill=\"#000\"/>"
+    );
+    for (i, line) in lines.iter().enumerate() {
+        let y = (i as u32 + 1) * LINE_HEIGHT_PX;
+        out.push_str(&format!("<text x=\"0\" y=\"{y}\">{line}</text>"));
+    }
+    out.push_str("</svg>");
+    out
+}
+
+/// Just the foreground color half of `sgr_to_css`, for the SVG renderer: an SVG
+/// `<tspan>` carries one `fill`, so bold/italic/underline/background don't have a
+/// place to go there the way they do in an HTML inline style.
+fn sgr_foreground(params: &str, mut fg: Option<String>) -> Option<String> {
+    let codes: Vec<i32> = if params.is_empty() {
+        vec![0]
+    } else {
+        params.split(';').map(|s| s.parse().unwrap_or(0)).collect()
+    };
+    for code in codes {
+        match code {
+            0 => fg = None,
+            30..=37 => fg = Some(ANSI_COLORS[(code - 30) as usize].to_string()),
+            90..=97 => fg = Some(ANSI_BRIGHT_COLORS[(code - 90) as usize].to_string()),
+            _ => {}
+        }
+    }
+    fg
+}
+
+fn sgr_to_css(params: &str) -> String {
+    let codes: Vec<i32> = if params.is_empty() {
+        vec![0]
+    } else {
+        params.split(';').map(|s| s.parse().unwrap_or(0)).collect()
+    };
+    let mut styles = Vec::new();
+    for code in codes {
+        match code {
+            0 => styles.clear(),
+            1 => styles.push("font-weight:bold".to_string()),
+            3 => styles.push("font-style:italic".to_string()),
+            4 => styles.push("text-decoration:underline".to_string()),
+            30..=37 => styles.push(format!("color:{}", ANSI_COLORS[(code - 30) as usize])),
+            40..=47 => styles.push(format!("background-color:{}", ANSI_COLORS[(code - 40) as usize])),
+            90..=97 => styles.push(format!("color:{}", ANSI_BRIGHT_COLORS[(code - 90) as usize])),
+            100..=107 => styles.push(format!("background-color:{}", ANSI_BRIGHT_COLORS[(code - 100) as usize])),
+            _ => {}
+        }
+    }
+    styles.join(";")
+}
@@ -0,0 +1,120 @@
+//! Scans child output for OSC 52 clipboard-write/query sequences (`ESC]52;Pc;Pd`,
+//! terminated by BEL or ST) and gates them through `--clipboard-policy`, the same way
+//! `bracketed_paste.rs` watches for DECSET 2004 — except here each match is acted on
+//! immediately rather than toggling persistent state, since one OSC 52 sequence is a
+//! complete, one-shot clipboard operation rather than a mode switch. The harness needs
+//! to mediate clipboard access for security: a child that can silently write to (or
+//! read from) the host clipboard via an escape sequence shouldn't get to do so
+//! unobserved, or — under `deny` — at all.
+
+const OSC52_PREFIX: &[u8] = b"\x1b]52;";
+const ST: &[u8] = b"\x1b\\";
+const BEL: u8 = 0x07;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipboardPolicy {
+    /// Forward the sequence to the terminal unchanged, same as `EventOnly` — an
+    /// explicit opt-in to clipboard access rather than the default's implicit one.
+    Allow,
+    /// Strip the sequence so it never reaches the real terminal: the host only ever
+    /// sees the structured event, never the escape bytes that would have triggered
+    /// its own clipboard handling.
+    Deny,
+    /// Forward the sequence unchanged, same as `Allow`. The default: existing
+    /// passthrough behavior for a host that hasn't opted into enforcement is left
+    /// alone, with the new `clipboard` event layered on top purely for visibility.
+    #[default]
+    EventOnly,
+}
+
+/// Parses `--clipboard-policy`'s argument; `None` for anything else, so an unrecognized
+/// value can be reported the same way `--child-encoding` reports an unknown encoding
+/// name, rather than silently falling back to the default.
+pub fn parse_policy(name: &str) -> Option<ClipboardPolicy> {
+    match name {
+        "allow" => Some(ClipboardPolicy::Allow),
+        "deny" => Some(ClipboardPolicy::Deny),
+        "event-only" => Some(ClipboardPolicy::EventOnly),
+        _ => None,
+    }
+}
+
+/// One OSC 52 sequence found by `scan_and_filter`. `payload_base64` is `Pd` verbatim —
+/// either the base64 clipboard payload the child wants written, or the literal `?` a
+/// query uses — left undecoded since it's the host's business what to do with it, not
+/// ptyd's to validate as well-formed base64.
+pub struct ClipboardWrite {
+    pub selection: u8,
+    pub payload_base64: String,
+    pub is_query: bool,
+    pub allowed: bool,
+}
+
+/// Scans `chunk` for complete OSC 52 sequences and, per `policy`, returns the bytes
+/// that should actually reach the terminal (identical to `chunk` unless `policy` is
+/// `Deny`, in which case every OSC 52 sequence found is cut out) alongside one
+/// `ClipboardWrite` per sequence found, in order. A sequence whose terminator hasn't
+/// arrived yet in this chunk is left untouched rather than guessed at, and a sequence
+/// split across a `read` boundary entirely is missed — the same limitation
+/// `commands.rs`'s OSC 133/7 scanning already accepts.
+pub fn scan_and_filter(chunk: &[u8], policy: ClipboardPolicy) -> (Vec<u8>, Vec<ClipboardWrite>) {
+    let mut events = Vec::new();
+    let mut filtered = Vec::with_capacity(chunk.len());
+    let mut pos = 0;
+    while let Some(rel_start) = find(&chunk[pos..], OSC52_PREFIX) {
+        let start = pos + rel_start;
+        filtered.extend_from_slice(&chunk[pos..start]);
+        let body_start = start + OSC52_PREFIX.len();
+        let Some((body_len, terminator_len)) = find_terminator(&chunk[body_start..]) else {
+            filtered.extend_from_slice(&chunk[start..]);
+            pos = chunk.len();
+            break;
+        };
+        let sequence_end = body_start + body_len + terminator_len;
+        match parse_body(&chunk[body_start..body_start + body_len]) {
+            Some((selection, payload)) => {
+                let allowed = !matches!(policy, ClipboardPolicy::Deny);
+                events.push(ClipboardWrite {
+                    selection,
+                    payload_base64: payload.to_string(),
+                    is_query: payload == "?",
+                    allowed,
+                });
+                if allowed {
+                    filtered.extend_from_slice(&chunk[start..sequence_end]);
+                }
+            }
+            // Malformed body (no `;`, empty selection) — not ours to police, pass it
+            // through unchanged regardless of policy.
+            None => filtered.extend_from_slice(&chunk[start..sequence_end]),
+        }
+        pos = sequence_end;
+    }
+    filtered.extend_from_slice(&chunk[pos..]);
+    (filtered, events)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    (0..=haystack.len() - needle.len()).find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+fn find_terminator(rest: &[u8]) -> Option<(usize, usize)> {
+    let bel = rest.iter().position(|&b| b == BEL).map(|idx| (idx, 1));
+    let st = find(rest, ST).map(|idx| (idx, ST.len()));
+    match (bel, st) {
+        (Some(b), Some(s)) => Some(if b.0 <= s.0 { b } else { s }),
+        (Some(b), None) => Some(b),
+        (None, Some(s)) => Some(s),
+        (None, None) => None,
+    }
+}
+
+fn parse_body(body: &[u8]) -> Option<(u8, &str)> {
+    let text = std::str::from_utf8(body).ok()?;
+    let (selection, payload) = text.split_once(';')?;
+    let selection = selection.as_bytes().first().copied()?;
+    Some((selection, payload))
+}
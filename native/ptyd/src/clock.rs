@@ -0,0 +1,112 @@
+//! A seam between "what time is it" and the rest of ptyd — debounce windows, the
+//! `--cpu-budget-ms` poll interval, idle-stats wall time, and the timestamps attached
+//! to recorded output (scrollback, command boundaries) — so that logic can be driven
+//! deterministically by a `FakeClock` in tests instead of real sleeps. Everything here
+//! speaks in milliseconds, matching every other `_ms` field and flag in this codebase,
+//! rather than introducing `Duration`/`Instant` wrapper types most of the rest of ptyd
+//! doesn't use.
+//!
+//! `poll(2)`'s own timeout still blocks on real OS time no matter what `Clock` a
+//! session is built with — only the decision logic built on top of "how much time has
+//! passed" is abstracted here, not the blocking syscall itself. Simulating that too
+//! would mean reimplementing the relay loop's event sourcing, which is a different
+//! (and much larger) project than making its timing decisions unit-testable.
+
+#[cfg(test)]
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Clock {
+    /// Milliseconds on some monotonically increasing clock with no defined epoch; only
+    /// the difference between two calls is meaningful. Used for debounce/timeout
+    /// deadlines, which only ever care about elapsed time, never wall time.
+    fn monotonic_ms(&self) -> i64;
+
+    /// Wall-clock milliseconds since the Unix epoch, for timestamps that get recorded
+    /// (scrollback entries, command boundaries, session start/end).
+    fn unix_ms(&self) -> i64;
+}
+
+/// What every real binary entry point wires in. Shared via `Rc` (see `SharedClock`)
+/// rather than passed by value since it's handed to several structs that outlive a
+/// single call (`IdleStats`, `CommandLifecycle`, `InputRateLimiter`, ...).
+#[derive(Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn monotonic_ms(&self) -> i64 {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_millis() as i64
+    }
+
+    fn unix_ms(&self) -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+    }
+}
+
+/// How every timing-sensitive struct in this codebase is handed its clock: cheaply
+/// `Clone`-able, and a trait object so callers don't need to be generic over which
+/// `Clock` impl they were built with.
+pub type SharedClock = Rc<dyn Clock>;
+
+/// A single `Cell<i64>` standing in for both of `Clock`'s notions of time — nothing
+/// here needs them to advance independently, so a test just calls `advance_ms` once per
+/// simulated tick. Cloning a `FakeClock` shares the same underlying cell, so a test can
+/// keep its own handle to advance the exact clock it handed to the code under test.
+/// Test-only: no binary entry point ever wires up anything but `SystemClock`.
+#[cfg(test)]
+#[derive(Clone)]
+pub(crate) struct FakeClock(Rc<Cell<i64>>);
+
+#[cfg(test)]
+impl FakeClock {
+    pub(crate) fn new(start_unix_ms: i64) -> Self {
+        FakeClock(Rc::new(Cell::new(start_unix_ms)))
+    }
+
+    pub(crate) fn advance_ms(&self, ms: i64) {
+        self.0.set(self.0.get() + ms);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn monotonic_ms(&self) -> i64 {
+        self.0.get()
+    }
+
+    fn unix_ms(&self) -> i64 {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_at_the_given_time_on_both_notions_of_time() {
+        let clock = FakeClock::new(1_700_000_000_000);
+        assert_eq!(clock.monotonic_ms(), 1_700_000_000_000);
+        assert_eq!(clock.unix_ms(), 1_700_000_000_000);
+    }
+
+    #[test]
+    fn advance_ms_moves_both_notions_of_time_together() {
+        let clock = FakeClock::new(0);
+        clock.advance_ms(250);
+        clock.advance_ms(50);
+        assert_eq!(clock.monotonic_ms(), 300);
+        assert_eq!(clock.unix_ms(), 300);
+    }
+
+    #[test]
+    fn clones_share_the_same_underlying_clock() {
+        let clock = FakeClock::new(0);
+        let handle = clock.clone();
+        handle.advance_ms(10);
+        assert_eq!(clock.monotonic_ms(), 10);
+    }
+}
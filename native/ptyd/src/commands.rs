@@ -0,0 +1,141 @@
+//! Tracks per-command output boundaries by watching for OSC 133 shell-integration
+//! sequences in the child's output stream, so a client can later ask "what did
+//! command #N print" instead of the host slicing raw output by timestamp.
+
+const OSC133_COMMAND_START: &[u8] = b"\x1b]133;C";
+const OSC133_COMMAND_END: &[u8] = b"\x1b]133;D";
+const OSC7_PREFIX: &[u8] = b"\x1b]7;";
+
+#[derive(Default)]
+pub struct CommandRegion {
+    pub start: u64,
+    pub end: Option<u64>,
+    pub correlation_id: Option<u32>,
+}
+
+/// A command boundary crossing noticed during `observe`, for the caller to turn
+/// into a command-start/command-end event on whatever event channel it uses.
+pub enum CommandBoundary {
+    Start { index: usize, correlation_id: Option<u32> },
+    End { index: usize, correlation_id: Option<u32> },
+}
+
+#[derive(Default)]
+pub struct CommandTracker {
+    regions: Vec<CommandRegion>,
+    total_bytes: u64,
+    history: Vec<u8>,
+    pending_correlation_id: Option<u32>,
+    osc7_cwd: Option<String>,
+}
+
+impl CommandTracker {
+    const MAX_HISTORY_BYTES: usize = 1 << 20;
+
+    /// Associates the next detected command-start boundary with a client-supplied
+    /// correlation id (see `OPCODE_DATA_TAGGED`).
+    pub fn tag_next_command(&mut self, correlation_id: u32) {
+        self.pending_correlation_id = Some(correlation_id);
+    }
+
+    /// Call once per chunk of master output, in order, before relaying it onward.
+    /// Returns command-start/end boundaries noticed in this chunk, plus the shell's
+    /// new cwd if an OSC 7 sequence in it reported one different from the last one
+    /// seen (`None` on every chunk that doesn't change it, including ones with no
+    /// OSC 7 at all).
+    pub fn observe(&mut self, chunk: &[u8]) -> (Vec<CommandBoundary>, Option<String>) {
+        let chunk_offset = self.total_bytes;
+        let mut boundaries = Vec::new();
+
+        for idx in memchr_all(chunk, OSC133_COMMAND_START) {
+            let correlation_id = self.pending_correlation_id.take();
+            self.regions.push(CommandRegion {
+                start: chunk_offset + idx as u64,
+                end: None,
+                correlation_id,
+            });
+            boundaries.push(CommandBoundary::Start {
+                index: self.regions.len() - 1,
+                correlation_id,
+            });
+        }
+        for idx in memchr_all(chunk, OSC133_COMMAND_END) {
+            if let Some((index, open)) = self
+                .regions
+                .iter_mut()
+                .enumerate()
+                .rev()
+                .find(|(_, r)| r.end.is_none())
+            {
+                open.end = Some(chunk_offset + idx as u64);
+                boundaries.push(CommandBoundary::End {
+                    index,
+                    correlation_id: open.correlation_id,
+                });
+            }
+        }
+
+        let mut cwd_changed = None;
+        for idx in memchr_all(chunk, OSC7_PREFIX) {
+            if let Some(cwd) = parse_osc7(&chunk[idx + OSC7_PREFIX.len()..]) {
+                if self.osc7_cwd.as_deref() != Some(cwd.as_str()) {
+                    cwd_changed = Some(cwd.clone());
+                }
+                self.osc7_cwd = Some(cwd);
+            }
+        }
+
+        self.history.extend_from_slice(chunk);
+        if self.history.len() > Self::MAX_HISTORY_BYTES {
+            let overflow = self.history.len() - Self::MAX_HISTORY_BYTES;
+            self.history.drain(0..overflow);
+        }
+        self.total_bytes += chunk.len() as u64;
+
+        (boundaries, cwd_changed)
+    }
+
+    /// Best-known cwd: the last OSC 7 report from the shell, or `None` if the shell
+    /// has never emitted one (callers should fall back to `/proc/<pid>/cwd`).
+    pub fn osc7_cwd(&self) -> Option<&str> {
+        self.osc7_cwd.as_deref()
+    }
+
+    /// Returns the captured bytes for command `index` (0-based), if still retained
+    /// in the bounded history buffer.
+    pub fn command_output(&self, index: usize) -> Option<&[u8]> {
+        let region = self.regions.get(index)?;
+        let end = region.end.unwrap_or(self.total_bytes);
+        let history_start = self.total_bytes.saturating_sub(self.history.len() as u64);
+        if region.start < history_start {
+            return None;
+        }
+        let start = (region.start - history_start) as usize;
+        let end = (end.saturating_sub(history_start)) as usize;
+        self.history.get(start..end.min(self.history.len()))
+    }
+}
+
+/// Parses the remainder of an OSC 7 sequence (`file://host/path` up to BEL or ST)
+/// into a plain filesystem path.
+fn parse_osc7(rest: &[u8]) -> Option<String> {
+    let terminator = rest.iter().position(|&b| b == 0x07 || b == 0x1b)?;
+    let body = std::str::from_utf8(&rest[..terminator]).ok()?;
+    let path = body.strip_prefix("file://").and_then(|s| s.split_once('/').map(|(_, p)| p)).unwrap_or(body);
+    Some(format!("/{}", path.trim_start_matches('/')))
+}
+
+fn memchr_all(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut start = 0;
+    while start + needle.len() <= haystack.len() {
+        match haystack[start..].windows(needle.len()).position(|w| w == needle) {
+            Some(rel) => {
+                offsets.push(start + rel);
+                start += rel + needle.len();
+            }
+            None => break,
+        }
+    }
+    offsets
+}
@@ -0,0 +1,23 @@
+//! zstd framing for `OPCODE_DATA_COMPRESSED`/`OUTPUT_OPCODE_DATA_COMPRESSED` (see
+//! `protocol.rs`). Unlike `crc32.rs`, a real compression codec is well past what's
+//! worth hand-rolling, so this just wraps the `zstd` crate's one-shot `bulk` API —
+//! each chunk is compressed/decompressed independently, with no dictionary or
+//! cross-chunk state, matching how every other per-chunk frame in this protocol is
+//! handled.
+
+/// Default `zstd` compression level for `OUTPUT_OPCODE_DATA_COMPRESSED` frames — the
+/// library's own default, a reasonable ratio/speed tradeoff for interactive output
+/// ptyd has no particular reason to second-guess.
+pub const DEFAULT_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+pub fn compress(bytes: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+    zstd::bulk::compress(bytes, level)
+}
+
+/// Decompresses `bytes`, which must expand to exactly `original_len` — the length a
+/// `OPCODE_DATA_COMPRESSED`/`OUTPUT_OPCODE_DATA_COMPRESSED` frame carries alongside the
+/// compressed payload, needed upfront since `zstd::bulk::decompress` takes an output
+/// capacity rather than growing to fit.
+pub fn decompress(bytes: &[u8], original_len: usize) -> std::io::Result<Vec<u8>> {
+    zstd::bulk::decompress(bytes, original_len)
+}
@@ -0,0 +1,172 @@
+//! Optional `~/.config/ptyd/config.toml` with a `[defaults]` table and named
+//! `[profiles.NAME]` tables, selected with `--profile NAME`, so hosts stop having to
+//! template a dozen flags onto every invocation. Every setting can also come from a
+//! `PTYD_*` environment variable, for container deployments that would rather set env
+//! vars than template a command line. Precedence, highest first: CLI flag, env var,
+//! profile, `[defaults]` — see `run_default` in `main.rs`, which applies each source
+//! only where a higher-precedence one left the setting unset.
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Default, Clone)]
+pub struct ConfigValues {
+    pub stderr_fd: Option<libc::c_int>,
+    pub tee_file: Option<String>,
+    pub report_idle_stats: Option<bool>,
+    pub scrollback_file: Option<String>,
+    pub scrollback_bytes: Option<usize>,
+    pub summary: Option<bool>,
+    pub child_encoding: Option<String>,
+    pub min_cols: Option<u16>,
+    pub max_cols: Option<u16>,
+    pub min_rows: Option<u16>,
+    pub max_rows: Option<u16>,
+    pub resize_debounce_ms: Option<u64>,
+    pub max_input_bytes_per_sec: Option<u64>,
+    pub input_tee_file: Option<String>,
+    pub status_fd: Option<libc::c_int>,
+    pub events_fd: Option<libc::c_int>,
+    pub cpu_budget_ms: Option<u64>,
+    pub compress_min_bytes: Option<usize>,
+    pub control_fd: Option<libc::c_int>,
+    pub clipboard_policy: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct ConfigFile {
+    #[serde(default)]
+    defaults: ConfigValues,
+    #[serde(default)]
+    profiles: std::collections::BTreeMap<String, ConfigValues>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(PathBuf::from(home).join(".config/ptyd/config.toml"))
+}
+
+/// Loads `[defaults]`, overlaid with `[profiles.<profile>]` if given and present.
+/// Returns an empty `ConfigValues` (not an error) when the file is absent, since the
+/// config file is entirely optional.
+pub fn load(profile: Option<&str>) -> ConfigValues {
+    let Some(path) = config_path() else {
+        return ConfigValues::default();
+    };
+    let Ok(text) = std::fs::read_to_string(&path) else {
+        return ConfigValues::default();
+    };
+    let Ok(file) = toml::from_str::<ConfigFile>(&text) else {
+        return ConfigValues::default();
+    };
+
+    let mut values = file.defaults;
+    if let Some(name) = profile {
+        if let Some(profile_values) = file.profiles.get(name) {
+            if profile_values.stderr_fd.is_some() {
+                values.stderr_fd = profile_values.stderr_fd;
+            }
+            if profile_values.tee_file.is_some() {
+                values.tee_file = profile_values.tee_file.clone();
+            }
+            if profile_values.report_idle_stats.is_some() {
+                values.report_idle_stats = profile_values.report_idle_stats;
+            }
+            if profile_values.scrollback_file.is_some() {
+                values.scrollback_file = profile_values.scrollback_file.clone();
+            }
+            if profile_values.scrollback_bytes.is_some() {
+                values.scrollback_bytes = profile_values.scrollback_bytes;
+            }
+            if profile_values.summary.is_some() {
+                values.summary = profile_values.summary;
+            }
+            if profile_values.child_encoding.is_some() {
+                values.child_encoding = profile_values.child_encoding.clone();
+            }
+            if profile_values.min_cols.is_some() {
+                values.min_cols = profile_values.min_cols;
+            }
+            if profile_values.max_cols.is_some() {
+                values.max_cols = profile_values.max_cols;
+            }
+            if profile_values.min_rows.is_some() {
+                values.min_rows = profile_values.min_rows;
+            }
+            if profile_values.max_rows.is_some() {
+                values.max_rows = profile_values.max_rows;
+            }
+            if profile_values.resize_debounce_ms.is_some() {
+                values.resize_debounce_ms = profile_values.resize_debounce_ms;
+            }
+            if profile_values.max_input_bytes_per_sec.is_some() {
+                values.max_input_bytes_per_sec = profile_values.max_input_bytes_per_sec;
+            }
+            if profile_values.input_tee_file.is_some() {
+                values.input_tee_file = profile_values.input_tee_file.clone();
+            }
+            if profile_values.status_fd.is_some() {
+                values.status_fd = profile_values.status_fd;
+            }
+            if profile_values.events_fd.is_some() {
+                values.events_fd = profile_values.events_fd;
+            }
+            if profile_values.cpu_budget_ms.is_some() {
+                values.cpu_budget_ms = profile_values.cpu_budget_ms;
+            }
+            if profile_values.compress_min_bytes.is_some() {
+                values.compress_min_bytes = profile_values.compress_min_bytes;
+            }
+            if profile_values.control_fd.is_some() {
+                values.control_fd = profile_values.control_fd;
+            }
+            if profile_values.clipboard_policy.is_some() {
+                values.clipboard_policy = profile_values.clipboard_policy.clone();
+            }
+        }
+    }
+    values
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// `PTYD_STDERR_FD`, `PTYD_TEE_FILE`, `PTYD_REPORT_IDLE_STATS` (`1`/`true`/`yes`),
+/// `PTYD_SCROLLBACK_FILE`, `PTYD_SCROLLBACK_BYTES`, `PTYD_SUMMARY` (`1`/`true`/`yes`),
+/// `PTYD_CHILD_ENCODING`, `PTYD_MIN_COLS`, `PTYD_MAX_COLS`, `PTYD_MIN_ROWS`,
+/// `PTYD_MAX_ROWS`, `PTYD_RESIZE_DEBOUNCE_MS`, `PTYD_MAX_INPUT_BYTES_PER_SEC`,
+/// `PTYD_INPUT_TEE_FILE`, `PTYD_STATUS_FD`, `PTYD_EVENTS_FD`, `PTYD_CPU_BUDGET_MS`,
+/// `PTYD_COMPRESS_MIN_BYTES`, `PTYD_CONTROL_FD`, `PTYD_CLIPBOARD_POLICY`.
+/// `PTYD_PROFILE` is read separately via
+/// `env_profile` since it selects *which* profile `load` reads, rather than being a
+/// value `load` could itself return.
+pub fn env_profile() -> Option<String> {
+    env_var("PTYD_PROFILE")
+}
+
+pub fn env_overrides() -> ConfigValues {
+    ConfigValues {
+        stderr_fd: env_var("PTYD_STDERR_FD").and_then(|v| v.parse().ok()),
+        tee_file: env_var("PTYD_TEE_FILE"),
+        report_idle_stats: env_var("PTYD_REPORT_IDLE_STATS").map(|v| matches!(v.as_str(), "1" | "true" | "yes")),
+        scrollback_file: env_var("PTYD_SCROLLBACK_FILE"),
+        scrollback_bytes: env_var("PTYD_SCROLLBACK_BYTES").and_then(|v| v.parse().ok()),
+        summary: env_var("PTYD_SUMMARY").map(|v| matches!(v.as_str(), "1" | "true" | "yes")),
+        child_encoding: env_var("PTYD_CHILD_ENCODING"),
+        min_cols: env_var("PTYD_MIN_COLS").and_then(|v| v.parse().ok()),
+        max_cols: env_var("PTYD_MAX_COLS").and_then(|v| v.parse().ok()),
+        min_rows: env_var("PTYD_MIN_ROWS").and_then(|v| v.parse().ok()),
+        max_rows: env_var("PTYD_MAX_ROWS").and_then(|v| v.parse().ok()),
+        resize_debounce_ms: env_var("PTYD_RESIZE_DEBOUNCE_MS").and_then(|v| v.parse().ok()),
+        max_input_bytes_per_sec: env_var("PTYD_MAX_INPUT_BYTES_PER_SEC").and_then(|v| v.parse().ok()),
+        input_tee_file: env_var("PTYD_INPUT_TEE_FILE"),
+        status_fd: env_var("PTYD_STATUS_FD").and_then(|v| v.parse().ok()),
+        events_fd: env_var("PTYD_EVENTS_FD").and_then(|v| v.parse().ok()),
+        cpu_budget_ms: env_var("PTYD_CPU_BUDGET_MS").and_then(|v| v.parse().ok()),
+        compress_min_bytes: env_var("PTYD_COMPRESS_MIN_BYTES").and_then(|v| v.parse().ok()),
+        control_fd: env_var("PTYD_CONTROL_FD").and_then(|v| v.parse().ok()),
+        clipboard_policy: env_var("PTYD_CLIPBOARD_POLICY"),
+    }
+}
@@ -0,0 +1,43 @@
+//! Newline-delimited JSON control channel — `--control-fd N` (see `main.rs`) — as an
+//! alternative to multiplexing `OPCODE_*` control frames into the same binary stdin
+//! stream terminal data already rides on. Lets a shell script or a quick prototype
+//! drive resize/signal/close/queries with `echo`/`jq` instead of hand-rolling
+//! `protocol.rs`'s binary framing. Orthogonal to terminal data itself: with
+//! `--control-fd` set, stdin carries nothing but raw bytes straight to the child (see
+//! `run_default`'s doc comment), the same way `--raw-output` leaves stdout unframed.
+
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ControlMessage {
+    Resize { cols: u16, rows: u16 },
+    Signal { signal: i32 },
+    Close,
+    CloseGraceful { grace_ms: Option<u32> },
+    QueryWinsize,
+    QueryInfo,
+    QueryState,
+    Stats,
+}
+
+/// Drains as many complete newline-delimited JSON lines as `buf` holds, leaving any
+/// trailing partial line in place for the next read. A line that fails to parse (bad
+/// JSON, unknown `op`, wrong field types) is dropped rather than treated as a fatal
+/// framing error — the same leniency `parse_defer_exec_frames` gives an unrecognized
+/// opcode byte, since one malformed line from a hand-typed prototype shouldn't take
+/// the whole control channel down.
+pub fn parse_control_lines(buf: &mut Vec<u8>) -> Vec<ControlMessage> {
+    let mut messages = Vec::new();
+    let mut consumed = 0;
+    while let Some(offset) = buf[consumed..].iter().position(|&b| b == b'\n') {
+        let line_end = consumed + offset;
+        let line = &buf[consumed..line_end];
+        if let Ok(message) = serde_json::from_slice::<ControlMessage>(line) {
+            messages.push(message);
+        }
+        consumed = line_end + 1;
+    }
+    buf.drain(..consumed);
+    messages
+}
@@ -0,0 +1,98 @@
+//! `--cpu-budget-ms N`: kills the session once the child tree's cumulative CPU time
+//! exceeds a configured budget, separate from any wall-clock timeout — meant to catch
+//! a spin loop in an otherwise quiet session (no output, no exit) that a wall-clock
+//! timeout alone can't distinguish from legitimate long-running work. Usage is tracked
+//! via a dedicated cgroup v2's `cpu.stat`, not `rusage.rs`'s `getrusage(RUSAGE_CHILDREN)`
+//! approximation, since that only aggregates children that have already been reaped and
+//! would never notice a single long-lived offender. A `cpu-budget-warning` event fires
+//! once usage crosses `WARN_PCT` of the budget, giving a wrapper advance notice before
+//! the kill.
+//!
+//! Creating and delegating a cgroup needs root or a pre-delegated subtree, so like
+//! `--trace-exec`/`--audit-syscalls` this degrades silently (no `cpu-budget-*` events,
+//! no enforcement) rather than failing the session when that's not available.
+
+use std::fs;
+use std::path::PathBuf;
+
+use libc::pid_t;
+
+/// Usage crossing this percentage of the budget fires one `cpu-budget-warning` event.
+const WARN_PCT: u64 = 80;
+
+pub enum CpuBudgetEvent {
+    Warning { usage_ms: u64, budget_ms: u64 },
+    Exceeded { usage_ms: u64, budget_ms: u64 },
+}
+
+/// A cgroup v2 created for one session's child tree, tracking its cumulative CPU time
+/// against a configured budget.
+pub struct CpuBudget {
+    cgroup_path: PathBuf,
+    budget_ms: u64,
+    budget_us: u64,
+    warn_us: u64,
+    warned: bool,
+}
+
+impl CpuBudget {
+    /// Creates `/sys/fs/cgroup/ptyd-<session_id>.scope` and moves `pid` into it before
+    /// it execs, so every descendant it ever forks inherits the same cgroup. Returns
+    /// `None` on any failure (no permission, cgroupfs not mounted, not delegated, ...)
+    /// so the caller can run the session without budget enforcement.
+    pub fn create(session_id: &str, pid: pid_t, budget_ms: u64) -> Option<Self> {
+        let cgroup_path = PathBuf::from(format!("/sys/fs/cgroup/ptyd-{session_id}.scope"));
+        fs::create_dir(&cgroup_path).ok()?;
+        if fs::write(cgroup_path.join("cgroup.procs"), pid.to_string()).is_err() {
+            let _ = fs::remove_dir(&cgroup_path);
+            return None;
+        }
+        let budget_us = budget_ms.saturating_mul(1000);
+        let budget = CpuBudget {
+            cgroup_path,
+            budget_ms,
+            budget_us,
+            warn_us: budget_us * WARN_PCT / 100,
+            warned: false,
+        };
+        if budget.usage_us().is_some() {
+            return Some(budget);
+        }
+        // `cpu.stat` not existing means this cgroup hierarchy can't give us what we
+        // need; move the pid back out before giving up so it isn't stuck in a cgroup
+        // this struct never gets to `Drop`-clean (an empty `cgroup.procs` moves to the
+        // root cgroup, which always exists).
+        let _ = fs::write("/sys/fs/cgroup/cgroup.procs", pid.to_string());
+        let _ = fs::remove_dir_all(&budget.cgroup_path);
+        None
+    }
+
+    fn usage_us(&self) -> Option<u64> {
+        let stat = fs::read_to_string(self.cgroup_path.join("cpu.stat")).ok()?;
+        stat.lines().find_map(|line| line.strip_prefix("usage_usec ")).and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Call periodically from the relay loop. Reports at most one warning (the first
+    /// time usage crosses `WARN_PCT` of the budget) and, once usage reaches the full
+    /// budget, an exceeded event every time it's called until the caller acts on it.
+    pub fn poll(&mut self) -> Option<CpuBudgetEvent> {
+        let usage_us = self.usage_us()?;
+        if usage_us >= self.budget_us {
+            return Some(CpuBudgetEvent::Exceeded { usage_ms: usage_us / 1_000, budget_ms: self.budget_ms });
+        }
+        if !self.warned && usage_us >= self.warn_us {
+            self.warned = true;
+            return Some(CpuBudgetEvent::Warning { usage_ms: usage_us / 1_000, budget_ms: self.budget_ms });
+        }
+        None
+    }
+}
+
+impl Drop for CpuBudget {
+    fn drop(&mut self) {
+        // The cgroup can only be removed once it has no member tasks left, which is
+        // true by the time this drops: it's held for the lifetime of `relay_loop`,
+        // which has already reaped the child.
+        let _ = fs::remove_dir(&self.cgroup_path);
+    }
+}
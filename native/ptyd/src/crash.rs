@@ -0,0 +1,120 @@
+//! When the child dies from SIGSEGV/SIGABRT, a `"crash"` event carries enough context
+//! for the harness to show more than "exit 139": the signal, whether the kernel wrote a
+//! core (and where), and the output trailing right up to the crash.
+//!
+//! Reaping via `waitid` rather than a plain `wait(2)` status is what makes the
+//! core-dumped flag reliable — its `siginfo_t` reports `CLD_DUMPED` as a distinct
+//! `si_code` from `CLD_KILLED`, rather than requiring a `WCOREDUMP` bit tucked into an
+//! `int` status the way `pty::child_exit_code` works with elsewhere in this crate.
+
+use std::path::PathBuf;
+
+use libc::{c_int, pid_t};
+
+/// How much trailing output a `"crash"` event carries, independent of whatever
+/// `--scrollback-file`/`--tee-file` retention (if any) the session was started with.
+pub const RECENT_OUTPUT_CAP: usize = 4096;
+
+pub struct CrashInfo {
+    pub signal: i32,
+    pub core_dumped: bool,
+    pub core_path: Option<String>,
+}
+
+/// The exited/signaled/core-dumped shape an `OUTPUT_OPCODE_EXIT_STATUS` frame reports
+/// (see `protocol::frame_output_exit_status`), for every exit this crate sees — not
+/// just the crash-signal subset `CrashInfo` covers.
+pub struct ExitStatus {
+    pub exited: bool,
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+}
+
+/// Recovers an approximate `ExitStatus` from just the collapsed `128 + signum`/exit-code
+/// convention `pty::child_exit_code` and this module both use, for the one reap path
+/// that never sees a `siginfo_t` at all: under `--audit-syscalls`, the supervisor thread
+/// in `audit.rs` owns `waitpid` and reports only the final code over a pipe (see
+/// `audit::SyscallAuditor::read_exit_code`). `core_dumped` can't be recovered from a
+/// bare code, so it's always reported `false` here rather than guessed.
+pub fn exit_status_from_code(code: i32) -> ExitStatus {
+    if code >= 128 {
+        ExitStatus { exited: false, signal: Some(code - 128), core_dumped: false }
+    } else {
+        ExitStatus { exited: true, signal: None, core_dumped: false }
+    }
+}
+
+pub fn is_crash_signal(signal: i32) -> bool {
+    matches!(signal, libc::SIGSEGV | libc::SIGABRT)
+}
+
+fn from_siginfo(info: &libc::siginfo_t) -> (i32, ExitStatus, Option<CrashInfo>) {
+    let signal = unsafe { info.si_status() };
+    match info.si_code {
+        libc::CLD_KILLED | libc::CLD_DUMPED => {
+            let core_dumped = info.si_code == libc::CLD_DUMPED;
+            let status = ExitStatus { exited: false, signal: Some(signal), core_dumped };
+            let crashed = is_crash_signal(signal).then(|| {
+                let core_path = if core_dumped { locate_core(unsafe { info.si_pid() }) } else { None };
+                CrashInfo { signal, core_dumped, core_path }
+            });
+            (128 + signal, status, crashed)
+        }
+        _ => {
+            let status = ExitStatus { exited: true, signal: None, core_dumped: false };
+            (signal, status, None)
+        }
+    }
+}
+
+/// Blocking-reaps `pid`, returning the same `128 + signum`/exit-status convention
+/// `pty::child_exit_code` uses, the exited/signaled/core-dumped shape an exit-status
+/// frame reports, and `CrashInfo` when it went down on a signal that event covers.
+/// Falls back to an all-exited-zero `ExitStatus` and `(1, None)` if `waitid` itself
+/// fails.
+pub fn reap(pid: pid_t) -> (i32, ExitStatus, Option<CrashInfo>) {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED) } != 0 {
+        return (1, ExitStatus { exited: true, signal: None, core_dumped: false }, None);
+    }
+    from_siginfo(&info)
+}
+
+/// Non-blocking counterpart for the `WNOHANG`-polling fallback used on kernels without
+/// a pidfd. `waitid(2)` with `WNOHANG` and nothing yet to report leaves `si_pid` unset
+/// (always 0, since a real child pid is never 0), which is how callers are expected to
+/// tell "nothing reaped" apart from a real status.
+pub fn try_reap(pid: pid_t) -> Option<(i32, ExitStatus, Option<CrashInfo>)> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags: c_int = libc::WEXITED | libc::WNOHANG;
+    if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) } != 0 {
+        return None;
+    }
+    if unsafe { info.si_pid() } == 0 {
+        return None;
+    }
+    Some(from_siginfo(&info))
+}
+
+/// Best-effort guess at where the kernel wrote a core file, from
+/// `/proc/sys/kernel/core_pattern`. Returns `None` for anything this can't resolve
+/// without reimplementing the kernel's own pattern expansion: a pattern piped to a
+/// collector (starts with `|`, e.g. `apport`/`systemd-coredump`) or one using a
+/// specifier this only partially expands.
+fn locate_core(pid: pid_t) -> Option<String> {
+    let pattern = std::fs::read_to_string("/proc/sys/kernel/core_pattern").ok()?;
+    let pattern = pattern.trim();
+    if pattern.is_empty() || pattern.starts_with('|') {
+        return None;
+    }
+    let expanded = pattern.replace("%p", &pid.to_string()).replace("%e", "*");
+    if expanded.contains('%') {
+        return None;
+    }
+    let path = if expanded.starts_with('/') {
+        PathBuf::from(expanded)
+    } else {
+        std::env::current_dir().ok()?.join(expanded)
+    };
+    path.exists().then(|| path.to_string_lossy().into_owned())
+}
@@ -0,0 +1,37 @@
+//! Minimal CRC-32 (the IEEE 802.3 / zlib polynomial, same one `gzip`/`zip` use), used to
+//! detect corruption on `OPCODE_DATA_CRC32`-framed input when a client tunnels ptyd
+//! traffic over something lossy (see `protocol.rs`). One function is small enough, and
+//! called rarely enough relative to everything else in the relay loop, that pulling in
+//! an external crate for it didn't seem worth it.
+
+use std::sync::OnceLock;
+
+const POLY: u32 = 0xEDB8_8320;
+
+fn table() -> &'static [u32; 256] {
+    static TABLE: OnceLock<[u32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            }
+            *entry = crc;
+        }
+        table
+    })
+}
+
+/// Computes the CRC-32 of `bytes`, matching `zlib`'s `crc32()` and what any off-the-shelf
+/// client library calls `crc32`/`CRC32` — so a client doesn't need a ptyd-specific
+/// checksum implementation to use `OPCODE_DATA_CRC32`.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let table = table();
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        let idx = ((crc ^ byte as u32) & 0xFF) as usize;
+        crc = table[idx] ^ (crc >> 8);
+    }
+    !crc
+}
@@ -0,0 +1,92 @@
+//! `--daemon`/`--pidfile PATH` for `ptyd serve`: classic double-fork daemonization plus
+//! a locked pidfile, so init tooling that expects a pidfile (and a process genuinely
+//! detached from the launching terminal, not just backgrounded with `&`) can manage
+//! `ptyd serve` the way it manages any other long-running service.
+
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::OnceLock;
+
+static PIDFILE_PATH: OnceLock<CString> = OnceLock::new();
+
+/// Double-forks and detaches from the controlling terminal: the first fork's parent
+/// exits immediately (so the shell that launched `ptyd` doesn't wait on it), the
+/// intermediate process calls `setsid` to start a new session with no controlling
+/// terminal, and the second fork's parent exits too, leaving only a session-leaderless
+/// grandchild as the daemon — the standard shape that stops a `SIGHUP` to the
+/// terminal's session from ever reaching it. `stdin`/`stdout`/`stderr` are redirected
+/// to `/dev/null` since nothing is left to read or display them.
+pub fn daemonize() -> Result<(), String> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } < 0 {
+        return Err("setsid failed".to_string());
+    }
+    fork_and_exit_parent()?;
+    redirect_stdio_to_dev_null()
+}
+
+fn fork_and_exit_parent() -> Result<(), String> {
+    match unsafe { libc::fork() } {
+        -1 => Err("fork failed".to_string()),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
+
+fn redirect_stdio_to_dev_null() -> Result<(), String> {
+    let dev_null =
+        OpenOptions::new().read(true).write(true).open("/dev/null").map_err(|err| format!("/dev/null: {err}"))?;
+    let fd = dev_null.as_raw_fd();
+    for target in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(fd, target) } < 0 {
+            return Err("dup2 onto stdio failed".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Writes the current (post-daemonize, if applicable) pid to `path`, holding an
+/// exclusive, non-blocking `flock` on the file for as long as the process runs — a
+/// second `ptyd serve --pidfile` pointed at the same path fails fast instead of
+/// silently overwriting a still-running daemon's pidfile. The open file is
+/// deliberately leaked so the lock lasts the process's whole lifetime; a `SIGTERM`/
+/// `SIGINT` handler installed here is what removes the file on the way out, since
+/// nothing else in this daemon's normal run ever returns from `serve::run`.
+pub fn write_pidfile(path: &str) -> Result<(), String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|err| format!("failed to open pidfile {path}: {err}"))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(format!("pidfile {path} is locked by another instance"));
+    }
+    file.set_len(0).map_err(|err| format!("failed to truncate pidfile {path}: {err}"))?;
+    let mut file = file;
+    writeln!(file, "{}", std::process::id()).map_err(|err| format!("failed to write pidfile {path}: {err}"))?;
+
+    let cstr = CString::new(path).map_err(|_| "pidfile path contains a NUL byte".to_string())?;
+    PIDFILE_PATH.set(cstr).ok();
+    install_cleanup_handler();
+    std::mem::forget(file);
+    Ok(())
+}
+
+fn install_cleanup_handler() {
+    unsafe {
+        libc::signal(libc::SIGTERM, cleanup_and_exit as *const () as usize);
+        libc::signal(libc::SIGINT, cleanup_and_exit as *const () as usize);
+    }
+}
+
+/// Signal handler, so only async-signal-safe calls here: `unlink` and `_exit`, nothing
+/// that allocates or locks.
+extern "C" fn cleanup_and_exit(_signum: i32) {
+    if let Some(path) = PIDFILE_PATH.get() {
+        unsafe { libc::unlink(path.as_ptr()) };
+    }
+    unsafe { libc::_exit(0) };
+}
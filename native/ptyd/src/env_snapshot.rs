@@ -0,0 +1,51 @@
+//! Captures a process's environment from `/proc/<pid>/environ` so callers can diff
+//! it across a command boundary and report `export`/`cd`-style state changes.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+pub type EnvMap = BTreeMap<String, String>;
+
+pub fn read_environ(pid: libc::pid_t) -> Option<EnvMap> {
+    let raw = fs::read(format!("/proc/{pid}/environ")).ok()?;
+    let mut map = EnvMap::new();
+    for entry in raw.split(|&b| b == 0) {
+        if entry.is_empty() {
+            continue;
+        }
+        let text = String::from_utf8_lossy(entry);
+        if let Some((key, value)) = text.split_once('=') {
+            map.insert(key.to_string(), value.to_string());
+        }
+    }
+    Some(map)
+}
+
+pub struct EnvDiff {
+    pub added: Vec<(String, String)>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, String, String)>,
+}
+
+pub fn diff(before: &EnvMap, after: &EnvMap) -> EnvDiff {
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+
+    for (key, after_value) in after {
+        match before.get(key) {
+            None => added.push((key.clone(), after_value.clone())),
+            Some(before_value) if before_value != after_value => {
+                changed.push((key.clone(), before_value.clone(), after_value.clone()))
+            }
+            _ => {}
+        }
+    }
+    for key in before.keys() {
+        if !after.contains_key(key) {
+            removed.push(key.clone());
+        }
+    }
+
+    EnvDiff { added, removed, changed }
+}
@@ -0,0 +1,256 @@
+//! Optional descendant exec tracing via the kernel's process-events connector
+//! (`CN_IDX_PROC` over `NETLINK_CONNECTOR`), enabled with `--trace-exec`. The
+//! connector is a single system-wide multicast feed of every process's fork/exec/exit;
+//! ptyd subscribes to it and keeps only `PROC_EVENT_EXEC` notifications whose pid is a
+//! descendant of the session's child, walking `/proc/<pid>/stat`'s ppid chain to
+//! decide. `libc` doesn't expose the netlink/connector wire structs for generic Linux
+//! (only for Android), so the handful this needs are defined here instead of pulling
+//! in a netlink crate for what's otherwise a handful of fixed-layout structs. Opening
+//! the connector needs `CAP_NET_ADMIN` (or root); a session without it just runs with
+//! tracing silently unavailable rather than failing to start.
+
+use libc::{c_int, pid_t};
+
+const NETLINK_CONNECTOR: c_int = 11;
+const CN_IDX_PROC: u32 = 0x1;
+const CN_VAL_PROC: u32 = 0x1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_EVENT_EXEC: u32 = 0x0000_0002;
+
+#[repr(C)]
+#[derive(Default)]
+struct SockaddrNl {
+    nl_family: u16,
+    nl_pad: u16,
+    nl_pid: u32,
+    nl_groups: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct NlMsgHdr {
+    nlmsg_len: u32,
+    nlmsg_type: u16,
+    nlmsg_flags: u16,
+    nlmsg_seq: u32,
+    nlmsg_pid: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CnMsg {
+    id_idx: u32,
+    id_val: u32,
+    seq: u32,
+    ack: u32,
+    len: u16,
+    flags: u16,
+}
+
+const NLMSG_HDRLEN: usize = std::mem::size_of::<NlMsgHdr>();
+const CN_MSG_LEN: usize = std::mem::size_of::<CnMsg>();
+const NLMSG_DONE: u16 = 0x3;
+const NLM_F_ACK: u16 = 0x4;
+
+/// One descendant exec, as reported by the connector.
+pub struct ExecEvent {
+    pub pid: pid_t,
+    pub parent_pid: pid_t,
+    pub argv: Vec<String>,
+}
+
+/// An open connector subscription, to be polled alongside the session's other fds.
+pub struct ExecTracer {
+    fd: c_int,
+    root_pid: pid_t,
+}
+
+impl ExecTracer {
+    /// Opens the connector and subscribes to its proc-event multicast group. Returns
+    /// `None` on any failure (missing privilege, kernel without `CONFIG_PROC_EVENTS`,
+    /// ...) so the caller can just skip tracing rather than fail the session.
+    pub fn open(root_pid: pid_t) -> Option<Self> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM, NETLINK_CONNECTOR) };
+        if fd < 0 {
+            return None;
+        }
+
+        let addr = SockaddrNl {
+            nl_family: libc::AF_NETLINK as u16,
+            nl_pad: 0,
+            nl_pid: std::process::id(),
+            nl_groups: CN_IDX_PROC,
+        };
+        let rc = unsafe {
+            libc::bind(
+                fd,
+                std::ptr::addr_of!(addr).cast(),
+                std::mem::size_of::<SockaddrNl>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            unsafe { libc::close(fd) };
+            return None;
+        }
+
+        if send_listen_request(fd).is_err() {
+            unsafe { libc::close(fd) };
+            return None;
+        }
+
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags >= 0 {
+            unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+        }
+
+        Some(ExecTracer { fd, root_pid })
+    }
+
+    pub fn fd(&self) -> c_int {
+        self.fd
+    }
+
+    /// Drains whatever connector messages are currently available (the fd is expected
+    /// to already be non-blocking) and returns the `PROC_EVENT_EXEC`s among them whose
+    /// pid descends from `root_pid`.
+    pub fn poll_events(&self) -> Vec<ExecEvent> {
+        let mut events = Vec::new();
+        let mut buf = [0_u8; 4096];
+        loop {
+            let n = unsafe { libc::recv(self.fd, buf.as_mut_ptr().cast(), buf.len(), 0) };
+            if n <= 0 {
+                break;
+            }
+            parse_messages(&buf[..n as usize], self.root_pid, &mut events);
+        }
+        events
+    }
+}
+
+impl Drop for ExecTracer {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn send_listen_request(fd: c_int) -> Result<(), ()> {
+    let payload = PROC_CN_MCAST_LISTEN.to_ne_bytes();
+    let cn_msg = CnMsg {
+        id_idx: CN_IDX_PROC,
+        id_val: CN_VAL_PROC,
+        seq: 0,
+        ack: 0,
+        len: payload.len() as u16,
+        flags: 0,
+    };
+    let total_len = NLMSG_HDRLEN + CN_MSG_LEN + payload.len();
+    let header = NlMsgHdr {
+        nlmsg_len: total_len as u32,
+        nlmsg_type: NLMSG_DONE,
+        nlmsg_flags: NLM_F_ACK,
+        nlmsg_seq: 0,
+        nlmsg_pid: std::process::id(),
+    };
+
+    let mut msg = Vec::with_capacity(total_len);
+    msg.extend_from_slice(struct_bytes(&header));
+    msg.extend_from_slice(struct_bytes(&cn_msg));
+    msg.extend_from_slice(&payload);
+
+    let sent = unsafe { libc::send(fd, msg.as_ptr().cast(), msg.len(), 0) };
+    if sent as usize != msg.len() {
+        return Err(());
+    }
+    Ok(())
+}
+
+fn struct_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts((value as *const T).cast(), std::mem::size_of::<T>()) }
+}
+
+/// Walks one or more `nlmsghdr`-framed messages out of a single recv'd datagram,
+/// extracting `PROC_EVENT_EXEC` notifications for descendants of `root_pid`.
+fn parse_messages(data: &[u8], root_pid: pid_t, events: &mut Vec<ExecEvent>) {
+    let mut offset = 0;
+    while offset + NLMSG_HDRLEN <= data.len() {
+        let nlmsg_len = u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        if nlmsg_len < NLMSG_HDRLEN || offset + nlmsg_len > data.len() {
+            break;
+        }
+
+        let body_start = offset + NLMSG_HDRLEN;
+        if body_start + CN_MSG_LEN <= data.len() {
+            let cn_len_offset = body_start + 16; // id_idx, id_val, seq, ack = 4 u32s
+            if cn_len_offset + 2 <= data.len() {
+                let cn_payload_len = u16::from_ne_bytes(data[cn_len_offset..cn_len_offset + 2].try_into().unwrap()) as usize;
+                let payload_start = body_start + CN_MSG_LEN;
+                let payload_end = payload_start + cn_payload_len;
+                if payload_end <= data.len() {
+                    parse_proc_event(&data[payload_start..payload_end], root_pid, events);
+                }
+            }
+        }
+
+        offset += nlmsg_len;
+    }
+}
+
+/// A `proc_event`: `{ what: u32, cpu: u32, timestamp_ns: u64 }` header followed by a
+/// `what`-tagged union; only the exec variant (`{ pid: u32, tgid: u32 }`) is read.
+fn parse_proc_event(payload: &[u8], root_pid: pid_t, events: &mut Vec<ExecEvent>) {
+    const HEADER_LEN: usize = 16;
+    if payload.len() < HEADER_LEN + 4 {
+        return;
+    }
+    let what = u32::from_ne_bytes(payload[0..4].try_into().unwrap());
+    if what != PROC_EVENT_EXEC {
+        return;
+    }
+    let pid = u32::from_ne_bytes(payload[HEADER_LEN..HEADER_LEN + 4].try_into().unwrap()) as pid_t;
+    if !is_descendant(pid, root_pid) {
+        return;
+    }
+    events.push(ExecEvent {
+        pid,
+        parent_pid: read_ppid(pid).unwrap_or(0),
+        argv: read_argv(pid),
+    });
+}
+
+/// Walks `/proc/<pid>/stat`'s ppid chain up to `root_pid`, giving up after a bounded
+/// number of hops so a `/proc` race (pid reused mid-walk) can't spin forever.
+fn is_descendant(pid: pid_t, root_pid: pid_t) -> bool {
+    let mut current = pid;
+    for _ in 0..64 {
+        if current == root_pid {
+            return true;
+        }
+        if current <= 1 {
+            return false;
+        }
+        match read_ppid(current) {
+            Some(ppid) => current = ppid,
+            None => return false,
+        }
+    }
+    false
+}
+
+fn read_ppid(pid: pid_t) -> Option<pid_t> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // Field 2 (comm) is parenthesized and may itself contain spaces/parens, so resume
+    // splitting after its closing paren rather than just splitting on whitespace.
+    let after_comm = stat.rfind(')')? + 1;
+    stat[after_comm..].split_whitespace().nth(1)?.parse().ok()
+}
+
+fn read_argv(pid: pid_t) -> Vec<String> {
+    let raw = match std::fs::read(format!("/proc/{pid}/cmdline")) {
+        Ok(bytes) => bytes,
+        Err(_) => return Vec::new(),
+    };
+    raw.split(|&b| b == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).into_owned())
+        .collect()
+}
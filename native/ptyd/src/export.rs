@@ -0,0 +1,79 @@
+//! `ptyd export <path> --format <plain|ansi|html|svg> [--output PATH]`: renders a raw
+//! byte dump — the kind `--tee-file`/`--scrollback-file` already produce (see
+//! `tee.rs`/`scrollback.rs`) — into one of `capture.rs`'s export formats. ptyd has no
+//! timestamped recording format of its own (no "`.cast`" file, no per-frame VT model);
+//! whatever bytes are at `<path>` are treated as one continuous stream, same as a live
+//! capture of retained scrollback.
+
+use crate::capture;
+
+struct ExportArgs {
+    input_path: String,
+    format: String,
+    output_path: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Option<ExportArgs> {
+    let input_path = args.first()?.clone();
+    let mut format = "plain".to_string();
+    let mut output_path = None;
+    let mut idx = 1;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--format" => {
+                format = args.get(idx + 1)?.clone();
+                idx += 2;
+            }
+            "--output" => {
+                output_path = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            _ => return None,
+        }
+    }
+    Some(ExportArgs {
+        input_path,
+        format,
+        output_path,
+    })
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let Some(export_args) = parse_args(args) else {
+        eprintln!("usage: ptyd export <path> [--format plain|ansi|html|svg] [--output <path>]");
+        return 2;
+    };
+
+    let bytes = match std::fs::read(&export_args.input_path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("ptyd export: {}: {err}", export_args.input_path);
+            return 1;
+        }
+    };
+
+    let rendered = match export_args.format.as_str() {
+        "plain" => capture::plain_text(&bytes),
+        "ansi" => String::from_utf8_lossy(&bytes).into_owned(),
+        "html" => capture::html(&bytes),
+        "svg" => capture::svg(&bytes),
+        other => {
+            eprintln!("ptyd export: unknown format {other:?} (expected plain, ansi, html, or svg)");
+            return 2;
+        }
+    };
+
+    match export_args.output_path {
+        Some(path) => match std::fs::write(&path, rendered) {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("ptyd export: {path}: {err}");
+                1
+            }
+        },
+        None => {
+            print!("{rendered}");
+            0
+        }
+    }
+}
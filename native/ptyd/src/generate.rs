@@ -0,0 +1,131 @@
+//! `ptyd generate --rate 50MB/s --pattern ansi`: a fake session that produces synthetic
+//! output through the same bounded `OutputBuffer`/backpressure path `relay_loop` uses for
+//! real pty output, instead of a forked child, so client developers can load-test their
+//! frontends against realistic volume and content without needing a real workload that
+//! happens to produce it.
+
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock};
+use crate::outbuf::OutputBuffer;
+
+/// How often a new chunk is generated and offered to the buffer; short enough that
+/// `--rate` feels responsive, long enough not to burn CPU on an otherwise-idle target.
+const TICK_MS: i64 = 50;
+
+struct GenerateArgs {
+    rate_bytes_per_sec: u64,
+    pattern: String,
+    duration_ms: Option<u64>,
+}
+
+fn parse_rate(s: &str) -> Option<u64> {
+    let s = s.strip_suffix("/s").unwrap_or(s);
+    let (number, multiplier) = if let Some(n) = s.strip_suffix("GB") {
+        (n, 1_000_000_000)
+    } else if let Some(n) = s.strip_suffix("MB") {
+        (n, 1_000_000)
+    } else if let Some(n) = s.strip_suffix("KB") {
+        (n, 1_000)
+    } else if let Some(n) = s.strip_suffix('B') {
+        (n, 1)
+    } else {
+        (s, 1)
+    };
+    let value: u64 = number.parse().ok()?;
+    Some(value * multiplier)
+}
+
+fn parse_args(args: &[String]) -> Option<GenerateArgs> {
+    let mut rate_bytes_per_sec = None;
+    let mut pattern = "plain".to_string();
+    let mut duration_ms = None;
+    let mut idx = 0;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--rate" => {
+                rate_bytes_per_sec = Some(parse_rate(args.get(idx + 1)?)?);
+                idx += 2;
+            }
+            "--pattern" => {
+                pattern = args.get(idx + 1)?.clone();
+                idx += 2;
+            }
+            "--duration-ms" => {
+                duration_ms = Some(args.get(idx + 1)?.parse().ok()?);
+                idx += 2;
+            }
+            _ => return None,
+        }
+    }
+    Some(GenerateArgs {
+        rate_bytes_per_sec: rate_bytes_per_sec?,
+        pattern,
+        duration_ms,
+    })
+}
+
+/// Fills `buf` with at least `len` bytes of `seq`'s worth of synthetic output, cycling
+/// `seq` forward as needed so a long run doesn't just repeat the same line forever.
+fn fill_pattern(pattern: &str, seq: &mut u64, len: usize, buf: &mut Vec<u8>) {
+    buf.clear();
+    while buf.len() < len {
+        match pattern {
+            "ansi" => {
+                let color = 31 + (*seq % 7) as u32;
+                buf.extend_from_slice(format!("\x1b[{color}mline {seq}\x1b[0m\r\n").as_bytes());
+            }
+            "progress" => {
+                let pct = (*seq % 101) as usize;
+                let filled = "#".repeat(pct / 2);
+                let empty = "-".repeat(50 - pct / 2);
+                buf.extend_from_slice(format!("\r[{filled}{empty}] {pct}%").as_bytes());
+            }
+            _ => {
+                buf.extend_from_slice(format!("line {seq}\n").as_bytes());
+            }
+        }
+        *seq += 1;
+    }
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let Some(generate_args) = parse_args(args) else {
+        eprintln!("usage: ptyd generate --rate <N[KB|MB|GB]/s> [--pattern plain|ansi|progress] [--duration-ms <ms>]");
+        return 2;
+    };
+
+    let clock = SystemClock;
+    let mut output_buffer = OutputBuffer::new(OutputBuffer::DEFAULT_HIGH_WATERMARK, OutputBuffer::DEFAULT_LOW_WATERMARK);
+    let bytes_per_tick = (generate_args.rate_bytes_per_sec as u128 * TICK_MS as u128 / 1000) as usize;
+    let mut chunk = Vec::with_capacity(bytes_per_tick + 256);
+    let mut seq: u64 = 0;
+    let started_at_ms = clock.monotonic_ms();
+
+    loop {
+        if let Some(duration_ms) = generate_args.duration_ms {
+            if (clock.monotonic_ms() - started_at_ms) as u64 >= duration_ms {
+                break;
+            }
+        }
+
+        // Don't keep generating into a buffer the reader isn't draining; a slow
+        // consumer should see backpressure here the same way a real child's output
+        // does in `relay_loop`, not an ever-growing queue of synthetic traffic.
+        if output_buffer.depth() < OutputBuffer::DEFAULT_HIGH_WATERMARK {
+            fill_pattern(&generate_args.pattern, &mut seq, bytes_per_tick.max(1), &mut chunk);
+            output_buffer.push(&chunk);
+        }
+
+        if output_buffer.flush_nonblocking(libc::STDOUT_FILENO).0.is_err() {
+            return 1;
+        }
+
+        std::thread::sleep(Duration::from_millis(TICK_MS as u64));
+    }
+
+    if output_buffer.flush_blocking(libc::STDOUT_FILENO).is_err() {
+        return 1;
+    }
+    0
+}
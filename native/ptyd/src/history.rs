@@ -0,0 +1,207 @@
+//! Persistent session history: one row per completed `ptyd` session (command, start/end
+//! times, exit status, resource usage, recording path), in a small sqlite database
+//! shared across processes. Since every session is its own process (see the module doc
+//! on `main.rs`), this is how "recent runs" survives past any single process exiting —
+//! `ptyd history` opens the same file and reads it back.
+
+use rusqlite::{params, Connection};
+
+pub struct SessionRecord {
+    pub session_id: String,
+    pub session_name: Option<String>,
+    pub command: String,
+    pub started_at_unix_ms: i64,
+    pub ended_at_unix_ms: i64,
+    pub exit_code: i32,
+    pub cpu_user_ms: i64,
+    pub cpu_sys_ms: i64,
+    pub peak_rss_kb: i64,
+    pub recording_path: Option<String>,
+}
+
+fn db_path() -> Option<std::path::PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(std::path::PathBuf::from(home).join(".local/share/ptyd/history.db"))
+}
+
+/// Opens (creating if needed) the sessions table on whatever connection `conn` points
+/// at. Split out from `open` so tests can point it at an in-memory or temp-file
+/// connection instead of the real `~/.local/share/ptyd/history.db`.
+fn init_schema(conn: &Connection) -> Option<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            session_id TEXT PRIMARY KEY,
+            session_name TEXT,
+            command TEXT NOT NULL,
+            started_at_unix_ms INTEGER NOT NULL,
+            ended_at_unix_ms INTEGER NOT NULL,
+            exit_code INTEGER NOT NULL,
+            cpu_user_ms INTEGER NOT NULL,
+            cpu_sys_ms INTEGER NOT NULL,
+            peak_rss_kb INTEGER NOT NULL,
+            recording_path TEXT
+        )",
+        [],
+    )
+    .ok()?;
+    Some(())
+}
+
+fn open() -> Option<Connection> {
+    let path = db_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).ok()?;
+    }
+    let conn = Connection::open(path).ok()?;
+    init_schema(&conn)?;
+    Some(conn)
+}
+
+/// Inserts (or replaces) one session row on `conn`, the part of `record` that doesn't
+/// depend on where the database file lives — factored out so it can be exercised
+/// against an in-memory connection in tests.
+fn insert(conn: &Connection, record: &SessionRecord) {
+    let _ = conn.execute(
+        "INSERT OR REPLACE INTO sessions (
+            session_id, session_name, command, started_at_unix_ms, ended_at_unix_ms,
+            exit_code, cpu_user_ms, cpu_sys_ms, peak_rss_kb, recording_path
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            record.session_id,
+            record.session_name,
+            record.command,
+            record.started_at_unix_ms,
+            record.ended_at_unix_ms,
+            record.exit_code,
+            record.cpu_user_ms,
+            record.cpu_sys_ms,
+            record.peak_rss_kb,
+            record.recording_path,
+        ],
+    );
+}
+
+/// Records one completed session. Best-effort: a missing `HOME`, an unwritable data
+/// directory, or a locked database drops the row rather than failing the session whose
+/// work is otherwise already done.
+pub fn record(record: &SessionRecord) {
+    let Some(conn) = open() else { return };
+    insert(&conn, record);
+}
+
+/// Reads back the `limit` most recent sessions, most recent first. Factored out of
+/// `print_recent` so the query itself — not the `println!` formatting around it — can
+/// be tested against an in-memory connection.
+fn fetch_recent(conn: &Connection, limit: i64) -> Vec<SessionRecord> {
+    let Ok(mut stmt) = conn.prepare(
+        "SELECT session_id, session_name, command, started_at_unix_ms, ended_at_unix_ms,
+                exit_code, cpu_user_ms, cpu_sys_ms, peak_rss_kb, recording_path
+         FROM sessions ORDER BY started_at_unix_ms DESC LIMIT ?1",
+    ) else {
+        return Vec::new();
+    };
+    let rows = stmt.query_map(params![limit], |row| {
+        Ok(SessionRecord {
+            session_id: row.get(0)?,
+            session_name: row.get(1)?,
+            command: row.get(2)?,
+            started_at_unix_ms: row.get(3)?,
+            ended_at_unix_ms: row.get(4)?,
+            exit_code: row.get(5)?,
+            cpu_user_ms: row.get(6)?,
+            cpu_sys_ms: row.get(7)?,
+            peak_rss_kb: row.get(8)?,
+            recording_path: row.get(9)?,
+        })
+    });
+    let Ok(rows) = rows else { return Vec::new() };
+    rows.flatten().collect()
+}
+
+/// Prints the `limit` most recent sessions as newline-delimited JSON, most recent
+/// first, for `ptyd history`.
+pub fn print_recent(limit: i64) {
+    let Some(conn) = open() else { return };
+    for row in fetch_recent(&conn, limit) {
+        let name_field = match &row.session_name {
+            Some(name) => crate::lifecycle::json_escape(name),
+            None => "null".to_string(),
+        };
+        let recording_field = match &row.recording_path {
+            Some(path) => crate::lifecycle::json_escape(path),
+            None => "null".to_string(),
+        };
+        println!(
+            "{{\"session_id\":\"{}\",\"session_name\":{name_field},\"command\":{},\"started_at_unix_ms\":{},\"ended_at_unix_ms\":{},\"exit_code\":{},\"cpu_user_ms\":{},\"cpu_sys_ms\":{},\"peak_rss_kb\":{},\"recording_path\":{recording_field}}}",
+            row.session_id,
+            crate::lifecycle::json_escape(&row.command),
+            row.started_at_unix_ms,
+            row.ended_at_unix_ms,
+            row.exit_code,
+            row.cpu_user_ms,
+            row.cpu_sys_ms,
+            row.peak_rss_kb,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn
+    }
+
+    fn sample(session_id: &str, started_at_unix_ms: i64) -> SessionRecord {
+        SessionRecord {
+            session_id: session_id.to_string(),
+            session_name: Some("build".to_string()),
+            command: "cargo build".to_string(),
+            started_at_unix_ms,
+            ended_at_unix_ms: started_at_unix_ms + 1_000,
+            exit_code: 0,
+            cpu_user_ms: 10,
+            cpu_sys_ms: 5,
+            peak_rss_kb: 2_048,
+            recording_path: None,
+        }
+    }
+
+    #[test]
+    fn fetch_recent_orders_newest_first() {
+        let conn = memory_conn();
+        insert(&conn, &sample("a", 1_000));
+        insert(&conn, &sample("b", 3_000));
+        insert(&conn, &sample("c", 2_000));
+
+        let rows = fetch_recent(&conn, 10);
+        let ids: Vec<&str> = rows.iter().map(|r| r.session_id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "c", "a"]);
+    }
+
+    #[test]
+    fn fetch_recent_respects_the_limit() {
+        let conn = memory_conn();
+        insert(&conn, &sample("a", 1_000));
+        insert(&conn, &sample("b", 2_000));
+        insert(&conn, &sample("c", 3_000));
+
+        assert_eq!(fetch_recent(&conn, 2).len(), 2);
+    }
+
+    #[test]
+    fn insert_replaces_an_existing_row_with_the_same_session_id() {
+        let conn = memory_conn();
+        insert(&conn, &sample("a", 1_000));
+        let mut updated = sample("a", 1_000);
+        updated.exit_code = 7;
+        insert(&conn, &updated);
+
+        let rows = fetch_recent(&conn, 10);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].exit_code, 7);
+    }
+}
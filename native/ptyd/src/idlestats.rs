@@ -0,0 +1,55 @@
+//! `--report-idle-stats` self-measurement: counts relay-loop wakeups and this
+//! process's own CPU time for the life of the session, and reports them on exit.
+//! Meant to be run against an idle session (nothing typed, no child output) to catch
+//! regressions that reintroduce periodic polling instead of blocking in `poll`
+//! indefinitely — a regression shows up as elevated wakeups/CPU for the same idle
+//! wall-clock window.
+
+use crate::clock::SharedClock;
+use crate::protocol::write_all_fd;
+use crate::rusage::{usage_delta, ResourceUsage};
+
+pub struct IdleStats {
+    session_fields_json: String,
+    enabled: bool,
+    wakeups: u64,
+    started_at_ms: i64,
+    usage_at_start: ResourceUsage,
+    clock: SharedClock,
+}
+
+impl IdleStats {
+    pub fn new(session_fields_json: String, enabled: bool, clock: SharedClock) -> Self {
+        IdleStats {
+            session_fields_json,
+            enabled,
+            wakeups: 0,
+            started_at_ms: clock.monotonic_ms(),
+            usage_at_start: ResourceUsage::self_now(),
+            clock,
+        }
+    }
+
+    /// Call once per `poll()` return (excluding the initial blocking call being
+    /// counted twice) to record a loop wakeup.
+    pub fn record_wakeup(&mut self) {
+        if self.enabled {
+            self.wakeups += 1;
+        }
+    }
+}
+
+impl Drop for IdleStats {
+    fn drop(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        let wall_ms = self.clock.monotonic_ms() - self.started_at_ms;
+        let delta = usage_delta(&self.usage_at_start, &ResourceUsage::self_now());
+        let line = format!(
+            "{{{},\"event\":\"idle-stats\",\"wakeups\":{},\"wall_ms\":{wall_ms},\"cpu_user_ms\":{},\"cpu_sys_ms\":{}}}\n",
+            self.session_fields_json, self.wakeups, delta.user_ms, delta.sys_ms
+        );
+        let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+    }
+}
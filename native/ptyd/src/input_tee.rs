@@ -0,0 +1,53 @@
+//! Optional recording of client-sent input bytes to a log file, separate from
+//! `--tee-file` (which mirrors child *output*). Each write is prefixed with a
+//! `[<unix_ms>]` timestamp header so "what exactly did the client send, and when" can
+//! be answered when debugging a misbehaving TUI, without wading through scrollback
+//! looking for the echo of what was typed. Toggleable at runtime via
+//! `OPCODE_TOGGLE_INPUT_TEE` so a host can open a capture window around just the
+//! exchange it's debugging rather than recording a whole session from the start.
+
+use libc::c_int;
+
+use crate::protocol::write_all_fd;
+
+pub struct InputTee {
+    fd: c_int,
+    enabled: bool,
+}
+
+impl InputTee {
+    pub fn open(path: &str) -> Option<Self> {
+        let cpath = std::ffi::CString::new(path).ok()?;
+        let fd = unsafe { libc::open(cpath.as_ptr(), libc::O_CREAT | libc::O_WRONLY | libc::O_APPEND, 0o600) };
+        if fd < 0 {
+            return None;
+        }
+        Some(InputTee { fd, enabled: true })
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Records `bytes` (the input actually relayed to the child, post-throttling and
+    /// post-transcoding) with a timestamp header, unless toggled off.
+    pub fn write(&self, bytes: &[u8]) -> Result<(), ()> {
+        if !self.enabled || bytes.is_empty() {
+            return Ok(());
+        }
+        let unix_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let header = format!("[{unix_ms}] ");
+        write_all_fd(self.fd, header.as_bytes())?;
+        write_all_fd(self.fd, bytes)?;
+        write_all_fd(self.fd, b"\n")
+    }
+}
+
+impl Drop for InputTee {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
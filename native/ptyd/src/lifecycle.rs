@@ -0,0 +1,183 @@
+//! Bundles everything ptyd tracks across a single command's lifetime (output region,
+//! environment diff, and now resource usage) and turns crossings into JSON event
+//! lines on stderr, keeping the relay loop in `main.rs` free of this bookkeeping.
+
+use std::collections::HashMap;
+
+use crate::clock::SharedClock;
+use crate::commands::{CommandBoundary, CommandTracker};
+use crate::env_snapshot::{diff, read_environ, EnvMap};
+use crate::protocol::write_all_fd;
+use crate::pty::proc_cwd;
+use crate::repl::ReplDetector;
+use crate::rusage::{rusage_children_delta, ResourceUsage};
+use crate::session::SessionContext;
+
+pub struct CommandLifecycle {
+    session: SessionContext,
+    commands: CommandTracker,
+    env_at_start: HashMap<usize, EnvMap>,
+    started_at: HashMap<usize, (i64, ResourceUsage)>,
+    repl: ReplDetector,
+    clock: SharedClock,
+}
+
+impl CommandLifecycle {
+    pub fn new(session: SessionContext, clock: SharedClock) -> Self {
+        CommandLifecycle {
+            session,
+            commands: CommandTracker::default(),
+            env_at_start: HashMap::new(),
+            started_at: HashMap::new(),
+            repl: ReplDetector::default(),
+            clock,
+        }
+    }
+
+    pub fn observe_output(&mut self, chunk: &[u8], child_pid: libc::pid_t, master_fd: libc::c_int) {
+        let (boundaries, cwd_changed) = self.commands.observe(chunk);
+        for boundary in boundaries {
+            self.emit_boundary(&boundary, child_pid, master_fd);
+        }
+        if let Some(cwd) = cwd_changed {
+            self.emit_cwd_changed(&cwd);
+        }
+    }
+
+    /// Reports an OSC 7 cwd report that changed the shell's known working directory,
+    /// so a client can track it live instead of scraping escape sequences itself or
+    /// waiting for the next `command-start`/`command-end`/`state` event that happens
+    /// to carry a `cwd` field.
+    fn emit_cwd_changed(&self, cwd: &str) {
+        let line = format!("{{{},\"event\":\"cwd-changed\",\"cwd\":{}}}\n", self.session.fields_json(), json_escape(cwd));
+        emit(&line);
+    }
+
+    pub fn tag_next_command(&mut self, correlation_id: u32) {
+        self.commands.tag_next_command(correlation_id);
+    }
+
+    pub fn query_command(&self, index: u32) {
+        let session = self.session.fields_json();
+        let body = match self.commands.command_output(index as usize) {
+            Some(bytes) => format!(
+                "{{{session},\"event\":\"command-output\",\"index\":{index},\"text\":{}}}\n",
+                json_escape(&String::from_utf8_lossy(bytes))
+            ),
+            None => format!("{{{session},\"event\":\"command-output\",\"index\":{index},\"text\":null}}\n"),
+        };
+        emit(&body);
+    }
+
+    pub fn query_state(&self, child_pid: libc::pid_t) {
+        let session = self.session.fields_json();
+        let cwd_field = match self.resolve_cwd(child_pid) {
+            Some(cwd) => json_escape(&cwd),
+            None => "null".to_string(),
+        };
+        emit(&format!("{{{session},\"event\":\"state\",\"cwd\":{cwd_field}}}\n"));
+    }
+
+    fn resolve_cwd(&self, child_pid: libc::pid_t) -> Option<String> {
+        self.commands.osc7_cwd().map(str::to_string).or_else(|| proc_cwd(child_pid))
+    }
+
+    fn emit_boundary(&mut self, boundary: &CommandBoundary, child_pid: libc::pid_t, master_fd: libc::c_int) {
+        let (name, index, correlation_id) = match boundary {
+            CommandBoundary::Start { index, correlation_id } => ("command-start", *index, *correlation_id),
+            CommandBoundary::End { index, correlation_id } => ("command-end", *index, *correlation_id),
+        };
+        let id_field = match correlation_id {
+            Some(id) => format!(",\"correlation_id\":{id}"),
+            None => String::new(),
+        };
+
+        let env_field = match boundary {
+            CommandBoundary::Start { index, .. } => {
+                if let Some(env) = read_environ(child_pid) {
+                    self.env_at_start.insert(*index, env);
+                }
+                String::new()
+            }
+            CommandBoundary::End { index, .. } => match (self.env_at_start.remove(index), read_environ(child_pid)) {
+                (Some(before), Some(after)) => format!(",\"env_diff\":{}", env_diff_json(&before, &after)),
+                _ => String::new(),
+            },
+        };
+
+        let cwd_field = match self.resolve_cwd(child_pid) {
+            Some(cwd) => format!(",\"cwd\":{}", json_escape(&cwd)),
+            None => String::new(),
+        };
+
+        let usage_field = match boundary {
+            CommandBoundary::Start { index, .. } => {
+                self.started_at.insert(*index, (self.clock.monotonic_ms(), ResourceUsage::children_now()));
+                String::new()
+            }
+            CommandBoundary::End { index, .. } => match self.started_at.remove(index) {
+                Some((started_at_ms, usage_at_start)) => {
+                    let wall_ms = self.clock.monotonic_ms() - started_at_ms;
+                    let delta = rusage_children_delta(&usage_at_start);
+                    format!(
+                        ",\"duration_ms\":{wall_ms},\"cpu_user_ms\":{},\"cpu_sys_ms\":{},\"peak_rss_kb\":{}",
+                        delta.user_ms, delta.sys_ms, delta.max_rss_kb
+                    )
+                }
+                None => String::new(),
+            },
+        };
+
+        let session = self.session.fields_json();
+        let line =
+            format!("{{{session},\"event\":\"{name}\",\"index\":{index}{id_field}{env_field}{cwd_field}{usage_field}}}\n");
+        emit(&line);
+
+        let repl_transition = match boundary {
+            CommandBoundary::Start { .. } => self.repl.on_command_start(master_fd).map(|repl| ("repl-enter", repl)),
+            CommandBoundary::End { .. } => self.repl.on_command_end(master_fd).map(|repl| ("repl-exit", repl)),
+        };
+        if let Some((event, repl)) = repl_transition {
+            emit(&format!("{{{session},\"event\":\"{event}\",\"repl\":{}}}\n", json_escape(&repl)));
+        }
+    }
+}
+
+fn emit(line: &str) {
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+fn env_diff_json(before: &EnvMap, after: &EnvMap) -> String {
+    let delta = diff(before, after);
+    let added = delta
+        .added
+        .iter()
+        .map(|(k, v)| format!("{}:{}", json_escape(k), json_escape(v)))
+        .collect::<Vec<_>>()
+        .join(",");
+    let removed = delta.removed.iter().map(|k| json_escape(k).to_string()).collect::<Vec<_>>().join(",");
+    let changed = delta
+        .changed
+        .iter()
+        .map(|(k, before, after)| format!("{}:[{},{}]", json_escape(k), json_escape(before), json_escape(after)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{\"added\":{{{added}}},\"removed\":[{removed}],\"changed\":{{{changed}}}}}")
+}
+
+pub fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
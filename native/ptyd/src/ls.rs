@@ -0,0 +1,169 @@
+//! `ptyd ls --socket PATH`: the first client of `serve.rs`'s control protocol from a
+//! second process. Connects, sends `OP_LIST`, and prints each session `serve` is
+//! hosting as one newline-delimited JSON object — the same shape `history.rs` already
+//! prints `ptyd history` rows in — so an operator can see what's running without
+//! attaching to anything.
+
+use std::os::unix::net::UnixStream;
+
+use crate::serve::{read_control_frame, write_frame, OP_AUTH, OP_ERROR, OP_LIST, OP_OK};
+
+struct SessionInfo {
+    id: String,
+    name: Option<String>,
+    argv: Vec<String>,
+    pid: i32,
+    cols: u16,
+    rows: u16,
+    attached: u32,
+    idle_ms: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+struct LsArgs {
+    socket_path: String,
+    token: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Option<LsArgs> {
+    let mut idx = 0;
+    let mut socket_path = None;
+    let mut token = None;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--socket" => {
+                socket_path = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--token" => {
+                token = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            _ => return None,
+        }
+    }
+    Some(LsArgs { socket_path: socket_path?, token })
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let Some(LsArgs { socket_path, token }) = parse_args(args) else {
+        eprintln!("usage: ptyd ls --socket <path> [--token <token>]");
+        return 2;
+    };
+
+    let mut stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("ptyd ls: failed to connect to {socket_path}: {err}");
+            return 1;
+        }
+    };
+
+    if let Some(token) = token {
+        write_frame(&mut stream, OP_AUTH, token.as_bytes());
+        match read_control_frame(&mut stream) {
+            Some((OP_OK, _)) => {}
+            Some((OP_ERROR, reason)) => {
+                eprintln!("ptyd ls: auth failed: {}", String::from_utf8_lossy(&reason));
+                return 1;
+            }
+            _ => {
+                eprintln!("ptyd ls: no response to auth");
+                return 1;
+            }
+        }
+    }
+
+    write_frame(&mut stream, OP_LIST, &[]);
+    let Some((op, payload)) = read_control_frame(&mut stream) else {
+        eprintln!("ptyd ls: no response from {socket_path}");
+        return 1;
+    };
+    if op == OP_ERROR {
+        eprintln!("ptyd ls: {}", String::from_utf8_lossy(&payload));
+        return 1;
+    }
+    let Some(sessions) = decode_list_response(&payload) else {
+        eprintln!("ptyd ls: malformed response from {socket_path}");
+        return 1;
+    };
+
+    for session in &sessions {
+        println!(
+            "{{\"session_id\":\"{}\",\"name\":{},\"command\":{},\"pid\":{},\"cols\":{},\"rows\":{},\"attached_clients\":{},\"idle_ms\":{},\"bytes_in\":{},\"bytes_out\":{}}}",
+            session.id,
+            match &session.name {
+                Some(name) => crate::lifecycle::json_escape(name),
+                None => "null".to_string(),
+            },
+            crate::lifecycle::json_escape(&session.argv.join(" ")),
+            session.pid,
+            session.cols,
+            session.rows,
+            session.attached,
+            session.idle_ms,
+            session.bytes_in,
+            session.bytes_out,
+        );
+    }
+    0
+}
+
+/// Decodes `list_sessions`'s wire format: a u32be count, then each entry's id, name,
+/// argv, pid, size, attached-client count, idle time, and byte counters in the order
+/// `serve.rs`'s `encode_session_info` writes them.
+fn decode_list_response(payload: &[u8]) -> Option<Vec<SessionInfo>> {
+    let mut pos = 0;
+    let count = read_u32(payload, &mut pos)?;
+    let mut sessions = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let id = read_string(payload, &mut pos)?;
+        let name = if read_u8(payload, &mut pos)? != 0 { Some(read_string(payload, &mut pos)?) } else { None };
+        let argc = read_u32(payload, &mut pos)?;
+        let mut argv = Vec::with_capacity(argc as usize);
+        for _ in 0..argc {
+            argv.push(read_string(payload, &mut pos)?);
+        }
+        let pid = read_u32(payload, &mut pos)? as i32;
+        let cols = read_u16(payload, &mut pos)?;
+        let rows = read_u16(payload, &mut pos)?;
+        let attached = read_u32(payload, &mut pos)?;
+        let idle_ms = read_u64(payload, &mut pos)?;
+        let bytes_in = read_u64(payload, &mut pos)?;
+        let bytes_out = read_u64(payload, &mut pos)?;
+        sessions.push(SessionInfo { id, name, argv, pid, cols, rows, attached, idle_ms, bytes_in, bytes_out });
+    }
+    Some(sessions)
+}
+
+fn read_u8(payload: &[u8], pos: &mut usize) -> Option<u8> {
+    let byte = *payload.get(*pos)?;
+    *pos += 1;
+    Some(byte)
+}
+
+fn read_u16(payload: &[u8], pos: &mut usize) -> Option<u16> {
+    let bytes = payload.get(*pos..*pos + 2)?;
+    *pos += 2;
+    Some(u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(payload: &[u8], pos: &mut usize) -> Option<u32> {
+    let bytes = payload.get(*pos..*pos + 4)?;
+    *pos += 4;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(payload: &[u8], pos: &mut usize) -> Option<u64> {
+    let bytes = payload.get(*pos..*pos + 8)?;
+    *pos += 8;
+    Some(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_string(payload: &[u8], pos: &mut usize) -> Option<String> {
+    let len = read_u32(payload, pos)? as usize;
+    let bytes = payload.get(*pos..*pos + len)?;
+    *pos += len;
+    String::from_utf8(bytes.to_vec()).ok()
+}
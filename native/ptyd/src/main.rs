@@ -1,192 +1,1627 @@
-use libc::{c_char, c_int, pid_t};
-use std::ffi::CString;
-use std::io;
-use std::os::fd::RawFd;
+//! Every session spawned the usual way (no subcommand, or `multiplex`) is still its own
+//! `ptyd` process: the OS scheduler already gives each one a dedicated thread of
+//! execution, so a pathological session can't add tail latency to another session's
+//! relay loop. A configurable shared-event-loop vs. dedicated-thread-per-session mode
+//! only becomes a meaningful choice once one process hosts multiple sessions, which is
+//! now `serve.rs` — but `serve` sticks with a thread per connection there too, so there
+//! is still nothing here to make configurable.
+//!
+//! `serve.rs` listens on a Unix-domain socket and, with `--tcp`/`--ws`/`--sse`, on TCP
+//! ports as well; none of those sockets get `TCP_NODELAY`/`SO_KEEPALIVE`/buffer-size
+//! tuning, so they run with the OS defaults.
+//!
+//! `serve`'s session registry makes a `wait <session>` subcommand possible in a way it
+//! wasn't before — addressing a *running* session by id from a second process and
+//! getting its exit code over a control channel is exactly the gap `serve.rs`'s
+//! `OP_DESTROY` and pump thread now sit on top of — but `wait` itself, along with
+//! `send <session> --text ...`, `resize <session> <cols>x<rows>`, and `signal <session>
+//! SIGINT [--tree]`, are still just standalone subcommands this binary doesn't have
+//! yet. `ls` (`ls.rs`) is the first one that exists, talking `serve.rs`'s `OP_LIST` from
+//! a second process the same way those others would talk `OP_CREATE`/`OP_ATTACH`/
+//! `OP_DESTROY`.
+
+mod audit;
+mod bracketed_paste;
+mod bufpool;
+mod capture;
+mod clipboard;
+mod clock;
+mod commands;
+mod compression;
+mod config;
+mod control_json;
+mod cpubudget;
+mod crash;
+mod crc32;
+mod daemon;
+mod env_snapshot;
+mod exec_trace;
+mod export;
+mod generate;
+mod history;
+mod idlestats;
+mod input_tee;
+mod lifecycle;
+mod ls;
+mod multiplex;
+mod outbuf;
+mod persist;
+mod protocol;
+mod pty;
+mod repl;
+mod replay;
+mod rusage;
+mod scrollback;
+mod sd_notify;
+mod serve;
+mod session;
+mod sse;
+mod summary;
+mod tee;
+mod throttle;
+mod tls;
+mod transcode;
+mod watch;
+mod websocket;
+
 use std::process::ExitCode;
-use std::{mem, ptr};
 
-const OPCODE_DATA: u8 = 0x01;
-const OPCODE_RESIZE: u8 = 0x02;
-const OPCODE_CLOSE: u8 = 0x03;
+use audit::SyscallAuditor;
+use bracketed_paste::{frame_for_paste, BracketedPasteTracker};
+use bufpool::BufPool;
+use clipboard::{ClipboardPolicy, ClipboardWrite};
+use clock::{Clock, SharedClock, SystemClock};
+use control_json::{parse_control_lines, ControlMessage};
+use cpubudget::{CpuBudget, CpuBudgetEvent};
+use exec_trace::ExecTracer;
+use idlestats::IdleStats;
+use input_tee::InputTee;
+use lifecycle::CommandLifecycle;
+use outbuf::{OutputBuffer, WatermarkEvent};
+use protocol::{
+    apply_resize, parse_and_apply_frames, parse_and_apply_frames_strict, parse_defer_exec_frames, parse_frames_from_slice,
+    parse_frames_from_slice_strict, read_termios_flags, read_winsize, set_termios_flags, DeferExecEvent, FrameEvent, ResizeBounds,
+    write_all_fd,
+};
+use pty::{fork_and_exec_with_stderr, open_pty, open_stderr_pipe, pidfd_open, pty_path, send_veof, signal_child};
+use rusage::{rusage_children_delta, ResourceUsage};
+use scrollback::Scrollback;
+use session::SessionContext;
+use summary::SessionSummary;
+use tee::TeeFile;
+use throttle::InputRateLimiter;
+use transcode::{InputTranscoder, OutputTranscoder};
 
-fn errno_code() -> Option<i32> {
-    io::Error::last_os_error().raw_os_error()
+fn emit_watermark(session: &SessionContext, event: &WatermarkEvent) {
+    let (name, depth) = match event {
+        WatermarkEvent::High { depth } => ("output-backlog-high", *depth),
+        WatermarkEvent::Low { depth } => ("output-backlog-low", *depth),
+    };
+    let line = format!("{{{},\"event\":\"{name}\",\"depth\":{depth}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
 }
 
-fn write_all_fd(fd: RawFd, mut buf: &[u8]) -> Result<(), ()> {
-    while !buf.is_empty() {
-        let written = unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) };
-        if written < 0 {
-            if errno_code() == Some(libc::EINTR) {
-                continue;
-            }
-            return Err(());
+/// Replies to a `PING` immediately, before touching the pty at all, so the round trip
+/// measures only the control channel. Pairing this with a `DATA` frame sent at the
+/// same time and watching for its echo in the pty output lets a client separate
+/// control-channel latency from write-to-first-output latency through the shell.
+fn emit_pong(session: &SessionContext, client_ts: u64) {
+    let server_ts_us = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0);
+    let line = format!(
+        "{{{},\"event\":\"pong\",\"client_ts\":{client_ts},\"server_ts_us\":{server_ts_us}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Caps how many matches a single `scrollback-search` reply carries, so a pattern that
+/// matches most of a long session's history doesn't blow up the reply into something
+/// the size of the buffer it was meant to search instead of downloading.
+const MAX_SEARCH_MATCHES: usize = 200;
+
+/// How long a `RESIZE` frame waits, with no further `RESIZE` frame arriving, before
+/// it's actually applied. See `--resize-debounce-ms`.
+const DEFAULT_RESIZE_DEBOUNCE_MS: u64 = 30;
+
+/// Minimum output chunk size worth spending `zstd` CPU on. See `--compress-min-bytes`.
+const DEFAULT_COMPRESS_MIN_BYTES: usize = 256;
+
+/// Grace period between `OPCODE_CLOSE_GRACEFUL`'s `SIGTERM` and its `SIGKILL`
+/// escalation, used when the frame's own `grace_ms` field is `0`.
+const DEFAULT_CLOSE_GRACE_MS: i64 = 2000;
+
+/// Fallback geometry for `--cols`/`--rows` when only one of the pair is given, so the
+/// other dimension doesn't end up clamped down to `ResizeBounds::DEFAULT.min_cols`/
+/// `min_rows` (i.e. `1`) instead of something a child would actually expect to see.
+const DEFAULT_INITIAL_COLS: u16 = 80;
+const DEFAULT_INITIAL_ROWS: u16 = 24;
+
+/// Distinct exit codes for `run_default`'s unrecoverable-error paths, each paired with
+/// an `OUTPUT_OPCODE_ERROR` frame (see `emit_error_frame`/`emit_error_frame_direct`) so
+/// a host still has *some* signal even over a transport that can only see the exit
+/// code, not stdout. Plain `1`/`2` stay in use elsewhere (`2` for a missing command
+/// argv, `1` wherever a failure predates this distinction and hasn't been worth
+/// re-triaging).
+const EXIT_PTY_OPEN_FAILED: i32 = 3;
+const EXIT_EXEC_FAILED: i32 = 4;
+const EXIT_IO_FAILED: i32 = 1;
+
+fn emit_search_results(session: &SessionContext, scrollback: Option<&Scrollback>, correlation_id: u32, pattern: &str) {
+    let session_fields = session.fields_json();
+    let Some(scrollback) = scrollback else {
+        let line = format!(
+            "{{{session_fields},\"event\":\"scrollback-search\",\"correlation_id\":{correlation_id},\"error\":\"scrollback-disabled\"}}\n"
+        );
+        let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+        return;
+    };
+    let regex = match regex::Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            let line = format!(
+                "{{{session_fields},\"event\":\"scrollback-search\",\"correlation_id\":{correlation_id},\"error\":{}}}\n",
+                lifecycle::json_escape(&err.to_string())
+            );
+            let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+            return;
+        }
+    };
+    let matches = scrollback.search(&regex, MAX_SEARCH_MATCHES);
+    let matches_json = matches
+        .iter()
+        .map(|m| {
+            let ts_field = match m.unix_ms {
+                Some(ms) => ms.to_string(),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"line\":{},\"offset\":{},\"unix_ms\":{ts_field}}}",
+                lifecycle::json_escape(&m.line),
+                m.offset
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let line = format!(
+        "{{{session_fields},\"event\":\"scrollback-search\",\"correlation_id\":{correlation_id},\"matches\":[{matches_json}]}}\n"
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Renders retained scrollback in the requested format (`0` plain, `1`
+/// ANSI-preserving, `2` HTML — see `capture.rs`) and reports it back as a `capture`
+/// event carrying the same correlation id.
+fn emit_capture(session: &SessionContext, scrollback: Option<&Scrollback>, correlation_id: u32, format: u8) {
+    let session_fields = session.fields_json();
+    let Some(scrollback) = scrollback else {
+        let line = format!(
+            "{{{session_fields},\"event\":\"capture\",\"correlation_id\":{correlation_id},\"error\":\"scrollback-disabled\"}}\n"
+        );
+        let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+        return;
+    };
+    let bytes = scrollback.snapshot();
+    let (format_name, text) = match format {
+        0 => ("plain", capture::plain_text(&bytes)),
+        1 => ("ansi", String::from_utf8_lossy(&bytes).into_owned()),
+        2 => ("html", capture::html(&bytes)),
+        _ => {
+            let line = format!(
+                "{{{session_fields},\"event\":\"capture\",\"correlation_id\":{correlation_id},\"error\":\"unknown-format\"}}\n"
+            );
+            let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+            return;
+        }
+    };
+    let line = format!(
+        "{{{session_fields},\"event\":\"capture\",\"correlation_id\":{correlation_id},\"format\":\"{format_name}\",\"text\":{}}}\n",
+        lifecycle::json_escape(&text)
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Replies to an `OPCODE_REPLAY` frame by pushing retained scrollback (if any) onto
+/// `stdout_buffer` bracketed in `OUTPUT_OPCODE_REPLAY_BEGIN`/`OUTPUT_OPCODE_REPLAY_END`,
+/// so a client that reconnected or cleared its screen can repopulate the terminal by
+/// feeding the replayed bytes through the same escape-sequence interpreter it renders
+/// live output with. Under `--raw-output` there's no framing to bracket with, so the
+/// scrollback bytes are written straight to stdout instead, indistinguishable from
+/// live output the same way every other raw-mode frame already is.
+fn emit_replay(session: &SessionContext, scrollback: Option<&Scrollback>, stdout_buffer: &mut OutputBuffer, raw_output: bool) {
+    let bytes = scrollback.map(Scrollback::snapshot).unwrap_or_default();
+    if raw_output {
+        if let Some(event) = stdout_buffer.push(&bytes) {
+            emit_watermark(session, &event);
+        }
+        return;
+    }
+    if let Some(event) = stdout_buffer.push(&protocol::frame_output_replay_begin(bytes.len() as u32)) {
+        emit_watermark(session, &event);
+    }
+    if !bytes.is_empty() {
+        if let Some(event) = stdout_buffer.push(&protocol::frame_output_data(&bytes)) {
+            emit_watermark(session, &event);
         }
-        let w = written as usize;
-        buf = &buf[w..];
     }
-    Ok(())
+    if let Some(event) = stdout_buffer.push(&protocol::frame_output_replay_end()) {
+        emit_watermark(session, &event);
+    }
+}
+
+/// Reports a `RESIZE` frame that fell outside `ResizeBounds` and was clamped before
+/// being applied, so a client relying on absurd values (`0x0`, 10000 cols) finds out
+/// why the pty ended up a different size than it asked for.
+fn emit_resize_clamped(session: &SessionContext, requested_cols: u16, requested_rows: u16, applied_cols: u16, applied_rows: u16) {
+    let line = format!(
+        "{{{},\"event\":\"resize-clamped\",\"requested_cols\":{requested_cols},\"requested_rows\":{requested_rows},\"applied_cols\":{applied_cols},\"applied_rows\":{applied_rows}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
 }
 
-fn signal_child(child_pid: pid_t, sig: c_int) {
+/// Replies to an `OPCODE_QUERY_WINSIZE` frame with the pty's current `TIOCGWINSZ`, so a
+/// host reconnecting to a session it didn't start can learn the geometry the child
+/// believes it has instead of assuming whatever a prior client last requested.
+fn emit_winsize(session: &SessionContext, master_fd: libc::c_int) {
+    let line = match read_winsize(master_fd) {
+        Ok((cols, rows, xpixel, ypixel)) => format!(
+            "{{{},\"event\":\"winsize\",\"cols\":{cols},\"rows\":{rows},\"xpixel\":{xpixel},\"ypixel\":{ypixel}}}\n",
+            session.fields_json()
+        ),
+        Err(()) => format!("{{{},\"event\":\"winsize\",\"error\":\"ioctl-failed\"}}\n", session.fields_json()),
+    };
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Replies to an `OPCODE_QUERY_INFO` frame with the child's pid, process group, and the
+/// slave pty's `ttyname`, for hosts that need to hand a pid to `ptrace`/`lsof`-style
+/// tooling without having tracked it themselves since the session started.
+fn emit_child_info(session: &SessionContext, child_pid: libc::pid_t, master_fd: libc::c_int) {
     let pgid = unsafe { libc::getpgid(child_pid) };
-    if pgid < 0 {
+    let pgid_field = if pgid < 0 { "null".to_string() } else { pgid.to_string() };
+    let tty_field = match pty_path(master_fd) {
+        Some(path) => lifecycle::json_escape(&path),
+        None => "null".to_string(),
+    };
+    let line = format!(
+        "{{{},\"event\":\"child-info\",\"pid\":{child_pid},\"pgid\":{pgid_field},\"tty\":{tty_field}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports that `--max-input-bytes-per-sec` dropped bytes from a DATA frame because the
+/// client sent faster than the configured cap, so a misbehaving automation client shows
+/// up in the logs instead of just silently losing keystrokes.
+fn emit_input_throttled(session: &SessionContext, dropped_bytes: usize) {
+    let line = format!(
+        "{{{},\"event\":\"input-throttled\",\"dropped_bytes\":{dropped_bytes}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_DATA_CRC32` frame whose checksum didn't match its payload —
+/// dropped rather than relayed, so a client tunneling over a lossy transport can tell
+/// corruption happened instead of the child silently receiving mangled input.
+fn emit_frame_crc_mismatch(session: &SessionContext, expected: u32, computed: u32, length: usize) {
+    let line = format!(
+        "{{{},\"event\":\"frame-crc-mismatch\",\"expected\":{expected},\"computed\":{computed},\"length\":{length}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_DATA_COMPRESSED` frame that didn't decompress to its declared
+/// length — dropped rather than relayed, same reasoning as `emit_frame_crc_mismatch`.
+fn emit_decompression_failed(session: &SessionContext, length: usize) {
+    let line = format!("{{{},\"event\":\"decompression-failed\",\"length\":{length}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Replies to an `OPCODE_STATS` frame with the running counters `SessionSummary`
+/// already maintains for the eventual `--summary` line, plus the two this loop alone
+/// knows: wall-clock uptime and whether the child has gone to EOF yet. `child_state` is
+/// `"running"` until the master fd has reported EOF, even though the child may not be
+/// reaped yet at that point (see `master_eof` in `relay_loop`) — there's no cheaper way
+/// to learn the child already exited without racing the loop's own reap.
+fn emit_stats(session: &SessionContext, summary: &SessionSummary, uptime_ms: i64, child_eof: bool) {
+    let child_state = if child_eof { "exited" } else { "running" };
+    let last_dropped_field = match summary.last_dropped_opcode() {
+        Some(opcode) => opcode.to_string(),
+        None => "null".to_string(),
+    };
+    let line = format!(
+        "{{{},\"event\":\"stats\",\"uptime_ms\":{uptime_ms},\"bytes_in\":{},\"bytes_out\":{},\"frames_in\":{},\"frames_out\":{},\"dropped_opcodes\":{},\"last_dropped_opcode\":{last_dropped_field},\"child_state\":\"{child_state}\"}}\n",
+        session.fields_json(),
+        summary.bytes_in(),
+        summary.bytes_out(),
+        summary.frames_in(),
+        summary.frames_out(),
+        summary.dropped_opcodes(),
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports that an `OPCODE_TOGGLE_INPUT_TEE` frame switched `--input-tee-file`
+/// recording on or off, so a wrapper watching stderr can tell when a capture window
+/// it asked for actually started/stopped rather than inferring it from gaps in the log.
+fn emit_input_tee_toggled(session: &SessionContext, enabled: bool) {
+    let line = format!("{{{},\"event\":\"input-tee-toggled\",\"enabled\":{enabled}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports that an `OPCODE_SIGNAL` frame delivered `signal` to the child's process
+/// group, so a wrapper can tell its SIGINT/SIGTERM/etc. request actually landed.
+fn emit_signal_sent(session: &SessionContext, signal: i32) {
+    let line = format!("{{{},\"event\":\"signal-sent\",\"signal\":{signal}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_EOF` frame and whether `send_veof` actually reached the master
+/// (it can fail if the pty has no `VEOF` character configured, e.g. a child that's
+/// put the tty in raw mode).
+fn emit_eof_sent(session: &SessionContext, delivered: bool) {
+    let line = format!("{{{},\"event\":\"eof-sent\",\"delivered\":{delivered}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports a client's `HELLO` frame and whether this build accepted its declared
+/// protocol version.
+fn emit_hello_received(session: &SessionContext, client_version: u32, client_capabilities: u32, accepted: bool) {
+    let line = format!(
+        "{{{},\"event\":\"hello-received\",\"client_version\":{client_version},\"client_capabilities\":{client_capabilities},\"accepted\":{accepted}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports that the configured idle timeout elapsed with no input from the client
+/// (including `OPCODE_PING`, which doubles as a keepalive — see its doc comment in
+/// `protocol.rs`) and no output from the child either, right before the `SIGTERM`
+/// that starts tearing the session down.
+fn emit_idle_timeout(session: &SessionContext, idle_timeout_ms: i64) {
+    let line = format!("{{{},\"event\":\"idle-timeout\",\"idle_timeout_ms\":{idle_timeout_ms}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_SET_IDLE_TIMEOUT` frame; already applied to `idle_timeout_ms` by
+/// the time this is pushed, same as `InputTeeToggled`. `enabled` is `false` when the
+/// frame's `idle_timeout_ms` was `0`, disabling the timeout entirely.
+fn emit_idle_timeout_set(session: &SessionContext, idle_timeout_ms: Option<i64>) {
+    let enabled = idle_timeout_ms.is_some();
+    let timeout_field = idle_timeout_ms.map(|ms| ms.to_string()).unwrap_or_else(|| "null".to_string());
+    let line = format!(
+        "{{{},\"event\":\"idle-timeout-set\",\"enabled\":{enabled},\"idle_timeout_ms\":{timeout_field}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_SET_TERMIOS` frame and whether `set_termios_flags` actually
+/// landed (it can fail the same way `send_veof` can: `tcgetattr`/`tcsetattr` on the
+/// master fd failing outright, which in practice only happens once the slave is
+/// already gone).
+fn emit_termios_set(session: &SessionContext, mask: u8, value: u8, applied: bool) {
+    let line = format!(
+        "{{{},\"event\":\"termios-set\",\"mask\":{mask},\"value\":{value},\"applied\":{applied}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Replies to an `OPCODE_QUERY_TERMIOS` frame with the slave's current `TERMIOS_FLAG_*`
+/// bits and special characters, so a host can tell e.g. a password prompt has disabled
+/// echo on its own rather than only ever finding out what it itself last set.
+fn emit_termios(session: &SessionContext, master_fd: libc::c_int) {
+    let line = match read_termios_flags(master_fd) {
+        Ok((flags, veof, vintr, vsusp)) => format!(
+            "{{{},\"event\":\"termios\",\"flags\":{flags},\"veof\":{veof},\"vintr\":{vintr},\"vsusp\":{vsusp}}}\n",
+            session.fields_json()
+        ),
+        Err(()) => format!("{{{},\"event\":\"termios\",\"error\":\"tcgetattr-failed\"}}\n", session.fields_json()),
+    };
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_CLOSE_GRACEFUL` frame; the `SIGTERM` it triggers has already been
+/// sent by the time this is pushed, same as `InputTeeToggled`.
+fn emit_close_graceful(session: &SessionContext, grace_ms: i64) {
+    let line = format!("{{{},\"event\":\"close-graceful\",\"grace_ms\":{grace_ms}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports that an `OPCODE_CLOSE_GRACEFUL` grace period elapsed without the child
+/// exiting on its own, right before the `SIGKILL` that escalates it.
+fn emit_close_escalated(session: &SessionContext) {
+    let line = format!("{{{},\"event\":\"close-escalated\"}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_PAUSE`/`OPCODE_RESUME` frame; already applied to `output_paused`
+/// by the time this is pushed, same as `InputTeeToggled`.
+fn emit_output_pause(session: &SessionContext, paused: bool) {
+    let line = format!("{{{},\"event\":\"output-pause\",\"paused\":{paused}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports an `OPCODE_ENABLE_FLOW_CONTROL` frame opting the session into credit-based
+/// flow control with this window.
+fn emit_flow_control_enabled(session: &SessionContext, window_bytes: u32) {
+    let line = format!("{{{},\"event\":\"flow-control-enabled\",\"window_bytes\":{window_bytes}}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports that the client's input stream is done — either an explicit
+/// `OPCODE_HALF_CLOSE_INPUT` frame or plain stdin EOF — so a wrapper can tell the two
+/// apart from a hung session instead of just seeing keystrokes stop arriving.
+fn emit_input_half_closed(session: &SessionContext) {
+    let line = format!("{{{},\"event\":\"input-half-closed\"}}\n", session.fields_json());
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports a child that died from a crash signal (see `crash.rs`), giving the harness
+/// enough to show more than "exit 139": the signal, whether/where a core landed, and
+/// the output trailing right up to the crash.
+fn emit_crash(session: &SessionContext, recent_output: &[u8], info: &crash::CrashInfo) {
+    let core_path_json = match &info.core_path {
+        Some(path) => lifecycle::json_escape(path),
+        None => "null".to_string(),
+    };
+    let line = format!(
+        "{{{},\"event\":\"crash\",\"signal\":{},\"core_dumped\":{},\"core_path\":{core_path_json},\"recent_output\":{}}}\n",
+        session.fields_json(),
+        info.signal,
+        info.core_dumped,
+        lifecycle::json_escape(&String::from_utf8_lossy(recent_output)),
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Pushes `ptyd`'s final `OUTPUT_OPCODE_EXIT_STATUS` frame and flushes it before the
+/// process exits, so a host has the exact wait status even though it collapses to a
+/// single `128 + signum`/exit-code return code at the process level. Skipped under
+/// `--raw-output`, the same way `OUTPUT_OPCODE_DATA` framing is — a host that asked for
+/// unframed stdout gets *only* raw pty bytes, never a frame mixed in.
+fn emit_exit_status_frame(stdout_buffer: &mut OutputBuffer, status: &crash::ExitStatus, raw_output: bool) {
+    if raw_output {
         return;
     }
+    let framed = protocol::frame_output_exit_status(status.exited, status.signal, status.core_dumped);
+    stdout_buffer.push(&framed);
+    let _ = stdout_buffer.flush_blocking(libc::STDOUT_FILENO);
+}
+
+/// Writes an `OUTPUT_OPCODE_ERROR` frame directly to stdout, for a failure that
+/// happens before `stdout_buffer` exists (pty-open, exec) — nothing could already be
+/// queued that early, so there's no ordering to preserve by going through it. `category`
+/// is one of `"pty-open"`/`"exec"`; the message is whatever `errno` the failing syscall
+/// left behind. Skipped under `--raw-output`, the same as `emit_exit_status_frame`.
+fn emit_error_frame_direct(category: &str, raw_output: bool) {
+    if raw_output {
+        return;
+    }
+    let message = std::io::Error::last_os_error().to_string();
+    let framed = protocol::frame_output_error(category, &message);
+    let _ = write_all_fd(libc::STDOUT_FILENO, &framed);
+}
+
+/// Writes an `OUTPUT_OPCODE_ERROR` frame through `stdout_buffer`, flushing blocking so
+/// it actually reaches the host before the process exits — the same ordering
+/// `emit_exit_status_frame` gives its own final frame. `category` is `"io"` for every
+/// call site today (poll/read/write failures on the master or stdout fds); the message
+/// is whatever `errno` the failing syscall left behind.
+fn emit_error_frame(stdout_buffer: &mut OutputBuffer, category: &str, raw_output: bool) {
+    if raw_output {
+        return;
+    }
+    let message = std::io::Error::last_os_error().to_string();
+    let framed = protocol::frame_output_error(category, &message);
+    stdout_buffer.push(&framed);
+    let _ = stdout_buffer.flush_blocking(libc::STDOUT_FILENO);
+}
+
+/// Reports one OSC 52 clipboard-write/query sequence caught in the child's output by
+/// `clipboard::scan_and_filter`, with the policy that decided whether it also reached
+/// the real terminal (see `allowed` on `ClipboardWrite` — under `deny` it didn't).
+fn emit_clipboard(session: &SessionContext, write: &ClipboardWrite, policy: ClipboardPolicy) {
+    let policy_name = match policy {
+        ClipboardPolicy::Allow => "allow",
+        ClipboardPolicy::Deny => "deny",
+        ClipboardPolicy::EventOnly => "event-only",
+    };
+    let line = format!(
+        "{{{},\"event\":\"clipboard\",\"selection\":\"{}\",\"payload\":{},\"is_query\":{},\"policy\":\"{policy_name}\",\"allowed\":{}}}\n",
+        session.fields_json(),
+        write.selection as char,
+        lifecycle::json_escape(&write.payload_base64),
+        write.is_query,
+        write.allowed,
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
 
-    if pgid == child_pid {
-        let _ = unsafe { libc::killpg(pgid, sig) };
+/// Frames one already-read chunk of master output onto `stdout_buffer` and feeds it
+/// through the same bookkeeping (`lifecycle`, tee file, scrollback, crash
+/// recent-output ring, flow-control accounting, `summary`) the main relay loop's own
+/// `POLLIN` read applies — shared with `drain_master_for_flush` so a read triggered by
+/// `OPCODE_FLUSH` looks identical downstream to one triggered by `poll`.
+#[allow(clippy::too_many_arguments)]
+fn relay_master_chunk(
+    output_bytes: &[u8],
+    session: &SessionContext,
+    lifecycle: &mut CommandLifecycle,
+    bracketed_paste: &mut BracketedPasteTracker,
+    clipboard_policy: ClipboardPolicy,
+    pid: libc::pid_t,
+    master_fd: libc::c_int,
+    tee_file: Option<&TeeFile>,
+    scrollback: Option<&mut Scrollback>,
+    clock: &dyn Clock,
+    recent_output: &mut std::collections::VecDeque<u8>,
+    stdout_buffer: &mut OutputBuffer,
+    raw_output: bool,
+    sequenced_output: bool,
+    next_output_seq: &mut u32,
+    timestamped_output: bool,
+    read_at_ms: i64,
+    compression_enabled: bool,
+    compress_min_bytes: usize,
+    flow_control_window: Option<u32>,
+    bytes_unacked: &mut u32,
+    summary: &mut SessionSummary,
+) {
+    lifecycle.observe_output(output_bytes, pid, master_fd);
+    bracketed_paste.observe(output_bytes);
+    if let Some(tee) = tee_file {
+        let _ = tee.write(output_bytes);
+    }
+    if let Some(sb) = scrollback {
+        sb.append(output_bytes, clock.unix_ms());
+    }
+    recent_output.extend(output_bytes.iter().copied());
+    if recent_output.len() > crash::RECENT_OUTPUT_CAP {
+        let excess = recent_output.len() - crash::RECENT_OUTPUT_CAP;
+        recent_output.drain(0..excess);
+    }
+    // Scanned and (under `deny`) filtered after the tee/scrollback/recent-output
+    // bookkeeping above, which always keeps the unfiltered bytes — a security review
+    // of what a denied clipboard write actually contained needs the raw escape
+    // sequence, not just the redacted stream the live client saw.
+    let (filtered_bytes, clipboard_writes) = clipboard::scan_and_filter(output_bytes, clipboard_policy);
+    for write in &clipboard_writes {
+        emit_clipboard(session, write, clipboard_policy);
+    }
+    let output_bytes: &[u8] = &filtered_bytes;
+    let compressed_frame = if !raw_output && compression_enabled && output_bytes.len() >= compress_min_bytes {
+        compression::compress(output_bytes, compression::DEFAULT_LEVEL)
+            .ok()
+            .map(|compressed| protocol::frame_output_data_compressed(output_bytes.len() as u32, &compressed))
+    } else {
+        None
+    };
+    let pushed = if raw_output {
+        stdout_buffer.push(output_bytes)
+    } else if let Some(framed) = compressed_frame.as_ref() {
+        stdout_buffer.push(framed)
+    } else if sequenced_output {
+        let framed = protocol::frame_output_data_seq(*next_output_seq, output_bytes);
+        *next_output_seq = next_output_seq.wrapping_add(1);
+        stdout_buffer.push(&framed)
+    } else if timestamped_output {
+        stdout_buffer.push(&protocol::frame_output_data_timestamped(read_at_ms, output_bytes))
     } else {
-        let _ = unsafe { libc::kill(child_pid, sig) };
+        stdout_buffer.push(&protocol::frame_output_data(output_bytes))
+    };
+    if let Some(event) = pushed {
+        emit_watermark(session, &event);
+    }
+    if flow_control_window.is_some() {
+        *bytes_unacked = bytes_unacked.saturating_add(output_bytes.len() as u32);
     }
+    summary.record_output(output_bytes.len(), stdout_buffer.depth());
+    summary.record_frame_out();
 }
 
-fn parse_and_apply_frames(incoming: &mut Vec<u8>, master_fd: RawFd, child_pid: pid_t) -> Result<(), ()> {
+/// Answers an `OPCODE_FLUSH` frame: reads the master fd for as long as `poll` reports
+/// it immediately readable (a zero-timeout `poll` before each `read`, rather than
+/// toggling `O_NONBLOCK` on a fd the rest of `relay_loop` otherwise treats as
+/// blocking), relaying every chunk exactly like the main loop's own `POLLIN` branch
+/// does. Stops — without reaping or reporting anything itself — the moment the master
+/// isn't immediately readable, or on EOF/a read error, leaving that for the main
+/// loop's own `POLLIN` handling on a later iteration rather than duplicating it here.
+#[allow(clippy::too_many_arguments)]
+fn drain_master_for_flush(
+    master_fd: libc::c_int,
+    pid: libc::pid_t,
+    io_buf: &mut [u8],
+    session: &SessionContext,
+    lifecycle: &mut CommandLifecycle,
+    bracketed_paste: &mut BracketedPasteTracker,
+    clipboard_policy: ClipboardPolicy,
+    tee_file: Option<&TeeFile>,
+    mut scrollback: Option<&mut Scrollback>,
+    clock: &dyn Clock,
+    recent_output: &mut std::collections::VecDeque<u8>,
+    stdout_buffer: &mut OutputBuffer,
+    raw_output: bool,
+    sequenced_output: bool,
+    next_output_seq: &mut u32,
+    timestamped_output: bool,
+    compression_enabled: bool,
+    compress_min_bytes: usize,
+    flow_control_window: Option<u32>,
+    bytes_unacked: &mut u32,
+    summary: &mut SessionSummary,
+    mut output_transcoder: Option<&mut OutputTranscoder>,
+) {
     loop {
-        if incoming.is_empty() {
-            return Ok(());
+        let mut pfd = libc::pollfd { fd: master_fd, events: libc::POLLIN, revents: 0 };
+        let rc = unsafe { libc::poll(&mut pfd, 1, 0) };
+        if rc <= 0 || (pfd.revents & libc::POLLIN) == 0 {
+            return;
         }
+        let n = unsafe { libc::read(master_fd, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+        let read_at_ms = clock.monotonic_ms();
+        if n <= 0 {
+            return;
+        }
+        let n_usize = n as usize;
+        let decoded = output_transcoder.as_deref_mut().map(|t| t.decode(&io_buf[..n_usize]));
+        let output_bytes: &[u8] = decoded.as_deref().map(str::as_bytes).unwrap_or(&io_buf[..n_usize]);
+        relay_master_chunk(
+            output_bytes,
+            session,
+            lifecycle,
+            bracketed_paste,
+            clipboard_policy,
+            pid,
+            master_fd,
+            tee_file,
+            scrollback.as_deref_mut(),
+            clock,
+            recent_output,
+            stdout_buffer,
+            raw_output,
+            sequenced_output,
+            next_output_seq,
+            timestamped_output,
+            read_at_ms,
+            compression_enabled,
+            compress_min_bytes,
+            flow_control_window,
+            bytes_unacked,
+            summary,
+        );
+    }
+}
+
+/// Reports that the child tree's cumulative CPU time, as tracked by `--cpu-budget-ms`
+/// (see `cpubudget.rs`), crossed the warning threshold short of the kill budget.
+fn emit_cpu_budget_warning(session: &SessionContext, usage_ms: u64, budget_ms: u64) {
+    let line = format!(
+        "{{{},\"event\":\"cpu-budget-warning\",\"usage_ms\":{usage_ms},\"budget_ms\":{budget_ms}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports that `--cpu-budget-ms` killed the session for exceeding its CPU budget,
+/// right before the `SIGKILL` that triggers it (see `cpubudget.rs`).
+fn emit_cpu_budget_exceeded(session: &SessionContext, usage_ms: u64, budget_ms: u64) {
+    let line = format!(
+        "{{{},\"event\":\"cpu-budget-exceeded\",\"usage_ms\":{usage_ms},\"budget_ms\":{budget_ms}}}\n",
+        session.fields_json()
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Reports one descendant exec caught by `--trace-exec` (see `exec_trace.rs`).
+fn emit_exec_trace(session: &SessionContext, event: &exec_trace::ExecEvent) {
+    let argv_json = event
+        .argv
+        .iter()
+        .map(|arg| lifecycle::json_escape(arg).to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let line = format!(
+        "{{{},\"event\":\"exec-trace\",\"pid\":{},\"parent_pid\":{},\"argv\":[{argv_json}]}}\n",
+        session.fields_json(),
+        event.pid,
+        event.parent_pid,
+    );
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
 
-        match incoming[0] {
-            OPCODE_DATA => {
-                if incoming.len() < 5 {
-                    return Ok(());
+/// Writes the single startup JSON object `--status-fd` promises: pid, child pid, pty
+/// path, session id, and negotiated protocol version. Written once, right after the
+/// child is forked, so a wrapper reading this fd gets everything it needs to attach
+/// without scraping stdout (which stays pure terminal data) or racing the child's exec.
+fn emit_startup_status(
+    status_fd: libc::c_int,
+    pid: libc::pid_t,
+    child_pid: libc::pid_t,
+    pty_path: Option<&str>,
+    session: &SessionContext,
+) {
+    let pty_path_json = match pty_path {
+        Some(path) => lifecycle::json_escape(path),
+        None => "null".to_string(),
+    };
+    let line = format!(
+        "{{\"pid\":{pid},\"child_pid\":{child_pid},\"pty_path\":{pty_path_json},{},\"protocol_version\":{}}}\n",
+        session.fields_json(),
+        protocol::PROTOCOL_VERSION,
+    );
+    let _ = write_all_fd(status_fd, line.as_bytes());
+}
+
+fn set_nonblocking(fd: libc::c_int) {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags >= 0 {
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+    }
+}
+
+/// Leading flags accepted in front of the command argv, stripped in any order.
+#[derive(Default)]
+struct LeadingFlags {
+    stderr_relay_fd: Option<libc::c_int>,
+    tee_file_path: Option<String>,
+    report_idle_stats: Option<bool>,
+    profile: Option<String>,
+    session_name: Option<String>,
+    scrollback_file: Option<String>,
+    scrollback_bytes: Option<usize>,
+    summary: Option<bool>,
+    child_encoding: Option<String>,
+    min_cols: Option<u16>,
+    max_cols: Option<u16>,
+    min_rows: Option<u16>,
+    max_rows: Option<u16>,
+    resize_debounce_ms: Option<u64>,
+    max_input_bytes_per_sec: Option<u64>,
+    input_tee_file: Option<String>,
+    status_fd: Option<libc::c_int>,
+    events_fd: Option<libc::c_int>,
+    trace_exec: bool,
+    audit_syscalls: bool,
+    cpu_budget_ms: Option<u64>,
+    raw_output: bool,
+    idle_timeout_ms: Option<u64>,
+    defer_exec: bool,
+    compress_min_bytes: Option<usize>,
+    stderr_framed: bool,
+    control_fd: Option<libc::c_int>,
+    clipboard_policy: Option<String>,
+    strict_protocol: bool,
+    cols: Option<u16>,
+    rows: Option<u16>,
+    xpixel: Option<u16>,
+    ypixel: Option<u16>,
+}
+
+/// Strips recognized `--stderr-fd N` / `--tee-file PATH` / `--report-idle-stats` /
+/// `--profile NAME` / `--session-name NAME` / `--scrollback-file PATH` /
+/// `--scrollback-bytes N` / `--summary` / `--child-encoding NAME` / `--min-cols N` /
+/// `--max-cols N` / `--min-rows N` / `--max-rows N` / `--resize-debounce-ms N` /
+/// `--max-input-bytes-per-sec N` / `--input-tee-file PATH` / `--status-fd N` /
+/// `--events-fd N` / `--trace-exec` / `--audit-syscalls` / `--cpu-budget-ms N` /
+/// `--raw-output` / `--idle-timeout-ms N` / `--defer-exec` / `--compress-min-bytes N` /
+/// `--stderr-framed` / `--control-fd N` / `--clipboard-policy NAME` / `--strict-protocol` /
+/// `--cols N` / `--rows N` / `--xpixel N` / `--ypixel N`
+/// flags from the front of `args`, in any order, returning the remaining argv to exec.
+fn take_leading_flags(args: &[String]) -> (LeadingFlags, &[String]) {
+    let mut flags = LeadingFlags::default();
+    let mut rest = args;
+    loop {
+        match rest.first().map(String::as_str) {
+            Some("--stderr-fd") => match rest.get(1).and_then(|v| v.parse::<libc::c_int>().ok()) {
+                Some(value) => {
+                    flags.stderr_relay_fd = Some(value);
+                    rest = &rest[2..];
                 }
-                let n = u32::from_be_bytes([incoming[1], incoming[2], incoming[3], incoming[4]]) as usize;
-                if incoming.len() < 5 + n {
-                    return Ok(());
+                None => break,
+            },
+            Some("--tee-file") => match rest.get(1) {
+                Some(path) => {
+                    flags.tee_file_path = Some(path.clone());
+                    rest = &rest[2..];
                 }
-
-                if n > 0 {
-                    write_all_fd(master_fd, &incoming[5..5 + n])?;
+                None => break,
+            },
+            Some("--report-idle-stats") => {
+                flags.report_idle_stats = Some(true);
+                rest = &rest[1..];
+            }
+            Some("--profile") => match rest.get(1) {
+                Some(name) => {
+                    flags.profile = Some(name.clone());
+                    rest = &rest[2..];
                 }
-                incoming.drain(0..(5 + n));
+                None => break,
+            },
+            Some("--session-name") => match rest.get(1) {
+                Some(name) => {
+                    flags.session_name = Some(name.clone());
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--scrollback-file") => match rest.get(1) {
+                Some(path) => {
+                    flags.scrollback_file = Some(path.clone());
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--scrollback-bytes") => match rest.get(1).and_then(|v| v.parse::<usize>().ok()) {
+                Some(value) => {
+                    flags.scrollback_bytes = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--summary") => {
+                flags.summary = Some(true);
+                rest = &rest[1..];
             }
-            OPCODE_RESIZE => {
-                if incoming.len() < 5 {
-                    return Ok(());
+            Some("--child-encoding") => match rest.get(1) {
+                Some(name) => {
+                    flags.child_encoding = Some(name.clone());
+                    rest = &rest[2..];
                 }
-
-                let cols = u16::from_be_bytes([incoming[1], incoming[2]]);
-                let rows = u16::from_be_bytes([incoming[3], incoming[4]]);
-
-                let mut ws: libc::winsize = unsafe { mem::zeroed() };
-                ws.ws_col = cols;
-                ws.ws_row = rows;
-                let rc = unsafe { libc::ioctl(master_fd, libc::TIOCSWINSZ, &ws) };
-                if rc < 0 {
-                    return Err(());
+                None => break,
+            },
+            Some("--min-cols") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.min_cols = Some(value);
+                    rest = &rest[2..];
                 }
-
-                signal_child(child_pid, libc::SIGWINCH);
-                incoming.drain(0..5);
+                None => break,
+            },
+            Some("--max-cols") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.max_cols = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--min-rows") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.min_rows = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--max-rows") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.max_rows = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--resize-debounce-ms") => match rest.get(1).and_then(|v| v.parse::<u64>().ok()) {
+                Some(value) => {
+                    flags.resize_debounce_ms = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--max-input-bytes-per-sec") => match rest.get(1).and_then(|v| v.parse::<u64>().ok()) {
+                Some(value) => {
+                    flags.max_input_bytes_per_sec = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--input-tee-file") => match rest.get(1) {
+                Some(path) => {
+                    flags.input_tee_file = Some(path.clone());
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--status-fd") => match rest.get(1).and_then(|v| v.parse::<libc::c_int>().ok()) {
+                Some(value) => {
+                    flags.status_fd = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--events-fd") => match rest.get(1).and_then(|v| v.parse::<libc::c_int>().ok()) {
+                Some(value) => {
+                    flags.events_fd = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--trace-exec") => {
+                flags.trace_exec = true;
+                rest = &rest[1..];
             }
-            OPCODE_CLOSE => {
-                signal_child(child_pid, libc::SIGHUP);
-                incoming.drain(0..1);
+            Some("--audit-syscalls") => {
+                flags.audit_syscalls = true;
+                rest = &rest[1..];
             }
-            _ => {
-                incoming.drain(0..1);
+            Some("--cpu-budget-ms") => match rest.get(1).and_then(|v| v.parse::<u64>().ok()) {
+                Some(value) => {
+                    flags.cpu_budget_ms = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--raw-output") => {
+                flags.raw_output = true;
+                rest = &rest[1..];
+            }
+            Some("--idle-timeout-ms") => match rest.get(1).and_then(|v| v.parse::<u64>().ok()) {
+                Some(value) => {
+                    flags.idle_timeout_ms = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--defer-exec") => {
+                flags.defer_exec = true;
+                rest = &rest[1..];
             }
+            Some("--compress-min-bytes") => match rest.get(1).and_then(|v| v.parse::<usize>().ok()) {
+                Some(value) => {
+                    flags.compress_min_bytes = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--stderr-framed") => {
+                flags.stderr_framed = true;
+                rest = &rest[1..];
+            }
+            Some("--control-fd") => match rest.get(1).and_then(|v| v.parse::<libc::c_int>().ok()) {
+                Some(value) => {
+                    flags.control_fd = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--clipboard-policy") => match rest.get(1) {
+                Some(name) => {
+                    flags.clipboard_policy = Some(name.clone());
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--strict-protocol") => {
+                flags.strict_protocol = true;
+                rest = &rest[1..];
+            }
+            Some("--cols") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.cols = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--rows") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.rows = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--xpixel") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.xpixel = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            Some("--ypixel") => match rest.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(value) => {
+                    flags.ypixel = Some(value);
+                    rest = &rest[2..];
+                }
+                None => break,
+            },
+            _ => break,
         }
     }
+    (flags, rest)
 }
 
-fn child_exit_code(status: c_int) -> i32 {
-    if libc::WIFEXITED(status) {
-        return libc::WEXITSTATUS(status);
-    }
-    if libc::WIFSIGNALED(status) {
-        return 128 + libc::WTERMSIG(status);
-    }
-    1
+/// What `wait_for_defer_exec_setup` collected before the `OPCODE_EXEC` frame that ends
+/// `--defer-exec`'s setup phase.
+struct DeferExecSetup {
+    argv: Vec<String>,
+    env: Vec<(String, String)>,
+    cwd: Option<String>,
 }
 
-fn run() -> i32 {
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    if args.is_empty() {
-        return 2;
+/// Blocks reading stdin until an `OPCODE_EXEC` frame arrives, collecting whatever
+/// `OPCODE_SET_ENV`/`OPCODE_SET_CWD` frames preceded it. Returns `None` on stdin EOF
+/// (or a read error) before `EXEC` ever showed up, which `--defer-exec` treats as a
+/// startup failure rather than running a command with no argv.
+fn wait_for_defer_exec_setup() -> Option<DeferExecSetup> {
+    let mut incoming: Vec<u8> = Vec::with_capacity(4096);
+    let mut io_buf = [0_u8; 4096];
+    let mut env: Vec<(String, String)> = Vec::new();
+    let mut cwd: Option<String> = None;
+    loop {
+        for event in parse_defer_exec_frames(&mut incoming) {
+            match event {
+                DeferExecEvent::SetEnv { key, value } => env.push((key, value)),
+                DeferExecEvent::SetCwd { path } => cwd = Some(path),
+                DeferExecEvent::Exec { argv } => return Some(DeferExecSetup { argv, env, cwd }),
+            }
+        }
+        let n = unsafe { libc::read(libc::STDIN_FILENO, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+        if n <= 0 {
+            return None;
+        }
+        incoming.extend_from_slice(&io_buf[..n as usize]);
     }
+}
 
-    let cstrings: Vec<CString> = match args
-        .iter()
-        .map(|arg| CString::new(arg.as_str()))
-        .collect::<Result<Vec<_>, _>>()
-    {
-        Ok(v) => v,
-        Err(_) => return 2,
-    };
-    let mut argv: Vec<*const c_char> = cstrings.iter().map(|s| s.as_ptr()).collect();
-    argv.push(ptr::null());
-
-    let mut master_fd: c_int = 0;
-    let mut slave_fd: c_int = 0;
-    let open_rc = unsafe {
-        libc::openpty(
-            &mut master_fd,
-            &mut slave_fd,
-            ptr::null_mut(),
-            ptr::null_mut(),
-            ptr::null_mut(),
-        )
-    };
-    if open_rc != 0 {
-        return 1;
-    }
+/// Default mode: `ptyd [--profile NAME] [--stderr-fd N] [--tee-file PATH]
+/// [--report-idle-stats] <command> [args...]`. Opens a pty, execs `command` onto the
+/// slave side, and relays framed stdin to the master while framing master output onto
+/// stdout the same way (see `OUTPUT_OPCODE_DATA` below). With `--stderr-fd`, the child's stderr is split off the pty and relayed
+/// raw to the given fd instead of interleaving with pty stdout. With `--tee-file`,
+/// every byte of master output is also appended to the given file (see `tee.rs` for
+/// why that's a plain `write`, not a zero-copy syscall). With `--report-idle-stats`,
+/// loop wakeups and this process's own CPU usage are reported on exit (see
+/// `idlestats.rs`). `--profile` selects a named profile from `~/.config/ptyd/config.toml`
+/// (see `config.rs`) to fill in any of the above flags that weren't passed explicitly.
+/// Every one of these can also be set via a `PTYD_*` environment variable; precedence
+/// is flag > env var > config file (see `config::env_overrides`). `--session-name`
+/// attaches a human-readable name alongside the UUID ptyd generates for every session
+/// (see `session.rs`); every event line below is stamped with both. On exit, a row
+/// summarizing the session (command, times, exit status, resource usage, tee path) is
+/// recorded to the shared history store (see `history.rs`), queryable with `ptyd history`.
+/// With `--scrollback-file PATH` (and optionally `--scrollback-bytes N`, default 8MiB),
+/// master output is also retained in a bounded on-disk ring for later retrieval (see
+/// `scrollback.rs`) beyond what the relay loop's own small buffers hold. With
+/// `--summary`, a `summary` event is emitted on exit with duration, bytes relayed in
+/// each direction, peak output-backlog depth, exit reason, resource usage, and the
+/// recording path, so a wrapper script gets those facts in one line (see `summary.rs`).
+/// With `--child-encoding NAME` (e.g. `latin-1`, `shift-jis`, `gbk`), child output is
+/// transcoded from that encoding to UTF-8 before it reaches stdout/tee/scrollback, and
+/// host input is transcoded from UTF-8 to that encoding before it reaches the child
+/// (see `transcode.rs`), for vendor CLIs that still speak a legacy encoding.
+/// `--min-cols`/`--max-cols`/`--min-rows`/`--max-rows` override the default bounds a
+/// `RESIZE` frame is clamped into (see `ResizeBounds` in `protocol.rs`); a clamped
+/// request is reported as a `resize-clamped` event. During a window drag the host can
+/// send dozens of `RESIZE` frames a second; only the last one received within a
+/// `--resize-debounce-ms` window (default `DEFAULT_RESIZE_DEBOUNCE_MS`) is actually
+/// applied, so the child sees one `SIGWINCH` per drag settling rather than a storm of
+/// them mid-drag. With `--max-input-bytes-per-sec N`, input frames are relayed through
+/// a token-bucket cap (see `throttle.rs`); bytes beyond what the bucket admits are
+/// dropped and reported as an `input-throttled` event, so a misbehaving automation
+/// client spamming the pty can't lock up the child. The cap is off by default. With
+/// `--input-tee-file PATH`, every byte actually relayed to the child is mirrored to
+/// that file with a timestamp header (see `input_tee.rs`), separate from
+/// `--tee-file`'s record of child output; recording can be toggled on/off at runtime
+/// with `OPCODE_TOGGLE_INPUT_TEE`, reported as an `input-tee-toggled` event. With
+/// `--status-fd N`, a single JSON object (pid, child pid, pty path, session id,
+/// `protocol_version`) is written to that fd right after the child is forked, so a
+/// wrapper gets structured startup metadata without scraping stdout, which stays pure
+/// terminal data. With `--events-fd N`, every other JSON event ptyd emits (watermark,
+/// resize-clamped, input-throttled, pong, summary, idle-stats, ...) is redirected from
+/// fd 2 to that fd instead, so an existing consumer reading raw child output on stdout
+/// and diagnostics on stderr keeps working while a new consumer can open fd N for
+/// structured events alone. Since every event line is already written to
+/// `libc::STDERR_FILENO`, retargeting them is a single `dup2` rather than threading an
+/// fd through each call site. With `--trace-exec`, every `execve` anywhere in the
+/// child's descendant tree is reported as an `exec-trace` event with pid, parent pid,
+/// and argv (see `exec_trace.rs`); this needs `CAP_NET_ADMIN` to subscribe to the
+/// kernel's process-events connector, so it's opt-in and silently unavailable without
+/// that privilege rather than failing the session. `--audit-syscalls` goes further:
+/// it runs the child under `ptrace` and emits a `syscall-audit` event for every
+/// `open`/`openat`/`connect`/`execve` it makes, with the path or address and return
+/// value (see `audit.rs`). It's considerably more expensive than `--trace-exec`
+/// (every syscall round-trips through a stop), so it's meant for one-off
+/// high-assurance review of an untrusted command rather than routine sessions, and
+/// like `--trace-exec` it degrades silently (missing `ptrace` permission, non-x86_64
+/// hosts) rather than failing the session. With `--cpu-budget-ms N`, the child tree's
+/// cumulative CPU time is tracked via a dedicated cgroup (see `cpubudget.rs`) —
+/// tree-wide and exact, unlike `rusage.rs`'s reaped-children approximation — separately
+/// from any wall-clock timeout, so a spin loop in an otherwise quiet session (no
+/// output, no exit) gets caught too. A `cpu-budget-warning` event fires at 80% of the
+/// budget; exceeding it kills the session and is reported as a `cpu-budget-exceeded`
+/// event and, in `--summary`'s output, a `"cpu-budget-exceeded"` exit reason rather
+/// than the generic `"signaled"`. Creating the cgroup needs root or a delegated
+/// subtree, so like the tracing flags above it's silently unavailable rather than
+/// failing the session when that's not set up.
+///
+/// Master output is framed on stdout by default, each chunk wrapped in an
+/// `OUTPUT_OPCODE_DATA` frame (see `protocol.rs`) the same shape as input's `DATA`
+/// frames, so a host can tell pty bytes apart from any future out-of-band frame this
+/// stream might carry instead of having only stderr's JSON events to go on.
+/// `--raw-output` turns this back off for a host that still expects stdout to be
+/// nothing but terminal bytes. If a client's `HELLO` requests `CAP_COMPRESSION`, output
+/// chunks of at least `--compress-min-bytes` bytes (default `DEFAULT_COMPRESS_MIN_BYTES`)
+/// are `zstd`-compressed into an `OUTPUT_OPCODE_DATA_COMPRESSED` frame instead (see
+/// `compression.rs`), for a slow link where spending CPU beats spending bandwidth on a
+/// verbose command's output; smaller chunks keep using whatever framing the session would
+/// otherwise use. A client may also send compressed input as `OPCODE_DATA_COMPRESSED`
+/// whenever `CAP_COMPRESSION` is negotiated, regardless of the output-side threshold.
+///
+/// With `--stderr-framed`, the child's stderr is split off the pty the same way
+/// `--stderr-fd` already splits it (onto its own pipe rather than the slave), but
+/// instead of being relayed raw to a separate fd it's wrapped in
+/// `OUTPUT_OPCODE_STDERR_DATA` frames and interleaved on stdout alongside
+/// `OUTPUT_OPCODE_DATA`, for a host that would rather read one fd and tell the streams
+/// apart by opcode than open a second one. Useful for a non-interactive tool whose
+/// stderr is diagnostics the harness wants to keep separate from the terminal output it
+/// renders. Both flags can be combined, since the same pipe feeds both destinations,
+/// though most callers want one or the other. Ignored under `--raw-output`, the same as
+/// every other framing-dependent flag.
+///
+/// `--defer-exec` takes no command argv at all: ptyd opens the pty and then blocks
+/// reading `OPCODE_SET_ENV`/`OPCODE_SET_CWD`/`OPCODE_EXEC` frames off stdin (see
+/// `wait_for_defer_exec_setup` and `protocol::parse_defer_exec_frames`) before forking
+/// anything. `OPCODE_EXEC` ends the wait and supplies the argv; whatever
+/// `OPCODE_SET_ENV` frames arrived before it become the child's entire
+/// environment — not a diff against ptyd's own, and empty (not inherited) if none were
+/// sent — and the last `OPCODE_SET_CWD` frame (if any) becomes its working directory.
+/// This lets a host hand a subprocess an exact, reproducible environment rather than
+/// whatever ptyd itself happened to be started with.
+///
+/// With `--control-fd N`, control messages move off stdin entirely and onto a
+/// dedicated fd as newline-delimited JSON (see `control_json.rs`) — `{"op":"resize",
+/// "cols":100,"rows":30}`, `{"op":"signal","signal":15}`, `{"op":"close"}`,
+/// `{"op":"close_graceful","grace_ms":2000}`, `{"op":"query_winsize"}`,
+/// `{"op":"query_info"}`, `{"op":"query_state"}`, `{"op":"stats"}` — so a shell script
+/// can drive resize/signal/close/queries with `echo`/`jq` instead of hand-rolling
+/// `protocol.rs`'s binary framing. With `--control-fd` set, stdin carries nothing but
+/// raw bytes relayed straight to the child: there is no longer anything on that stream
+/// for `parse_and_apply_frames` to parse, since every control message that would
+/// otherwise ride an `OPCODE_*` frame now arrives on the control fd instead. A line
+/// that fails to parse is dropped rather than torn down as a protocol error, the same
+/// leniency an unrecognized binary opcode already gets.
+///
+/// With `--clipboard-policy NAME` (`allow`, `deny`, or `event-only`, default
+/// `event-only`), every OSC 52 clipboard-write/query sequence the child prints is
+/// caught (see `clipboard.rs`) and surfaced as a `clipboard` event — selection,
+/// payload, whether it was a query, and whether it was let through — instead of
+/// passing it to the real terminal unobserved. `allow`/`event-only` both forward the
+/// sequence unchanged (the latter purely for visibility, the former as an explicit
+/// policy choice with identical effect); `deny` strips it so the host's terminal never
+/// sees it and no clipboard access happens at all. An unrecognized policy name is
+/// reported and falls back to `event-only`, the same way an unrecognized
+/// `--child-encoding` falls back to untranscoded relaying.
+///
+/// With `--strict-protocol`, every stdin frame (even `OPCODE_CLOSE`'s plain opcode
+/// byte with no payload at all) must carry an explicit `[opcode][u32be length][length
+/// bytes]` wrapper (see `parse_frames_strict` in `protocol.rs`) instead of `parse_frames`'s
+/// usual mix of fixed-size and internally-length-prefixed shapes per opcode. An opcode
+/// this build doesn't recognize is skipped as a whole frame rather than one byte at a
+/// time, and an `OUTPUT_OPCODE_NAK` frame carrying that opcode is written back to
+/// stdout for each one dropped, so a protocol bug surfaces immediately instead of
+/// silently desyncing the stream. Has no effect once `--control-fd` has already moved
+/// control frames off stdin entirely, since there's nothing left on stdin for either
+/// parser to apply strict framing to.
+///
+/// What `wait_for_defer_exec_setup` collected before the `OPCODE_EXEC` frame that ends
+/// `--defer-exec`'s setup phase.
+struct DeferExecSetup {
+    argv: Vec<String>,
+    env: Vec<(String, String)>,
+    cwd: Option<String>,
+}
 
-    let pid = unsafe { libc::fork() };
-    if pid < 0 {
-        unsafe {
-            libc::close(master_fd);
-            libc::close(slave_fd);
+/// Blocks reading stdin until an `OPCODE_EXEC` frame arrives, collecting whatever
+/// `OPCODE_SET_ENV`/`OPCODE_SET_CWD` frames preceded it. Returns `None` on stdin EOF
+/// (or a read error) before `EXEC` ever showed up, which `--defer-exec` treats as a
+/// startup failure rather than running a command with no argv.
+fn wait_for_defer_exec_setup() -> Option<DeferExecSetup> {
+    let mut incoming: Vec<u8> = Vec::with_capacity(4096);
+    let mut io_buf = [0_u8; 4096];
+    let mut env: Vec<(String, String)> = Vec::new();
+    let mut cwd: Option<String> = None;
+    loop {
+        for event in parse_defer_exec_frames(&mut incoming) {
+            match event {
+                DeferExecEvent::SetEnv { key, value } => env.push((key, value)),
+                DeferExecEvent::SetCwd { path } => cwd = Some(path),
+                DeferExecEvent::Exec { argv } => return Some(DeferExecSetup { argv, env, cwd }),
+            }
+        }
+        let n = unsafe { libc::read(libc::STDIN_FILENO, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+        if n <= 0 {
+            return None;
         }
-        return 1;
+        incoming.extend_from_slice(&io_buf[..n as usize]);
     }
+}
 
-    if pid == 0 {
-        if unsafe { libc::setsid() } < 0 {
-            unsafe { libc::_exit(1) };
+fn run_default(raw_args: &[String]) -> i32 {
+    let clock: SharedClock = std::rc::Rc::new(SystemClock);
+    let (flags, args) = take_leading_flags(raw_args);
+    let env = config::env_overrides();
+    let profile = flags.profile.or_else(config::env_profile);
+    let config = config::load(profile.as_deref());
+    let events_fd = flags.events_fd.or(env.events_fd).or(config.events_fd);
+    if let Some(fd) = events_fd {
+        if fd != libc::STDERR_FILENO {
+            unsafe { libc::dup2(fd, libc::STDERR_FILENO) };
+            unsafe { libc::close(fd) };
+        }
+    }
+    let stderr_relay_fd = flags.stderr_relay_fd.or(env.stderr_fd).or(config.stderr_fd);
+    let tee_file_path = flags.tee_file_path.or(env.tee_file).or(config.tee_file);
+    let tee_file = tee_file_path.as_deref().and_then(TeeFile::open);
+    let report_idle_stats = flags.report_idle_stats.or(env.report_idle_stats).or(config.report_idle_stats);
+    let scrollback_file_path = flags.scrollback_file.or(env.scrollback_file).or(config.scrollback_file);
+    let scrollback_bytes = flags.scrollback_bytes.or(env.scrollback_bytes).or(config.scrollback_bytes);
+    let mut scrollback = scrollback_file_path
+        .as_deref()
+        .and_then(|path| Scrollback::open(path, scrollback_bytes.unwrap_or(8 * 1024 * 1024)));
+    let summary_enabled = flags.summary.or(env.summary).or(config.summary).unwrap_or(false);
+    let mut summary = SessionSummary::new(summary_enabled);
+    let child_encoding_name = flags.child_encoding.or(env.child_encoding).or(config.child_encoding);
+    let child_encoding = child_encoding_name.as_deref().and_then(transcode::lookup);
+    if let Some(name) = child_encoding_name.as_deref() {
+        if child_encoding.is_none() {
+            eprintln!("ptyd: unknown --child-encoding {name:?}, relaying bytes untranscoded");
         }
+    }
+    let mut output_transcoder = child_encoding.map(OutputTranscoder::new);
+    let mut input_transcoder = child_encoding.map(InputTranscoder::new);
+    let clipboard_policy_name = flags.clipboard_policy.or(env.clipboard_policy).or(config.clipboard_policy);
+    let clipboard_policy = clipboard_policy_name.as_deref().and_then(clipboard::parse_policy).unwrap_or_default();
+    if let Some(name) = clipboard_policy_name.as_deref() {
+        if clipboard::parse_policy(name).is_none() {
+            eprintln!("ptyd: unknown --clipboard-policy {name:?}, defaulting to event-only");
+        }
+    }
+    let resize_bounds = ResizeBounds {
+        min_cols: flags.min_cols.or(env.min_cols).or(config.min_cols).unwrap_or(ResizeBounds::DEFAULT.min_cols),
+        max_cols: flags.max_cols.or(env.max_cols).or(config.max_cols).unwrap_or(ResizeBounds::DEFAULT.max_cols),
+        min_rows: flags.min_rows.or(env.min_rows).or(config.min_rows).unwrap_or(ResizeBounds::DEFAULT.min_rows),
+        max_rows: flags.max_rows.or(env.max_rows).or(config.max_rows).unwrap_or(ResizeBounds::DEFAULT.max_rows),
+    };
+    let resize_debounce_ms = flags
+        .resize_debounce_ms
+        .or(env.resize_debounce_ms)
+        .or(config.resize_debounce_ms)
+        .unwrap_or(DEFAULT_RESIZE_DEBOUNCE_MS) as i64;
+    let raw_output = flags.raw_output;
+    let idle_timeout_ms = flags.idle_timeout_ms.map(|n| n as i64);
+    let max_input_bytes_per_sec = flags
+        .max_input_bytes_per_sec
+        .or(env.max_input_bytes_per_sec)
+        .or(config.max_input_bytes_per_sec);
+    let mut rate_limiter = max_input_bytes_per_sec.map(|n| InputRateLimiter::new(n, clock.clone()));
+    let compress_min_bytes = flags
+        .compress_min_bytes
+        .or(env.compress_min_bytes)
+        .or(config.compress_min_bytes)
+        .unwrap_or(DEFAULT_COMPRESS_MIN_BYTES);
+    let input_tee_file_path = flags.input_tee_file.or(env.input_tee_file).or(config.input_tee_file);
+    let mut input_tee = input_tee_file_path.as_deref().and_then(InputTee::open);
+    let status_fd = flags.status_fd.or(env.status_fd).or(config.status_fd);
+    let control_fd = flags.control_fd.or(env.control_fd).or(config.control_fd);
+    if let Some(fd) = control_fd {
+        set_nonblocking(fd);
+    }
+    let session = SessionContext::new(flags.session_name);
+    let mut idle_stats = IdleStats::new(session.fields_json(), report_idle_stats.unwrap_or(false), clock.clone());
+    if !flags.defer_exec && args.is_empty() {
+        return 2;
+    }
 
-        if unsafe { libc::ioctl(slave_fd, libc::TIOCSCTTY as libc::c_ulong, 0) } < 0 {
-            unsafe { libc::_exit(1) };
+    let pair = match open_pty() {
+        Some(p) => p,
+        None => {
+            emit_error_frame_direct("pty-open", raw_output);
+            return EXIT_PTY_OPEN_FAILED;
         }
+    };
+    if flags.cols.is_some() || flags.rows.is_some() || flags.xpixel.is_some() || flags.ypixel.is_some() {
+        let requested_cols = flags.cols.unwrap_or(DEFAULT_INITIAL_COLS);
+        let requested_rows = flags.rows.unwrap_or(DEFAULT_INITIAL_ROWS);
+        let (cols, rows, _) = resize_bounds.clamp(requested_cols, requested_rows);
+        let _ = pty::set_initial_winsize(pair.master_fd, cols, rows, flags.xpixel.unwrap_or(0), flags.ypixel.unwrap_or(0));
+    }
 
-        if unsafe { libc::dup2(slave_fd, libc::STDIN_FILENO) } < 0 {
-            unsafe { libc::_exit(1) };
+    let stderr_framed = flags.stderr_framed;
+    let strict_protocol = flags.strict_protocol;
+    let stderr_pipe = if stderr_relay_fd.is_some() || stderr_framed { open_stderr_pipe() } else { None };
+    let stderr_pipe_write = stderr_pipe.map(|(_, write_fd)| write_fd);
+
+    let (pid, auditor) = if flags.defer_exec {
+        let Some(setup) = wait_for_defer_exec_setup() else {
+            emit_error_frame_direct("exec", raw_output);
+            return EXIT_EXEC_FAILED;
+        };
+        match fork_and_exec_with_stderr(
+            &setup.argv,
+            pair.master_fd,
+            pair.slave_fd,
+            stderr_pipe_write,
+            false,
+            setup.cwd.as_deref(),
+            Some(&setup.env),
+        ) {
+            Some(pid) => (pid, None),
+            None => {
+                emit_error_frame_direct("exec", raw_output);
+                return EXIT_EXEC_FAILED;
+            }
         }
-        if unsafe { libc::dup2(slave_fd, libc::STDOUT_FILENO) } < 0 {
-            unsafe { libc::_exit(1) };
+    } else if flags.audit_syscalls {
+        match SyscallAuditor::spawn(args.to_vec(), pair.master_fd, pair.slave_fd, stderr_pipe_write, session.clone()) {
+            Some((pid, auditor)) => (pid, Some(auditor)),
+            None => {
+                eprintln!(
+                    "ptyd: --audit-syscalls requested but ptrace attach failed (need CAP_SYS_PTRACE, or an x86_64 host?), \
+                     running untraced"
+                );
+                match fork_and_exec_with_stderr(args, pair.master_fd, pair.slave_fd, stderr_pipe_write, false, None, None) {
+                    Some(pid) => (pid, None),
+                    None => {
+                        emit_error_frame_direct("exec", raw_output);
+                        return EXIT_EXEC_FAILED;
+                    }
+                }
+            }
         }
-        if unsafe { libc::dup2(slave_fd, libc::STDERR_FILENO) } < 0 {
-            unsafe { libc::_exit(1) };
+    } else {
+        match fork_and_exec_with_stderr(args, pair.master_fd, pair.slave_fd, stderr_pipe_write, false, None, None) {
+            Some(pid) => (pid, None),
+            None => {
+                emit_error_frame_direct("exec", raw_output);
+                return EXIT_EXEC_FAILED;
+            }
         }
+    };
+    let stderr_pipe_read = stderr_pipe.map(|(read_fd, _)| read_fd);
 
-        unsafe {
-            libc::close(master_fd);
-            libc::close(slave_fd);
-            libc::execvp(argv[0], argv.as_ptr());
-            libc::_exit(127);
-        }
+    if let Some(fd) = status_fd {
+        emit_startup_status(fd, std::process::id() as libc::pid_t, pid, pty_path(pair.master_fd).as_deref(), &session);
     }
 
-    unsafe {
-        libc::close(slave_fd);
+    let exec_tracer = if flags.trace_exec {
+        let tracer = ExecTracer::open(pid);
+        if tracer.is_none() {
+            eprintln!("ptyd: --trace-exec requested but the process-events connector could not be opened (need CAP_NET_ADMIN?)");
+        }
+        tracer
+    } else {
+        None
+    };
+
+    // `pidfd_open` lets child exit show up as a normal pollable fd, so an idle
+    // session blocks in `poll` indefinitely instead of waking every 100ms to check.
+    // Skipped for an audited child: the audit supervisor thread owns every
+    // `waitpid` call on it, including the one that detects its real exit, so
+    // `relay_loop` must not have its own pidfd-triggered or WNOHANG-fallback path
+    // racing that thread for the same pid (see `audit.rs`).
+    let pidfd = if auditor.is_some() { None } else { pidfd_open(pid) };
+    set_nonblocking(libc::STDOUT_FILENO);
+
+    let cpu_budget_ms = flags.cpu_budget_ms.or(env.cpu_budget_ms).or(config.cpu_budget_ms);
+    let mut cpu_budget = cpu_budget_ms.and_then(|budget_ms| CpuBudget::create(&session.id, pid, budget_ms));
+    if cpu_budget_ms.is_some() && cpu_budget.is_none() {
+        eprintln!("ptyd: --cpu-budget-ms requested but the cgroup could not be created (need root, or a delegated subtree?), running unbudgeted");
     }
 
     let mut incoming: Vec<u8> = Vec::with_capacity(8192);
-    let mut io_buf = vec![0_u8; 65_536];
+    let mut control_incoming: Vec<u8> = Vec::new();
+    let mut strict_naks: Vec<u8> = Vec::new();
+    // Drawn from a pool rather than allocated directly; this one buffer is acquired
+    // once and reused for every read for the rest of the session, and is not released
+    // back to the pool.
+    let mut bufpool = BufPool::new(65_536);
+    let mut io_buf = bufpool.acquire();
     let mut stdin_open = true;
+    let mut frame_events: Vec<FrameEvent> = Vec::new();
+    let mut lifecycle = CommandLifecycle::new(session.clone(), clock.clone());
+    let mut bracketed_paste = BracketedPasteTracker::default();
+    let mut stdout_buffer = OutputBuffer::new(OutputBuffer::DEFAULT_HIGH_WATERMARK, OutputBuffer::DEFAULT_LOW_WATERMARK);
 
-    loop {
-        let mut status: c_int = 0;
-        let waited = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
-        if waited == pid {
-            unsafe { libc::close(master_fd) };
-            return child_exit_code(status);
-        }
+    let started_at_unix_ms = clock.unix_ms();
+    let usage_baseline = ResourceUsage::children_now();
+    let mut cpu_budget_exceeded = false;
+    let mut protocol_incompatible = false;
+    let mut idle_timeout_hit = false;
+    if !raw_output {
+        let hello = protocol::frame_output_hello(protocol::PROTOCOL_VERSION, protocol::CAPABILITIES);
+        stdout_buffer.push(&hello);
+        let started = protocol::frame_output_started(pid, pty_path(pair.master_fd).as_deref().unwrap_or_default());
+        stdout_buffer.push(&started);
+        let _ = stdout_buffer.flush_blocking(libc::STDOUT_FILENO);
+    }
+    let exit_code = relay_loop(
+        pair.master_fd,
+        pid,
+        pidfd,
+        stderr_relay_fd,
+        stderr_pipe_read,
+        tee_file.as_ref(),
+        &session,
+        &mut idle_stats,
+        &mut incoming,
+        &mut io_buf,
+        &mut stdin_open,
+        &mut frame_events,
+        &mut lifecycle,
+        &mut bracketed_paste,
+        &mut stdout_buffer,
+        scrollback.as_mut(),
+        &mut summary,
+        output_transcoder.as_mut(),
+        input_transcoder.as_mut(),
+        &resize_bounds,
+        resize_debounce_ms,
+        rate_limiter.as_mut(),
+        input_tee.as_mut(),
+        exec_tracer.as_ref(),
+        auditor.as_ref(),
+        cpu_budget.as_mut(),
+        &mut cpu_budget_exceeded,
+        clock.as_ref(),
+        raw_output,
+        &mut protocol_incompatible,
+        idle_timeout_ms,
+        &mut idle_timeout_hit,
+        compress_min_bytes,
+        stderr_framed,
+        control_fd,
+        &mut control_incoming,
+        clipboard_policy,
+        strict_protocol,
+        &mut strict_naks,
+    );
+    let delta = rusage_children_delta(&usage_baseline);
+    let ended_at_unix_ms = clock.unix_ms();
+    let exit_reason_override = if cpu_budget_exceeded {
+        Some("cpu-budget-exceeded")
+    } else if protocol_incompatible {
+        Some("protocol-incompatible")
+    } else if idle_timeout_hit {
+        Some("idle-timeout")
+    } else {
+        None
+    };
+    summary.emit(
+        &session,
+        started_at_unix_ms,
+        ended_at_unix_ms,
+        exit_code,
+        &delta,
+        tee_file_path.as_deref(),
+        exit_reason_override,
+    );
+    history::record(&history::SessionRecord {
+        session_id: session.id.clone(),
+        session_name: session.name.clone(),
+        command: args.join(" "),
+        started_at_unix_ms,
+        ended_at_unix_ms,
+        exit_code,
+        cpu_user_ms: delta.user_ms,
+        cpu_sys_ms: delta.sys_ms,
+        peak_rss_kb: delta.max_rss_kb,
+        recording_path: tee_file_path,
+    });
+    exit_code
+}
 
-        let stdin_fd = if stdin_open { libc::STDIN_FILENO } else { -1 };
+/// The relay loop proper, split out of `run_default` so a session's completion
+/// (whichever poll branch below returns first) is a single return value `run_default`
+/// can record history against, rather than a dozen scattered early-return sites.
+#[allow(clippy::too_many_arguments)]
+fn relay_loop(
+    master_fd: libc::c_int,
+    pid: libc::pid_t,
+    pidfd: Option<libc::c_int>,
+    stderr_relay_fd: Option<libc::c_int>,
+    stderr_pipe_read: Option<libc::c_int>,
+    tee_file: Option<&TeeFile>,
+    session: &SessionContext,
+    idle_stats: &mut IdleStats,
+    incoming: &mut Vec<u8>,
+    io_buf: &mut [u8],
+    stdin_open: &mut bool,
+    frame_events: &mut Vec<FrameEvent>,
+    lifecycle: &mut CommandLifecycle,
+    bracketed_paste: &mut BracketedPasteTracker,
+    stdout_buffer: &mut OutputBuffer,
+    mut scrollback: Option<&mut Scrollback>,
+    summary: &mut SessionSummary,
+    mut output_transcoder: Option<&mut OutputTranscoder>,
+    mut input_transcoder: Option<&mut InputTranscoder>,
+    resize_bounds: &ResizeBounds,
+    resize_debounce_ms: i64,
+    mut rate_limiter: Option<&mut InputRateLimiter>,
+    mut input_tee: Option<&mut InputTee>,
+    exec_tracer: Option<&ExecTracer>,
+    auditor: Option<&SyscallAuditor>,
+    mut cpu_budget: Option<&mut CpuBudget>,
+    cpu_budget_exceeded: &mut bool,
+    clock: &dyn Clock,
+    raw_output: bool,
+    protocol_incompatible: &mut bool,
+    idle_timeout_ms: Option<i64>,
+    idle_timeout_hit: &mut bool,
+    compress_min_bytes: usize,
+    stderr_framed: bool,
+    control_fd: Option<libc::c_int>,
+    control_incoming: &mut Vec<u8>,
+    clipboard_policy: ClipboardPolicy,
+    strict_protocol: bool,
+    strict_naks: &mut Vec<u8>,
+) -> i32 {
+    let mut pending_resize: Option<(u16, u16, u16, u16)> = None;
+    let mut resize_deadline: Option<i64> = None;
+    // Set by `OPCODE_CLOSE_GRACEFUL` right after its `SIGTERM`; once this deadline
+    // passes without the child having exited (the loop would have already returned if
+    // it had), the next iteration escalates to `SIGKILL`.
+    let mut close_deadline: Option<i64> = None;
+    // Seeded to "now" rather than session start, so a client that never sends
+    // anything still gets the full `idle_timeout_ms` grace period before teardown.
+    // Updated on input frames and on master output alike (see `OPCODE_SET_IDLE_TIMEOUT`'s
+    // doc comment in `protocol.rs`) — either direction counts as the session being alive.
+    let mut last_activity_ms = clock.monotonic_ms();
+    let mut idle_timeout_signaled = false;
+    // Overridable at runtime by `OPCODE_SET_IDLE_TIMEOUT`; starts at whatever
+    // `--idle-timeout-ms` was (or wasn't) passed at startup.
+    let mut idle_timeout_ms = idle_timeout_ms;
+    // With an auditor attached, the master going EOF must not trigger this loop's own
+    // `waitpid` (see the exit-detection block below), so once seen it's left alone
+    // until the auditor's own fd reports the real exit.
+    let mut master_eof = false;
+    // Set/cleared by `OPCODE_PAUSE`/`OPCODE_RESUME`: an explicit client request to stop
+    // reading the master, on top of (not instead of) the automatic backpressure below —
+    // a slow consumer who never sends PAUSE still gets the watermark's protection, and a
+    // consumer that does send PAUSE gets to hold the pty's kernel buffer full even while
+    // `stdout_buffer` itself has drained.
+    let mut output_paused = false;
+    // Credit-based flow control (see `OPCODE_ENABLE_FLOW_CONTROL`): `None` until a
+    // client opts in, then `Some(window_bytes)`; `bytes_unacked` grows with every chunk
+    // read from the master and shrinks as `OPCODE_ACK` frames return credit.
+    let mut flow_control_window: Option<u32> = None;
+    let mut bytes_unacked: u32 = 0;
+    // Set once by a `HELLO` requesting `CAP_SEQUENCED_OUTPUT`; once set, every output
+    // data frame for the rest of the session carries a sequence number instead of
+    // plain `OUTPUT_OPCODE_DATA` (see `relay_master_chunk`).
+    let mut sequenced_output = false;
+    let mut next_output_seq: u32 = 0;
+    // Set once by a `HELLO` requesting `CAP_TIMESTAMPED_OUTPUT`; mutually exclusive
+    // with `sequenced_output` (see `OUTPUT_OPCODE_DATA_TIMESTAMPED`'s doc comment) —
+    // `sequenced_output` wins if a client somehow requests both.
+    let mut timestamped_output = false;
+    // Set once by a `HELLO` requesting `CAP_COMPRESSION`; takes priority over
+    // `sequenced_output`/`timestamped_output` for any chunk that clears
+    // `compress_min_bytes` (see `relay_master_chunk`'s doc comment and
+    // `OUTPUT_OPCODE_DATA_COMPRESSED`).
+    let mut compression_enabled = false;
+    // Captured here rather than threaded in from `run_default`, since `OPCODE_STATS`
+    // only needs uptime relative to the relay loop itself, not wall-clock session
+    // start (already reported separately via `--summary`/`history.rs`).
+    let loop_started_at_ms = clock.monotonic_ms();
+    // `cpu.stat` is only worth re-reading this often; anything tighter just adds
+    // wakeups to an otherwise idle session for no real gain in kill latency.
+    const CPU_BUDGET_CHECK_INTERVAL_MS: i64 = 250;
+    let mut cpu_budget_deadline = cpu_budget.as_ref().map(|_| clock.monotonic_ms() + CPU_BUDGET_CHECK_INTERVAL_MS);
+    // Kept independent of `--scrollback-file`/`--tee-file` (both opt-in) so a crash
+    // event always has *something* to show, even on a session that enabled neither.
+    let mut recent_output: std::collections::VecDeque<u8> = std::collections::VecDeque::with_capacity(crash::RECENT_OUTPUT_CAP);
+    loop {
+        let stdin_fd = if *stdin_open { libc::STDIN_FILENO } else { -1 };
+        // Only read more master output once stdout has drained enough to take it;
+        // otherwise a backlogged consumer would let the buffer grow unbounded.
+        let flow_control_blocked = flow_control_window.is_some_and(|window| bytes_unacked >= window);
+        let master_events = if master_eof || output_paused || flow_control_blocked || stdout_buffer.depth() >= OutputBuffer::DEFAULT_HIGH_WATERMARK {
+            0
+        } else {
+            libc::POLLIN
+        };
         let mut pfds = [
             libc::pollfd {
                 fd: stdin_fd,
@@ -194,35 +1629,399 @@ fn run() -> i32 {
                 revents: 0,
             },
             libc::pollfd {
-                fd: master_fd,
+                fd: if master_eof { -1 } else { master_fd },
+                events: master_events,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: stderr_pipe_read.unwrap_or(-1),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: pidfd.unwrap_or(-1),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: if stdout_buffer.depth() > 0 { libc::STDOUT_FILENO } else { -1 },
+                events: libc::POLLOUT,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: exec_tracer.map(ExecTracer::fd).unwrap_or(-1),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: auditor.map(SyscallAuditor::fd).unwrap_or(-1),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: control_fd.unwrap_or(-1),
                 events: libc::POLLIN,
                 revents: 0,
             },
         ];
 
-        let poll_rc = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as _, 100) };
+        // Without a pidfd we fall back to periodic WNOHANG polling on pre-5.3 kernels.
+        // An audited child has neither: its exit arrives over the auditor's own fd.
+        let mut poll_timeout = if pidfd.is_some() || auditor.is_some() { -1 } else { 100 };
+        // A pending debounced resize needs its own wakeup even if nothing else is
+        // ready, so the poll doesn't block straight past its deadline.
+        if let Some(deadline) = resize_deadline {
+            let remaining_ms = (deadline - clock.monotonic_ms()).clamp(0, i32::MAX as i64) as i32;
+            poll_timeout = if poll_timeout < 0 { remaining_ms } else { poll_timeout.min(remaining_ms) };
+        }
+        if let Some(deadline) = cpu_budget_deadline {
+            let remaining_ms = (deadline - clock.monotonic_ms()).clamp(0, i32::MAX as i64) as i32;
+            poll_timeout = if poll_timeout < 0 { remaining_ms } else { poll_timeout.min(remaining_ms) };
+        }
+        // A pending graceful close needs its own wakeup so the `SIGKILL` escalation
+        // fires on schedule even if the child produces no output in the meantime.
+        if let Some(deadline) = close_deadline {
+            let remaining_ms = (deadline - clock.monotonic_ms()).clamp(0, i32::MAX as i64) as i32;
+            poll_timeout = if poll_timeout < 0 { remaining_ms } else { poll_timeout.min(remaining_ms) };
+        }
+        // The idle-timeout deadline also needs its own wakeup, so a session with a
+        // silent client still notices the deadline has passed instead of blocking in
+        // `poll` past it waiting on activity that isn't coming.
+        if let Some(timeout_ms) = idle_timeout_ms {
+            let deadline = last_activity_ms + timeout_ms;
+            let remaining_ms = (deadline - clock.monotonic_ms()).clamp(0, i32::MAX as i64) as i32;
+            poll_timeout = if poll_timeout < 0 { remaining_ms } else { poll_timeout.min(remaining_ms) };
+        }
+        let poll_rc = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as _, poll_timeout) };
+        idle_stats.record_wakeup();
         if poll_rc < 0 {
-            if errno_code() == Some(libc::EINTR) {
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
                 continue;
             }
+            emit_error_frame(stdout_buffer, "io", raw_output);
             unsafe { libc::close(master_fd) };
-            return 1;
+            return EXIT_IO_FAILED;
         }
 
-        if stdin_open && (pfds[0].revents & libc::POLLIN) != 0 {
+        if let Some(deadline) = resize_deadline {
+            if clock.monotonic_ms() >= deadline {
+                if let Some((cols, rows, xpixel, ypixel)) = pending_resize.take() {
+                    let _ = apply_resize(master_fd, pid, cols, rows, xpixel, ypixel);
+                }
+                resize_deadline = None;
+            }
+        }
+
+        if let Some(deadline) = cpu_budget_deadline {
+            if clock.monotonic_ms() >= deadline {
+                if let Some(budget) = cpu_budget.as_deref_mut() {
+                    match budget.poll() {
+                        Some(CpuBudgetEvent::Warning { usage_ms, budget_ms }) => {
+                            emit_cpu_budget_warning(session, usage_ms, budget_ms)
+                        }
+                        Some(CpuBudgetEvent::Exceeded { usage_ms, budget_ms }) => {
+                            emit_cpu_budget_exceeded(session, usage_ms, budget_ms);
+                            *cpu_budget_exceeded = true;
+                            signal_child(pid, libc::SIGKILL);
+                        }
+                        None => {}
+                    }
+                }
+                cpu_budget_deadline = Some(clock.monotonic_ms() + CPU_BUDGET_CHECK_INTERVAL_MS);
+            }
+        }
+
+        if let Some(deadline) = close_deadline {
+            if clock.monotonic_ms() >= deadline {
+                close_deadline = None;
+                emit_close_escalated(session);
+                signal_child(pid, libc::SIGKILL);
+            }
+        }
+
+        if let Some(timeout_ms) = idle_timeout_ms {
+            if !idle_timeout_signaled && clock.monotonic_ms() - last_activity_ms >= timeout_ms {
+                idle_timeout_signaled = true;
+                *idle_timeout_hit = true;
+                emit_idle_timeout(session, timeout_ms);
+                // Same `SIGTERM`-then-`SIGKILL` escalation as `OPCODE_CLOSE_GRACEFUL`
+                // (see there), rather than a bare `SIGHUP`, so a child that ignores or
+                // ignores-then-traps `SIGHUP` still can't outlive an idle session —
+                // the whole point of this timeout for a CI runner that must never leak
+                // an interactive shell.
+                signal_child(pid, libc::SIGTERM);
+                close_deadline = Some(clock.monotonic_ms() + DEFAULT_CLOSE_GRACE_MS);
+            }
+        }
+
+        // With an auditor attached, its supervisor thread is the pid's sole waiter;
+        // neither the WNOHANG fallback nor the pidfd branch below may call `waitpid`
+        // on it (see `audit.rs`), so exit detection comes from its own fd instead.
+        if let Some(auditor) = auditor {
+            if (pfds[6].revents & libc::POLLIN) != 0 {
+                if let Some(code) = auditor.read_exit_code() {
+                    emit_exit_status_frame(stdout_buffer, &crash::exit_status_from_code(code), raw_output);
+                    unsafe { libc::close(master_fd) };
+                    return code;
+                }
+            }
+        } else if pidfd.is_none() {
+            if let Some((code, status, crashed)) = crash::try_reap(pid) {
+                if let Some(info) = crashed {
+                    emit_crash(session, recent_output.make_contiguous(), &info);
+                }
+                emit_exit_status_frame(stdout_buffer, &status, raw_output);
+                unsafe { libc::close(master_fd) };
+                return code;
+            }
+        } else if (pfds[3].revents & libc::POLLIN) != 0 {
+            let (code, status, crashed) = crash::reap(pid);
+            if let Some(info) = crashed {
+                emit_crash(session, recent_output.make_contiguous(), &info);
+            }
+            emit_exit_status_frame(stdout_buffer, &status, raw_output);
+            unsafe { libc::close(master_fd) };
+            if let Some(fd) = pidfd {
+                unsafe { libc::close(fd) };
+            }
+            return code;
+        }
+
+        if *stdin_open && (pfds[0].revents & libc::POLLIN) != 0 {
             let n = unsafe { libc::read(libc::STDIN_FILENO, io_buf.as_mut_ptr().cast(), io_buf.len()) };
             if n == 0 {
-                stdin_open = false;
+                *stdin_open = false;
+                emit_input_half_closed(session);
             } else if n < 0 {
-                if errno_code() != Some(libc::EINTR) {
-                    stdin_open = false;
+                if std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                    *stdin_open = false;
+                }
+            } else if control_fd.is_some() {
+                // With a dedicated control fd, stdin carries nothing but raw child
+                // input — every control message that would otherwise ride an
+                // `OPCODE_*` frame arrives on `control_fd` instead (see below), so
+                // there's no framing left on this stream for `parse_and_apply_frames`
+                // to parse.
+                let n_usize = n as usize;
+                summary.record_input(n_usize);
+                last_activity_ms = clock.monotonic_ms();
+                if write_all_fd(master_fd, &io_buf[..n_usize]).is_err() {
+                    emit_error_frame(stdout_buffer, "io", raw_output);
+                    unsafe { libc::close(master_fd) };
+                    return EXIT_IO_FAILED;
                 }
             } else {
                 let n_usize = n as usize;
-                incoming.extend_from_slice(&io_buf[..n_usize]);
-                if parse_and_apply_frames(&mut incoming, master_fd, pid).is_err() {
+                summary.record_input(n_usize);
+                last_activity_ms = clock.monotonic_ms();
+                // When there's no carried-over partial frame, parse straight out of the
+                // read buffer and skip the copy into `incoming` entirely; only a
+                // trailing partial frame (if any) needs to be buffered.
+                let parse_result = if incoming.is_empty() {
+                    let parsed = if strict_protocol {
+                        parse_frames_from_slice_strict(
+                            &io_buf[..n_usize],
+                            master_fd,
+                            pid,
+                            frame_events,
+                            input_transcoder.as_deref_mut(),
+                            resize_bounds,
+                            rate_limiter.as_deref_mut(),
+                            input_tee.as_deref_mut(),
+                            strict_naks,
+                        )
+                    } else {
+                        parse_frames_from_slice(
+                            &io_buf[..n_usize],
+                            master_fd,
+                            pid,
+                            frame_events,
+                            input_transcoder.as_deref_mut(),
+                            resize_bounds,
+                            rate_limiter.as_deref_mut(),
+                            input_tee.as_deref_mut(),
+                        )
+                    };
+                    match parsed {
+                        Ok(consumed) => {
+                            if consumed < n_usize {
+                                incoming.extend_from_slice(&io_buf[consumed..n_usize]);
+                            }
+                            Ok(())
+                        }
+                        Err(()) => Err(()),
+                    }
+                } else {
+                    incoming.extend_from_slice(&io_buf[..n_usize]);
+                    if strict_protocol {
+                        parse_and_apply_frames_strict(
+                            incoming,
+                            master_fd,
+                            pid,
+                            frame_events,
+                            input_transcoder.as_deref_mut(),
+                            resize_bounds,
+                            rate_limiter.as_deref_mut(),
+                            input_tee.as_deref_mut(),
+                            strict_naks,
+                        )
+                    } else {
+                        parse_and_apply_frames(
+                            incoming,
+                            master_fd,
+                            pid,
+                            frame_events,
+                            input_transcoder.as_deref_mut(),
+                            resize_bounds,
+                            rate_limiter.as_deref_mut(),
+                            input_tee.as_deref_mut(),
+                        )
+                    }
+                };
+                if parse_result.is_err() {
+                    emit_error_frame(stdout_buffer, "io", raw_output);
                     unsafe { libc::close(master_fd) };
-                    return 1;
+                    return EXIT_IO_FAILED;
+                }
+                if !strict_naks.is_empty() {
+                    for opcode in strict_naks.drain(..) {
+                        stdout_buffer.push(&protocol::frame_output_nak(opcode));
+                    }
+                    let _ = stdout_buffer.flush_blocking(libc::STDOUT_FILENO);
+                }
+                for event in frame_events.drain(..) {
+                    summary.record_frame_in();
+                    match event {
+                        FrameEvent::QueryCommand { index } => lifecycle.query_command(index),
+                        FrameEvent::TaggedInput { correlation_id } => lifecycle.tag_next_command(correlation_id),
+                        FrameEvent::QueryState => lifecycle.query_state(pid),
+                        FrameEvent::Ping { client_ts } => emit_pong(session, client_ts),
+                        FrameEvent::SearchScrollback { correlation_id, pattern } => {
+                            emit_search_results(session, scrollback.as_deref(), correlation_id, &pattern)
+                        }
+                        FrameEvent::CaptureScrollback { correlation_id, format } => {
+                            emit_capture(session, scrollback.as_deref(), correlation_id, format)
+                        }
+                        FrameEvent::ResizeClamped { requested_cols, requested_rows, applied_cols, applied_rows } => {
+                            emit_resize_clamped(session, requested_cols, requested_rows, applied_cols, applied_rows)
+                        }
+                        FrameEvent::Resize { cols, rows, xpixel, ypixel } => {
+                            pending_resize = Some((cols, rows, xpixel, ypixel));
+                            resize_deadline = Some(clock.monotonic_ms() + resize_debounce_ms);
+                        }
+                        FrameEvent::InputThrottled { dropped_bytes } => emit_input_throttled(session, dropped_bytes),
+                        FrameEvent::InputTeeToggled { enabled } => emit_input_tee_toggled(session, enabled),
+                        FrameEvent::InputHalfClosed => {
+                            *stdin_open = false;
+                            emit_input_half_closed(session);
+                        }
+                        FrameEvent::SignalSent { signal } => emit_signal_sent(session, signal),
+                        FrameEvent::Hello { client_version, client_capabilities } => {
+                            let accepted = client_version <= protocol::PROTOCOL_VERSION;
+                            emit_hello_received(session, client_version, client_capabilities, accepted);
+                            if !accepted {
+                                // A client requiring a newer protocol might shape its
+                                // frames in ways this build can't parse correctly; tear
+                                // the session down rather than risk silently
+                                // misinterpreting its bytes as some other opcode.
+                                *protocol_incompatible = true;
+                                signal_child(pid, libc::SIGHUP);
+                            } else if !raw_output && (client_capabilities & protocol::CAP_SEQUENCED_OUTPUT) != 0 {
+                                // Meaningless under `--raw-output` (no framing at all),
+                                // so only honored when the session is framed to begin
+                                // with — same gate `CAP_OUTPUT_FRAMING` itself implies.
+                                sequenced_output = true;
+                            } else if !raw_output && (client_capabilities & protocol::CAP_TIMESTAMPED_OUTPUT) != 0 {
+                                timestamped_output = true;
+                            }
+                            if !raw_output && (client_capabilities & protocol::CAP_COMPRESSION) != 0 {
+                                // Not part of the `else if` chain above: compression
+                                // takes priority over sequencing/timestamping on a
+                                // per-chunk basis, only for chunks that actually clear
+                                // `compress_min_bytes` (see `relay_master_chunk`).
+                                compression_enabled = true;
+                            }
+                        }
+                        FrameEvent::OutputPauseRequested => {
+                            output_paused = true;
+                            emit_output_pause(session, true);
+                        }
+                        FrameEvent::OutputResumeRequested => {
+                            output_paused = false;
+                            emit_output_pause(session, false);
+                        }
+                        FrameEvent::FlowControlEnabled { window_bytes } => {
+                            flow_control_window = Some(window_bytes);
+                            bytes_unacked = 0;
+                            emit_flow_control_enabled(session, window_bytes);
+                        }
+                        FrameEvent::Ack { acked_bytes } => {
+                            bytes_unacked = bytes_unacked.saturating_sub(acked_bytes);
+                        }
+                        FrameEvent::QueryWinsize => emit_winsize(session, master_fd),
+                        FrameEvent::QueryInfo => emit_child_info(session, pid, master_fd),
+                        FrameEvent::Eof => emit_eof_sent(session, send_veof(master_fd).is_ok()),
+                        FrameEvent::Flush { correlation_id } => {
+                            drain_master_for_flush(
+                                master_fd,
+                                pid,
+                                io_buf,
+                                session,
+                                lifecycle,
+                                bracketed_paste,
+                                clipboard_policy,
+                                tee_file,
+                                scrollback.as_deref_mut(),
+                                clock,
+                                &mut recent_output,
+                                stdout_buffer,
+                                raw_output,
+                                sequenced_output,
+                                &mut next_output_seq,
+                                timestamped_output,
+                                compression_enabled,
+                                compress_min_bytes,
+                                flow_control_window,
+                                &mut bytes_unacked,
+                                summary,
+                                output_transcoder.as_deref_mut(),
+                            );
+                            stdout_buffer.push(&protocol::frame_output_flush_ack(correlation_id));
+                            let _ = stdout_buffer.flush_blocking(libc::STDOUT_FILENO);
+                        }
+                        FrameEvent::FrameCrcMismatch { expected, computed, length } => {
+                            emit_frame_crc_mismatch(session, expected, computed, length)
+                        }
+                        FrameEvent::StatsRequested => {
+                            emit_stats(session, summary, clock.monotonic_ms() - loop_started_at_ms, master_eof)
+                        }
+                        FrameEvent::UnknownOpcode { opcode } => summary.record_dropped_opcode(opcode),
+                        FrameEvent::DecompressionFailed { length } => emit_decompression_failed(session, length),
+                        FrameEvent::CloseGraceful { grace_ms } => {
+                            let grace_ms = if grace_ms == 0 { DEFAULT_CLOSE_GRACE_MS } else { grace_ms as i64 };
+                            emit_close_graceful(session, grace_ms);
+                            signal_child(pid, libc::SIGTERM);
+                            close_deadline = Some(clock.monotonic_ms() + grace_ms);
+                        }
+                        FrameEvent::IdleTimeoutSet { idle_timeout_ms: new_timeout_ms } => {
+                            idle_timeout_ms = if new_timeout_ms == 0 { None } else { Some(new_timeout_ms as i64) };
+                            idle_timeout_signaled = false;
+                            last_activity_ms = clock.monotonic_ms();
+                            emit_idle_timeout_set(session, idle_timeout_ms);
+                        }
+                        FrameEvent::SetTermios { mask, value } => {
+                            let applied = set_termios_flags(master_fd, mask, value).is_ok();
+                            emit_termios_set(session, mask, value, applied);
+                        }
+                        FrameEvent::QueryTermios => emit_termios(session, master_fd),
+                        FrameEvent::Paste { payload } => {
+                            let wire_bytes = frame_for_paste(&payload, bracketed_paste.enabled());
+                            let _ = write_all_fd(master_fd, &wire_bytes);
+                        }
+                        FrameEvent::ReplayRequested => {
+                            emit_replay(session, scrollback.as_deref(), stdout_buffer, raw_output);
+                        }
+                    }
                 }
             }
         }
@@ -230,24 +2029,157 @@ fn run() -> i32 {
         if (pfds[1].revents & libc::POLLIN) != 0 {
             let n = unsafe { libc::read(master_fd, io_buf.as_mut_ptr().cast(), io_buf.len()) };
             if n == 0 {
-                let mut status2: c_int = 0;
-                let _ = unsafe { libc::waitpid(pid, &mut status2, 0) };
+                if auditor.is_some() {
+                    // Don't reap here — the auditor's supervisor thread is the sole
+                    // owner of `waitpid` for this pid; its own fd reports the real
+                    // exit on a later iteration.
+                    master_eof = true;
+                    continue;
+                }
+                let (code, status, crashed) = crash::reap(pid);
+                if let Some(info) = crashed {
+                    emit_crash(session, recent_output.make_contiguous(), &info);
+                }
+                emit_exit_status_frame(stdout_buffer, &status, raw_output);
                 unsafe { libc::close(master_fd) };
-                return child_exit_code(status2);
+                return code;
             }
             if n < 0 {
-                if errno_code() == Some(libc::EINTR) {
+                if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
                     continue;
                 }
+                emit_error_frame(stdout_buffer, "io", raw_output);
                 unsafe { libc::close(master_fd) };
-                return 1;
+                return EXIT_IO_FAILED;
             }
+            let read_at_ms = clock.monotonic_ms();
+            last_activity_ms = read_at_ms;
             let n_usize = n as usize;
-            if write_all_fd(libc::STDOUT_FILENO, &io_buf[..n_usize]).is_err() {
+            let decoded = output_transcoder.as_deref_mut().map(|t| t.decode(&io_buf[..n_usize]));
+            let output_bytes: &[u8] = decoded.as_deref().map(str::as_bytes).unwrap_or(&io_buf[..n_usize]);
+            relay_master_chunk(
+                output_bytes,
+                session,
+                lifecycle,
+                bracketed_paste,
+                clipboard_policy,
+                pid,
+                master_fd,
+                tee_file,
+                scrollback.as_deref_mut(),
+                clock,
+                &mut recent_output,
+                stdout_buffer,
+                raw_output,
+                sequenced_output,
+                &mut next_output_seq,
+                timestamped_output,
+                read_at_ms,
+                compression_enabled,
+                compress_min_bytes,
+                flow_control_window,
+                &mut bytes_unacked,
+                summary,
+            );
+        }
+
+        if (pfds[4].revents & libc::POLLOUT) != 0 {
+            let (result, watermark) = stdout_buffer.flush_nonblocking(libc::STDOUT_FILENO);
+            if result.is_err() {
+                emit_error_frame(stdout_buffer, "io", raw_output);
                 unsafe { libc::close(master_fd) };
-                return 1;
+                return EXIT_IO_FAILED;
+            }
+            if let Some(event) = watermark {
+                emit_watermark(session, &event);
+            }
+        }
+
+        if (stderr_relay_fd.is_some() || stderr_framed) && (pfds[2].revents & libc::POLLIN) != 0 {
+            if let Some(read_fd) = stderr_pipe_read {
+                let n = unsafe { libc::read(read_fd, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+                if n > 0 {
+                    let chunk = &io_buf[..n as usize];
+                    if let Some(stderr_fd) = stderr_relay_fd {
+                        let _ = write_all_fd(stderr_fd, chunk);
+                    }
+                    if stderr_framed && !raw_output {
+                        if let Some(event) = stdout_buffer.push(&protocol::frame_output_stderr_data(chunk)) {
+                            emit_watermark(session, &event);
+                        }
+                    }
+                }
             }
         }
+
+        if let Some(tracer) = exec_tracer {
+            if (pfds[5].revents & libc::POLLIN) != 0 {
+                for event in tracer.poll_events() {
+                    emit_exec_trace(session, &event);
+                }
+            }
+        }
+
+        if let Some(fd) = control_fd {
+            if (pfds[7].revents & libc::POLLIN) != 0 {
+                let n = unsafe { libc::read(fd, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+                if n > 0 {
+                    control_incoming.extend_from_slice(&io_buf[..n as usize]);
+                    for message in parse_control_lines(control_incoming) {
+                        match message {
+                            ControlMessage::Resize { cols, rows } => {
+                                let (applied_cols, applied_rows, clamped) = resize_bounds.clamp(cols, rows);
+                                if clamped {
+                                    emit_resize_clamped(session, cols, rows, applied_cols, applied_rows);
+                                }
+                                pending_resize = Some((applied_cols, applied_rows, 0, 0));
+                                resize_deadline = Some(clock.monotonic_ms() + resize_debounce_ms);
+                            }
+                            ControlMessage::Signal { signal } => {
+                                signal_child(pid, signal);
+                                emit_signal_sent(session, signal);
+                            }
+                            ControlMessage::Close => {
+                                signal_child(pid, libc::SIGHUP);
+                            }
+                            ControlMessage::CloseGraceful { grace_ms } => {
+                                let grace_ms = grace_ms.map(|ms| ms as i64).unwrap_or(DEFAULT_CLOSE_GRACE_MS);
+                                emit_close_graceful(session, grace_ms);
+                                signal_child(pid, libc::SIGTERM);
+                                close_deadline = Some(clock.monotonic_ms() + grace_ms);
+                            }
+                            ControlMessage::QueryWinsize => emit_winsize(session, master_fd),
+                            ControlMessage::QueryInfo => emit_child_info(session, pid, master_fd),
+                            ControlMessage::QueryState => lifecycle.query_state(pid),
+                            ControlMessage::Stats => {
+                                emit_stats(session, summary, clock.monotonic_ms() - loop_started_at_ms, master_eof)
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn run() -> i32 {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        return 2;
+    }
+
+    match args[0].as_str() {
+        "watch" => watch::run(&args[1..]),
+        "generate" => generate::run(&args[1..]),
+        "history" => {
+            history::print_recent(20);
+            0
+        }
+        "export" => export::run(&args[1..]),
+        "ls" => ls::run(&args[1..]),
+        "multiplex" => multiplex::run(&args[1..]),
+        "serve" => serve::run(&args[1..]),
+        _ => run_default(&args),
     }
 }
 
@@ -0,0 +1,143 @@
+//! `ptyd multiplex`: several ptys relayed over one stdin/stdout pair, rather than one
+//! subprocess per pty — see `OPCODE_OPEN_CHANNEL` et al.'s doc comments in
+//! `protocol.rs` for why this is a separate subcommand instead of a channel id bolted
+//! onto every existing opcode. Deliberately scoped down from the default mode's
+//! `relay_loop`: no scrollback, compression, flow control, CPU budget, idle timeout, or
+//! `--summary`/`OPCODE_STATS` telemetry per channel — just open/close/data/resize, the
+//! minimum a harness running dozens of terminals actually needs to drop one subprocess
+//! per terminal. A caller that needs any of those richer features for a given terminal
+//! can still run it under the default single-pty mode instead.
+
+use std::collections::HashMap;
+
+use crate::crash::reap;
+use crate::protocol::{
+    apply_resize, frame_output_channel_data, frame_output_channel_error, frame_output_channel_exit, parse_channel_frames,
+    write_all_fd, ChannelEvent,
+};
+use crate::pty::{fork_and_exec, open_pty, signal_child};
+
+struct Channel {
+    master_fd: libc::c_int,
+    pid: libc::pid_t,
+}
+
+/// Writes `frame` to stdout, closing over the one error behavior every output path in
+/// this module shares: a write failure means the host end is gone, so there's nothing
+/// left to do but give up.
+fn emit(frame: &[u8]) -> Result<(), ()> {
+    write_all_fd(libc::STDOUT_FILENO, frame)
+}
+
+fn open_channel(channels: &mut HashMap<u32, Channel>, channel_id: u32, argv: Vec<String>) -> Result<(), ()> {
+    if channels.contains_key(&channel_id) {
+        return emit(&frame_output_channel_error(channel_id, "channel id already open"));
+    }
+    let Some(pair) = open_pty() else {
+        return emit(&frame_output_channel_error(channel_id, "failed to allocate pty"));
+    };
+    let Some(pid) = fork_and_exec(&argv, pair.master_fd, pair.slave_fd) else {
+        unsafe { libc::close(pair.master_fd) };
+        return emit(&frame_output_channel_error(channel_id, "failed to fork/exec"));
+    };
+    channels.insert(channel_id, Channel { master_fd: pair.master_fd, pid });
+    Ok(())
+}
+
+fn close_channel(channels: &mut HashMap<u32, Channel>, channel_id: u32) {
+    if let Some(channel) = channels.get(&channel_id) {
+        signal_child(channel.pid, libc::SIGHUP);
+    }
+}
+
+/// A channel's master fd hit EOF: reaps its child, reports `OUTPUT_OPCODE_CHANNEL_EXIT`,
+/// and frees the id for a future `OPCODE_OPEN_CHANNEL` to reuse.
+fn reap_channel(channels: &mut HashMap<u32, Channel>, channel_id: u32) -> Result<(), ()> {
+    let Some(channel) = channels.remove(&channel_id) else {
+        return Ok(());
+    };
+    unsafe { libc::close(channel.master_fd) };
+    let (_code, status, _crash) = reap(channel.pid);
+    emit(&frame_output_channel_exit(channel_id, status.exited, status.signal, status.core_dumped))
+}
+
+fn dispatch(channels: &mut HashMap<u32, Channel>, event: ChannelEvent) -> Result<(), ()> {
+    match event {
+        ChannelEvent::Open { channel_id, argv } => open_channel(channels, channel_id, argv),
+        ChannelEvent::Close { channel_id } => {
+            close_channel(channels, channel_id);
+            Ok(())
+        }
+        ChannelEvent::Data { channel_id, payload } => {
+            if let Some(channel) = channels.get(&channel_id) {
+                let _ = write_all_fd(channel.master_fd, &payload);
+            }
+            Ok(())
+        }
+        ChannelEvent::Resize { channel_id, cols, rows } => {
+            if let Some(channel) = channels.get(&channel_id) {
+                let _ = apply_resize(channel.master_fd, channel.pid, cols, rows, 0, 0);
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn run(_args: &[String]) -> i32 {
+    let mut channels: HashMap<u32, Channel> = HashMap::new();
+    let mut incoming: Vec<u8> = Vec::with_capacity(8192);
+    let mut io_buf = vec![0_u8; 65_536];
+    let mut stdin_open = true;
+
+    loop {
+        if !stdin_open && channels.is_empty() {
+            return 0;
+        }
+
+        let stdin_fd = if stdin_open { libc::STDIN_FILENO } else { -1 };
+        let mut pfds = Vec::with_capacity(1 + channels.len());
+        pfds.push(libc::pollfd { fd: stdin_fd, events: libc::POLLIN, revents: 0 });
+        let channel_ids: Vec<u32> = channels.keys().copied().collect();
+        for &channel_id in &channel_ids {
+            let master_fd = channels[&channel_id].master_fd;
+            pfds.push(libc::pollfd { fd: master_fd, events: libc::POLLIN, revents: 0 });
+        }
+
+        let poll_rc = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as _, -1) };
+        if poll_rc < 0 {
+            continue;
+        }
+
+        if stdin_open && (pfds[0].revents & libc::POLLIN) != 0 {
+            let n = unsafe { libc::read(libc::STDIN_FILENO, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+            if n == 0 {
+                stdin_open = false;
+            } else if n > 0 {
+                incoming.extend_from_slice(&io_buf[..n as usize]);
+                for event in parse_channel_frames(&mut incoming) {
+                    if dispatch(&mut channels, event).is_err() {
+                        return 1;
+                    }
+                }
+            }
+        }
+
+        for (idx, &channel_id) in channel_ids.iter().enumerate() {
+            if (pfds[idx + 1].revents & libc::POLLIN) == 0 {
+                continue;
+            }
+            let master_fd = match channels.get(&channel_id) {
+                Some(channel) => channel.master_fd,
+                None => continue,
+            };
+            let n = unsafe { libc::read(master_fd, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+            if n == 0 {
+                if reap_channel(&mut channels, channel_id).is_err() {
+                    return 1;
+                }
+            } else if n > 0 && emit(&frame_output_channel_data(channel_id, &io_buf[..n as usize])).is_err() {
+                return 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Bounded pending-output buffer for the pty-to-stdout direction, with high/low
+//! watermark crossing events so a client can see "output backlog building" and
+//! throttle the child before the buffer grows without bound.
+
+use std::collections::VecDeque;
+
+use crate::protocol::write_all_fd;
+
+pub struct OutputBuffer {
+    pending: VecDeque<u8>,
+    high_watermark: usize,
+    low_watermark: usize,
+    above_high: bool,
+}
+
+pub enum WatermarkEvent {
+    High { depth: usize },
+    Low { depth: usize },
+}
+
+impl OutputBuffer {
+    pub const DEFAULT_HIGH_WATERMARK: usize = 256 * 1024;
+    pub const DEFAULT_LOW_WATERMARK: usize = 64 * 1024;
+
+    pub fn new(high_watermark: usize, low_watermark: usize) -> Self {
+        OutputBuffer {
+            pending: VecDeque::new(),
+            high_watermark,
+            low_watermark,
+            above_high: false,
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Option<WatermarkEvent> {
+        self.pending.extend(bytes.iter().copied());
+        if !self.above_high && self.pending.len() >= self.high_watermark {
+            self.above_high = true;
+            return Some(WatermarkEvent::High { depth: self.pending.len() });
+        }
+        None
+    }
+
+    pub fn depth(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Writes as much pending output as `fd` accepts without blocking. Returns a
+    /// low-watermark event if the buffer just drained back under the low mark.
+    /// Writes straight out of the deque's contiguous slice so steady-state flushing
+    /// allocates nothing on the relay hot path.
+    pub fn flush_nonblocking(&mut self, fd: libc::c_int) -> (Result<(), ()>, Option<WatermarkEvent>) {
+        while !self.pending.is_empty() {
+            let slice = self.pending.make_contiguous();
+            let n = unsafe { libc::write(fd, slice.as_ptr().cast(), slice.len()) };
+            if n < 0 {
+                let err = std::io::Error::last_os_error().raw_os_error();
+                if err == Some(libc::EAGAIN) || err == Some(libc::EWOULDBLOCK) {
+                    break;
+                }
+                if err == Some(libc::EINTR) {
+                    continue;
+                }
+                return (Err(()), None);
+            }
+            self.pending.drain(0..n as usize);
+        }
+
+        if self.above_high && self.pending.len() <= self.low_watermark {
+            self.above_high = false;
+            return (Ok(()), Some(WatermarkEvent::Low { depth: self.pending.len() }));
+        }
+        (Ok(()), None)
+    }
+
+    /// Blocking drain used at shutdown/flush points where backpressure should not
+    /// drop data.
+    pub fn flush_blocking(&mut self, fd: libc::c_int) -> Result<(), ()> {
+        let slice = self.pending.make_contiguous();
+        let result = write_all_fd(fd, slice);
+        self.pending.clear();
+        result
+    }
+}
@@ -0,0 +1,106 @@
+//! `--state-file PATH` for `ptyd serve`: a periodic, best-effort snapshot of session
+//! metadata so a daemon that's being upgraded or that crashes leaves behind more than
+//! nothing. This is honestly metadata-only — a pty master fd can't be reopened by path
+//! once the process that held it is gone, so there's no way to resurrect an actual
+//! terminal from this file. What it's for is forensics: on startup, `ptyd serve`
+//! reports what was running according to the last snapshot, so an operator (or a
+//! wrapper script) can see which commands need to be relaunched instead of discovering
+//! the gap by accident.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SessionSnapshot {
+    pub(crate) id: String,
+    pub(crate) name: Option<String>,
+    pub(crate) argv: Vec<String>,
+    pub(crate) pid: i32,
+}
+
+/// Overwrites `path` with `snapshot`, as a JSON array, via a write-then-rename so a
+/// reader never observes a half-written file. Called from a background thread on a
+/// fixed interval (see `serve::run`'s `--state-file` wiring) rather than on every
+/// registry mutation, the same tradeoff `history.rs`'s sqlite writes make: a snapshot
+/// a few seconds stale is fine for a forensic aid, and a daemon this busy has bigger
+/// problems than a missed write.
+pub(crate) fn write(path: &str, snapshot: &[SessionSnapshot]) {
+    let Ok(json) = serde_json::to_vec(snapshot) else { return };
+    let tmp_path = format!("{path}.tmp");
+    if std::fs::write(&tmp_path, json).is_ok() {
+        let _ = std::fs::rename(&tmp_path, path);
+    }
+}
+
+/// Reads back a prior `write`, if `path` exists and parses. Returns `None` (silently —
+/// logging is the caller's job) on a missing file, the common case of a daemon's
+/// first-ever start, or a malformed one, since a forensic aid that can't be trusted
+/// shouldn't be trusted.
+fn read(path: &str) -> Option<Vec<SessionSnapshot>> {
+    let contents = std::fs::read(path).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Logs what the last `--state-file` snapshot says was running before this process
+/// started, for an operator to notice and relaunch if they care to — nothing here
+/// touches the live session registry, since there's no fd left to attach it to.
+pub(crate) fn report_previous_sessions(path: &str) {
+    let Some(previous) = read(path) else { return };
+    if previous.is_empty() {
+        return;
+    }
+    eprintln!("ptyd serve: {} session(s) from a previous run were not carried over:", previous.len());
+    for session in &previous {
+        let label = session.name.as_deref().unwrap_or(&session.id);
+        eprintln!("  {label} (pid {}): {}", session.pid, session.argv.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("ptyd-persist-test-{name}-{}", std::process::id())).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn write_then_read_round_trips_a_snapshot() {
+        let path = temp_path("roundtrip");
+        let snapshot = vec![
+            SessionSnapshot { id: "a".to_string(), name: Some("build".to_string()), argv: vec!["cargo".to_string(), "build".to_string()], pid: 123 },
+            SessionSnapshot { id: "b".to_string(), name: None, argv: vec!["bash".to_string()], pid: 456 },
+        ];
+
+        write(&path, &snapshot);
+        let read_back = read(&path).unwrap();
+
+        assert_eq!(read_back.len(), 2);
+        assert_eq!(read_back[0].id, "a");
+        assert_eq!(read_back[0].name, Some("build".to_string()));
+        assert_eq!(read_back[1].pid, 456);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn read_returns_none_for_a_missing_file() {
+        let path = temp_path("missing");
+        assert!(read(&path).is_none());
+    }
+
+    #[test]
+    fn read_returns_none_for_a_malformed_file() {
+        let path = temp_path("malformed");
+        std::fs::write(&path, b"not json").unwrap();
+        assert!(read(&path).is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn write_leaves_no_tmp_file_behind_after_the_rename() {
+        let path = temp_path("no-tmp-leftover");
+        write(&path, &[]);
+        assert!(!std::path::Path::new(&format!("{path}.tmp")).exists());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,1600 @@
+use libc::{c_int, pid_t};
+use std::mem;
+
+use crate::compression;
+use crate::crc32;
+use crate::input_tee::InputTee;
+use crate::pty::signal_child;
+use crate::throttle::InputRateLimiter;
+use crate::transcode::InputTranscoder;
+
+/// Bumped whenever an opcode's wire shape changes incompatibly (new opcodes appended
+/// to the end, like `OPCODE_TOGGLE_INPUT_TEE` below, don't need a bump — a client only
+/// cares about this if it ever *removes* or *reshapes* one). Reported in `--status-fd`
+/// startup info (see `main.rs`) so a wrapper can refuse to talk to a `ptyd` build it
+/// doesn't understand instead of misparsing frames.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const OPCODE_DATA: u8 = 0x01;
+pub const OPCODE_RESIZE: u8 = 0x02;
+pub const OPCODE_CLOSE: u8 = 0x03;
+pub const OPCODE_QUERY_COMMAND: u8 = 0x04;
+pub const OPCODE_DATA_TAGGED: u8 = 0x05;
+pub const OPCODE_QUERY_STATE: u8 = 0x06;
+/// Marks the start of a multi-frame input payload; carries no body of its own.
+pub const OPCODE_DATA_BEGIN: u8 = 0x07;
+/// A chunk of a multi-frame input payload opened by `OPCODE_DATA_BEGIN`. Same wire
+/// shape as `OPCODE_DATA` (u32be length + bytes) and is relayed to the pty the moment
+/// the chunk completes, so the client never has to know the logical payload's total
+/// size up front or buffer more than one chunk at a time.
+pub const OPCODE_DATA_CONTINUE: u8 = 0x08;
+/// Marks the end of a multi-frame input payload; carries no body of its own.
+pub const OPCODE_DATA_END: u8 = 0x09;
+/// Carries a u64be client timestamp (client's own clock, opaque to ptyd) that is
+/// echoed straight back as a `pong` event so the client can measure control-channel
+/// round-trip latency without that round trip going through the pty/shell at all.
+/// Sent on a regular interval instead of just once, this doubles as a keepalive a
+/// host can use to detect a hung `ptyd` (no `pong` arriving within the expected
+/// window); `--idle-timeout-ms`/`OPCODE_SET_IDLE_TIMEOUT` (see `main.rs`) is the
+/// reverse direction, tearing the session down if neither a frame — a `PING` or
+/// otherwise — arrives from the client, nor any output crosses the master fd, for
+/// that long.
+pub const OPCODE_PING: u8 = 0x0A;
+/// Carries a u32be correlation id followed by a u32be length and a regex pattern,
+/// searched against retained scrollback server-side (see `scrollback.rs`); the result
+/// is reported back as a `scrollback-search` event carrying the same correlation id.
+pub const OPCODE_SEARCH_SCROLLBACK: u8 = 0x0B;
+/// Carries a u32be correlation id followed by one format byte (`0` plain, `1`
+/// ANSI-preserving, `2` HTML — see `capture.rs`), requesting retained scrollback be
+/// rendered in that format; the result is reported back as a `capture` event.
+pub const OPCODE_CAPTURE_SCROLLBACK: u8 = 0x0C;
+/// Carries one byte (`0` disable, nonzero enable), toggling `--input-tee-file`
+/// recording on or off without restarting the session (see `input_tee.rs`).
+pub const OPCODE_TOGGLE_INPUT_TEE: u8 = 0x0D;
+/// Carries no body. Tells the daemon the client has no more input to send, without
+/// tearing anything down: output keeps relaying and the child's exit is reported
+/// normally once it happens on its own. Distinct from plain stdin EOF (which stops
+/// input silently) in that it's reported via `FrameEvent::InputHalfClosed`, and
+/// distinct from `OPCODE_CLOSE` in that it never signals the child.
+pub const OPCODE_HALF_CLOSE_INPUT: u8 = 0x0E;
+/// Carries one byte naming a signal number (e.g. `libc::SIGINT`), delivered to the
+/// child's whole process group via `signal_child` — the same delivery `--tree` already
+/// uses for `signal_child`'s callers in `main.rs`, just reachable in-band instead of
+/// only at resize/close time. Lets the host send SIGINT/SIGTERM/SIGUSR1/etc. without
+/// tearing down the session the way `OPCODE_CLOSE`'s `SIGHUP` does.
+pub const OPCODE_SIGNAL: u8 = 0x0F;
+/// Carries a u32be protocol version and a u32be capability bitmask the client speaks,
+/// optional and only meaningful as the very first frame sent — nothing stops a client
+/// from never sending one, which is treated as "an older client that predates this
+/// opcode" rather than an error, so existing integrations keep working unchanged.
+/// `ptyd` compares `client_version` against `PROTOCOL_VERSION` and tears the session
+/// down (see `FrameEvent::Hello` in `main.rs`) rather than going on to parse frames a
+/// newer protocol might shape differently, instead of silently dropping bytes it
+/// doesn't recognize the way an unknown opcode otherwise would.
+pub const OPCODE_HELLO: u8 = 0x10;
+/// Carries no body. Stops `relay_loop` from reading the master fd at all until a
+/// matching `OPCODE_RESUME` arrives, on top of (not instead of) the watermark-based
+/// backpressure `OutputBuffer` already applies — once the pty's own kernel output
+/// buffer fills up behind that, the child blocks on its own writes, same as any
+/// program whose stdout isn't being read.
+pub const OPCODE_PAUSE: u8 = 0x11;
+/// Carries no body. Reverses an `OPCODE_PAUSE`; a no-op if output wasn't paused.
+pub const OPCODE_RESUME: u8 = 0x12;
+/// Carries a u32be window size in bytes, opting the session into credit-based flow
+/// control: once this many output bytes have been sent without a matching
+/// `OPCODE_ACK`, the master fd stops being read, the same way `OPCODE_PAUSE` does,
+/// until enough credit is returned. Off by default — plain `OPCODE_PAUSE`/`RESUME`
+/// (coarser, no byte counting) is enough for most clients; this is for transports
+/// that need to bound how much unacknowledged data is in flight. Meant to be sent
+/// right after `OPCODE_HELLO` as part of the same handshake, without reshaping
+/// `HELLO`'s own wire format for what's an opt-in, off-by-default mode.
+pub const OPCODE_ENABLE_FLOW_CONTROL: u8 = 0x13;
+/// Carries a u32be byte count, returning that much credit to a session that enabled
+/// `OPCODE_ENABLE_FLOW_CONTROL`. Ignored if flow control was never enabled.
+pub const OPCODE_ACK: u8 = 0x14;
+/// Like `OPCODE_RESIZE`, but with two extra trailing u16be fields — `ws_xpixel` and
+/// `ws_ypixel` — for clients that need pixel-accurate geometry (sixel viewers, kitty
+/// graphics) rather than just rows/cols. A new opcode rather than widening
+/// `OPCODE_RESIZE` itself, so existing clients sending the original 5-byte frame keep
+/// working unchanged.
+pub const OPCODE_RESIZE_PIXELS: u8 = 0x15;
+/// No payload. Asks ptyd to read the current `TIOCGWINSZ` back off the pty master and
+/// reply with a `winsize` event, so a host reconnecting to a session it didn't start
+/// can learn the geometry the child believes it has, rather than assuming whatever it
+/// last sent a `RESIZE` for.
+pub const OPCODE_QUERY_WINSIZE: u8 = 0x16;
+/// No payload. Asks ptyd to reply with a `child-info` event carrying the child's pid,
+/// process group, and the slave pty's `ttyname` — needed for debugging, `ptrace`
+/// attach, and `lsof`-style tooling against a session the caller didn't itself spawn.
+pub const OPCODE_QUERY_INFO: u8 = 0x17;
+/// Carries a u32be key length, the key bytes, a u32be value length, and the value
+/// bytes. Only meaningful during `--defer-exec`'s setup phase (see
+/// `wait_for_defer_exec_setup` in `main.rs`), before any child exists — ignored
+/// entirely outside it.
+pub const OPCODE_SET_ENV: u8 = 0x18;
+/// Carries a u32be path length and the path bytes. Like `OPCODE_SET_ENV`, only
+/// meaningful during `--defer-exec`'s setup phase.
+pub const OPCODE_SET_CWD: u8 = 0x19;
+/// Carries a u32be argument count, then for each argument a u32be length and its
+/// bytes. Ends `--defer-exec`'s setup phase: the child is forked and exec'd with
+/// this argv and whatever `OPCODE_SET_ENV`/`OPCODE_SET_CWD` frames arrived before it.
+/// None of this trio gets a `CAPABILITIES` bit: a client decides to speak them by passing `--defer-exec` on ptyd's own command line,
+/// not by negotiating over a `HELLO` the server can't even send yet — no child (and so
+/// no relay loop) exists until `OPCODE_EXEC` arrives.
+pub const OPCODE_EXEC: u8 = 0x1A;
+/// Carries no payload. Writes the slave's `VEOF` character to the pty master (see
+/// `pty::send_veof`), so a line-disciplined child reading from the tty (e.g. `cat`
+/// waiting on Ctrl-D) sees end-of-input without the host having to close stdin or
+/// send a signal.
+pub const OPCODE_EOF: u8 = 0x1B;
+/// Carries a u32be correlation id. Asks ptyd to read and relay everything currently
+/// sitting in the pty master's kernel read queue — not just whatever one `poll`
+/// wakeup's `read` happens to return — before replying with an
+/// `OUTPUT_OPCODE_FLUSH_ACK` carrying the same id, so a client has a byte-exact
+/// "everything produced so far has been delivered" synchronization point instead of
+/// racing the relay loop's own pacing.
+pub const OPCODE_FLUSH: u8 = 0x1C;
+/// Like `OPCODE_DATA`, but with a leading u32be CRC-32 (`crc32::checksum`, the same
+/// polynomial `zlib` uses) of the payload ahead of the usual u32be length and the
+/// bytes themselves — for a client tunneling ptyd traffic over something lossy enough
+/// that corrupted bytes reaching the pty unnoticed is a real concern (a dropped
+/// `OPCODE_DATA` frame is merely missing input; a corrupted one silently becomes
+/// different input). A frame whose checksum doesn't match is dropped rather than
+/// relayed, reported as `FrameEvent::FrameCrcMismatch` instead of reaching the child at
+/// all. A new opcode rather than widening `OPCODE_DATA` itself, so a client that hasn't
+/// negotiated this (checked `CAP_FRAME_CRC32` in the server's `HELLO`) keeps sending
+/// plain `OPCODE_DATA` frames unaffected.
+pub const OPCODE_DATA_CRC32: u8 = 0x1D;
+/// No payload. Asks ptyd to reply with a `stats` event carrying runtime telemetry —
+/// bytes and frames relayed in each direction, how many input opcodes were unknown and
+/// dropped, session uptime, and whether the child is still running — so a host can show
+/// per-session counters without reconstructing them from the rest of the event stream.
+pub const OPCODE_STATS: u8 = 0x1E;
+/// Like `OPCODE_DATA`, but the payload is a u32be decompressed length, then a u32be
+/// compressed length, then that many `zstd`-compressed bytes (see `compression.rs`) —
+/// decompressed before being relayed to the child exactly like a plain `OPCODE_DATA`
+/// payload. A client decides on its own when sending this is worth it; unlike the
+/// output direction (see `OUTPUT_OPCODE_DATA_COMPRESSED`) ptyd has no minimum-size
+/// threshold to apply here, since it isn't the one doing the compressing. Accepted
+/// whenever `CAP_COMPRESSION` is set, regardless of whether the client's own `HELLO`
+/// requested compressed *output* framing — the two directions are negotiated together
+/// but applied independently.
+pub const OPCODE_DATA_COMPRESSED: u8 = 0x1F;
+/// Carries a u32be grace period in milliseconds (`0` meaning "use ptyd's own default").
+/// Sends `SIGTERM` to the child's whole process group immediately, then — unless the
+/// child has already exited — `SIGKILL`s it once the grace period elapses, so a client
+/// that wants a clean shutdown chance doesn't have to also guarantee a dangling process
+/// can't outlive the session. Distinct from plain `OPCODE_CLOSE`'s unconditional
+/// `SIGHUP`, which a shell-like child would treat as hangup-and-exit but a child that
+/// ignores `SIGHUP` (or isn't a shell at all) might simply survive.
+pub const OPCODE_CLOSE_GRACEFUL: u8 = 0x20;
+/// Carries a u32be idle timeout in milliseconds (`0` disables it), overriding
+/// `--idle-timeout-ms` for the rest of the session — see `main.rs`'s `relay_loop`.
+/// Lets a client that only learns partway through a session that it must never leak an
+/// interactive shell (a CI runner picking one up from a pool, say) opt into the same
+/// protection `--idle-timeout-ms` gives a session configured with it from the start.
+pub const OPCODE_SET_IDLE_TIMEOUT: u8 = 0x21;
+/// Carries a mask byte and a value byte, each built from `TERMIOS_FLAG_*` bits below.
+/// For every bit set in the mask, the corresponding slave-side `termios` flag is set to
+/// whatever that same bit is in the value byte; bits clear in the mask are left alone.
+/// Applied directly via `tcsetattr` on the master fd — no `SIGWINCH`-style signal to the
+/// child, since termios changes take effect for whatever the line discipline does next,
+/// not something the child has to notice and react to. Lets a host that already renders
+/// typed input itself (so it doesn't want the pty echoing a second copy) or that wants
+/// to hand a child raw keystrokes without line buffering toggle those behaviors without
+/// the child's own cooperation, e.g. a shell that only sets raw mode once the user has
+/// already started an editor.
+pub const OPCODE_SET_TERMIOS: u8 = 0x26;
+/// `c_lflag`'s `ECHO` bit: whether the pty echoes input back as it's typed.
+pub const TERMIOS_FLAG_ECHO: u8 = 1 << 0;
+/// `c_lflag`'s `ICANON` bit: whether input is line-buffered (canonical mode) rather
+/// than delivered to the child a byte at a time (raw mode).
+pub const TERMIOS_FLAG_ICANON: u8 = 1 << 1;
+/// `c_lflag`'s `ISIG` bit: whether `INTR`/`QUIT`/`SUSP` bytes generate signals rather
+/// than being passed through as plain input bytes.
+pub const TERMIOS_FLAG_ISIG: u8 = 1 << 2;
+/// No payload. Asks ptyd to read the slave's current termios back off the pty master
+/// and reply with a `termios` event carrying the same `TERMIOS_FLAG_*` bits
+/// `OPCODE_SET_TERMIOS` takes, plus `VEOF`/`VINTR`/`VSUSP`. Lets a host notice when the
+/// child itself has changed one of these — the canonical case being a password prompt
+/// disabling `ECHO` on its own, which `OPCODE_SET_TERMIOS` alone gives no way to detect.
+pub const OPCODE_QUERY_TERMIOS: u8 = 0x27;
+/// Carries a u32be length and that many bytes, relayed to the child like `OPCODE_DATA`
+/// except the caller wraps it in `ESC[200~`/`ESC[201~` first if the application has
+/// enabled bracketed paste mode (see `bracketed_paste.rs`, which watches the output
+/// stream for the `ESC[?2004h`/`ESC[?2004l` sequences that toggle it) — otherwise
+/// relayed raw, the same as a plain `OPCODE_DATA` frame would be. Exists so every
+/// client doesn't have to track bracketed-paste state and mangle pasted text itself.
+pub const OPCODE_PASTE: u8 = 0x28;
+/// No payload. Asks ptyd to re-emit everything still retained in `--scrollback-file`
+/// (see `scrollback.rs`) back onto stdout, bracketed in `OUTPUT_OPCODE_REPLAY_BEGIN`/
+/// `OUTPUT_OPCODE_REPLAY_END` so a client can tell replayed bytes apart from the live
+/// output that keeps arriving alongside them. A no-op (empty replay) if
+/// `--scrollback-file` wasn't configured. Lets a client that reconnects to a session or
+/// has cleared its own screen repopulate the terminal without restarting the child —
+/// unlike `OPCODE_CAPTURE_SCROLLBACK`, which renders retained output as a one-shot JSON
+/// text reply for display/logging rather than feeding it back through the child's own
+/// escape sequences.
+pub const OPCODE_REPLAY: u8 = 0x29;
+
+/// Channel-multiplexing opcodes, parsed by `parse_channel_frames` below rather than the
+/// main `parse_frames`/`FrameEvent` pair — only meaningful to `ptyd multiplex` (see
+/// `multiplex.rs`), which runs several ptys over one stdin/stdout instead of the default
+/// mode's single pty, and so has no single `child_pid` for the rest of this file's
+/// opcodes to address. Not part of `CAPABILITIES`/`HELLO` negotiation for the same
+/// reason `OPCODE_SET_ENV`/`OPCODE_SET_CWD`/`OPCODE_EXEC` aren't: a client opts in by
+/// invoking `ptyd multiplex` instead of plain `ptyd`, not by negotiating over a `HELLO`
+/// the single-pty default mode would have to make sense of too.
+///
+/// Carries a u32be channel id the client picks (ptyd never generates one, so a client
+/// always knows which channel a reply concerns without waiting for one), a u32be
+/// argument count, then for each argument a u32be length and its bytes — the same
+/// shape `OPCODE_EXEC` uses for its argv, just scoped to one channel instead of the
+/// whole session. Spawns a fresh pty and child; replies with
+/// `OUTPUT_OPCODE_CHANNEL_ERROR` instead if the channel id is already open or the fork
+/// fails.
+pub const OPCODE_OPEN_CHANNEL: u8 = 0x22;
+/// Carries a u32be channel id. Sends `SIGHUP` to that channel's child (same signal
+/// `OPCODE_CLOSE` sends a whole single-pty session) and tears its pty down once it
+/// exits; a no-op if the id isn't open.
+pub const OPCODE_CLOSE_CHANNEL: u8 = 0x23;
+/// Carries a u32be channel id, then a u32be length and that many bytes, relayed to
+/// that channel's pty exactly like a plain `OPCODE_DATA` frame is for the default
+/// mode's single pty. Dropped silently if the id isn't open — the same "client and
+/// server can race over an exit neither has told the other about yet" situation
+/// `OPCODE_DATA` itself doesn't specially report either.
+pub const OPCODE_CHANNEL_DATA: u8 = 0x24;
+/// Carries a u32be channel id, then the same u16be cols/rows pair as `OPCODE_RESIZE`.
+/// Applied immediately, with no per-channel debounce — a multiplexed client is
+/// expected to already be coalescing its own resize stream before it reaches ptyd at
+/// all, the way a single real terminal only ever sends one `SIGWINCH` at a time.
+pub const OPCODE_CHANNEL_RESIZE: u8 = 0x25;
+
+/// Output-direction framing (stdout, host-bound), separate numbering from the
+/// input-direction opcodes above (stdin, child-bound) since the two are different
+/// streams a client parses independently — there's no ambiguity in reusing `0x01`.
+/// Same wire shape as `OPCODE_DATA`: one opcode byte, a u32be length, then that many
+/// bytes of raw pty output.
+pub const OUTPUT_OPCODE_DATA: u8 = 0x01;
+/// The last frame `ptyd` ever writes to stdout before exiting: carries the wait status
+/// `crash::ExitStatus` decoded, so a host doesn't have to fall back on ptyd's own exit
+/// code (which collapses a signal into `128 + signum` and can't carry the core-dumped
+/// bit at all). Same `opcode + u32be length + payload` shape as `OUTPUT_OPCODE_DATA`;
+/// payload is three bytes: `exited` (`0`/`1`), `signal` (the signal number, or `0` if
+/// `exited`), `core_dumped` (`0`/`1`).
+pub const OUTPUT_OPCODE_EXIT_STATUS: u8 = 0x02;
+/// The first frame `ptyd` ever writes to stdout, ahead of any real pty output: carries
+/// `PROTOCOL_VERSION` and `CAPABILITIES` (both u32be) so a host can tell which opcodes
+/// this build supports before it sends anything that depends on one. Same
+/// `opcode + u32be length + payload` shape as the others.
+pub const OUTPUT_OPCODE_HELLO: u8 = 0x03;
+/// Reply to an `OPCODE_FLUSH` frame: carries the same u32be correlation id, written to
+/// stdout right after every master byte buffered at the time `OPCODE_FLUSH` arrived has
+/// itself been written. Unlike every other control reply in this file (a JSON line on
+/// stderr — see `lifecycle.rs`/`main.rs`'s various `emit_*` helpers), this one has to
+/// interleave exactly with `OUTPUT_OPCODE_DATA` in the literal stdout byte stream,
+/// because its entire purpose is marking a position in that stream rather than
+/// reporting state. Same `opcode + u32be length + payload` shape as the others.
+pub const OUTPUT_OPCODE_FLUSH_ACK: u8 = 0x04;
+/// Like `OUTPUT_OPCODE_DATA`, but the payload leads with a u32be sequence number —
+/// `0` for the first frame emitted this way, incrementing (and wrapping) by one per
+/// frame — ahead of the raw bytes. Only emitted once a client's `HELLO` has requested
+/// `CAP_SEQUENCED_OUTPUT` (see `FrameEvent::Hello` in `main.rs`); lets a client that
+/// reconnects to a session notice a gap (a sequence number it never saw) or a
+/// duplicate (one it already has), a prerequisite for resuming a session over a link
+/// that can drop or replay bytes rather than just disconnect cleanly. Never mixed with
+/// plain `OUTPUT_OPCODE_DATA` in the same session — once negotiated, every data frame
+/// for the rest of the session uses this shape instead.
+pub const OUTPUT_OPCODE_DATA_SEQ: u8 = 0x05;
+/// Like `OUTPUT_OPCODE_DATA`, but the payload leads with a u64be monotonic timestamp
+/// (milliseconds, `Clock::monotonic_ms` — same clock `--cpu-budget-ms`/`--idle-timeout-ms`
+/// already use internally) captured at the moment `read()` returned these bytes from
+/// the master, not when the frame was written to stdout or when a consumer happens to
+/// parse it. For recording/latency tooling that wants ptyd's own read timing rather
+/// than timestamps dominated by whatever scheduling jitter sits between ptyd and the
+/// consumer. Only emitted once a client's `HELLO` has requested
+/// `CAP_TIMESTAMPED_OUTPUT`; mutually exclusive with `OUTPUT_OPCODE_DATA_SEQ` for the
+/// same reason neither is ever mixed with plain `OUTPUT_OPCODE_DATA` — a client wanting
+/// both gap-detection and per-frame timing needs a future opcode carrying both fields,
+/// not a combination of these two (see `main.rs`'s `relay_master_chunk`).
+pub const OUTPUT_OPCODE_DATA_TIMESTAMPED: u8 = 0x06;
+/// Like `OUTPUT_OPCODE_DATA`, but the payload leads with a u32be decompressed length
+/// ahead of the `zstd`-compressed bytes (see `compression.rs`) rather than the raw
+/// bytes themselves — for a client on a slow link that would rather spend CPU than
+/// bandwidth on a verbose command's output. Only emitted once a client's `HELLO` has
+/// requested `CAP_COMPRESSION`, and then only for a chunk at least
+/// `--compress-min-bytes` long (see `main.rs`); smaller chunks keep using whichever of
+/// `OUTPUT_OPCODE_DATA`/`OUTPUT_OPCODE_DATA_SEQ`/`OUTPUT_OPCODE_DATA_TIMESTAMPED` the
+/// session would otherwise use, since `zstd`'s own framing overhead can outweigh the
+/// saving on a handful of bytes. Takes priority over those other two framings when a
+/// chunk does clear the threshold, for the same reason they don't mix with each other —
+/// a client wanting sequencing/timestamps on compressed output needs a future opcode
+/// carrying all of those fields, not a combination of these.
+pub const OUTPUT_OPCODE_DATA_COMPRESSED: u8 = 0x07;
+
+/// `ptyd multiplex`-only output opcodes, mirroring `OPCODE_OPEN_CHANNEL` et al.'s input
+/// side above — see that doc comment for why these stand apart from `CAPABILITIES`.
+/// Payload is a u32be channel id, then a u32be length and that many bytes of that
+/// channel's pty output — `OUTPUT_OPCODE_DATA` with a channel id prefixed ahead of the
+/// length, rather than a new top-level length-then-payload shape.
+pub const OUTPUT_OPCODE_CHANNEL_DATA: u8 = 0x08;
+/// Payload is a u32be channel id, then the same three exit-status bytes
+/// `OUTPUT_OPCODE_EXIT_STATUS` carries — written once a channel's child has been
+/// reaped, after which the channel id is free for a future `OPCODE_OPEN_CHANNEL` to
+/// reuse.
+pub const OUTPUT_OPCODE_CHANNEL_EXIT: u8 = 0x09;
+/// Payload is a u32be channel id, then a u32be message length and that many bytes of
+/// a human-readable message — written when an `OPCODE_OPEN_CHANNEL` frame can't be
+/// honored (id already open, or the fork itself failed).
+pub const OUTPUT_OPCODE_CHANNEL_ERROR: u8 = 0x0A;
+
+/// Like `OUTPUT_OPCODE_DATA`, but carries a chunk of the child's stderr instead of pty
+/// output — only emitted once `--stderr-framed` has put the child's stderr on its own
+/// pipe rather than the pty slave (see `--stderr-fd`'s doc comment in `main.rs` for why
+/// that split already existed; this opcode is the alternative to relaying the split
+/// stream raw to a separate fd, for a host that would rather get diagnostics
+/// interleaved on the same stdout it already reads instead of opening a second fd).
+/// Meaningless under `--raw-output` (no framing at all) and so not emitted there, the
+/// same as every other `OUTPUT_OPCODE_*` besides plain `OUTPUT_OPCODE_DATA` itself.
+pub const OUTPUT_OPCODE_STDERR_DATA: u8 = 0x0B;
+
+/// Written right before `ptyd` exits on an unrecoverable error (pty-open failure, exec
+/// failure, or an I/O error on the master/stdout/stdin fds — see the distinct
+/// `EXIT_*_FAILED` codes in `main.rs`, which still tell the categories apart even if
+/// this frame itself never makes it out). Payload: a u32be category length and that
+/// many bytes of a short machine-readable category (`"pty-open"`, `"exec"`, `"io"`),
+/// then a u32be message length and that many bytes of the OS error string. Skipped
+/// under `--raw-output`, the same as every other non-`OUTPUT_OPCODE_DATA` frame.
+pub const OUTPUT_OPCODE_ERROR: u8 = 0x0C;
+/// Written right before ptyd starts replaying retained scrollback in response to an
+/// `OPCODE_REPLAY` frame. Payload: a u32be byte count of the replay body about to
+/// follow as a single `OUTPUT_OPCODE_DATA` frame (always plain `OUTPUT_OPCODE_DATA`,
+/// regardless of whatever sequencing/timestamping/compression this session's live
+/// output is using — the replayed bytes are a one-shot static blob, not part of the
+/// live stream's own framing state). `0` if `--scrollback-file` wasn't configured, in
+/// which case `OUTPUT_OPCODE_REPLAY_END` follows immediately with no data frame
+/// between them.
+pub const OUTPUT_OPCODE_REPLAY_BEGIN: u8 = 0x0D;
+/// Written right after the replay body `OUTPUT_OPCODE_REPLAY_BEGIN` announced, closing
+/// the bracket so a client knows it's seen the whole thing.
+pub const OUTPUT_OPCODE_REPLAY_END: u8 = 0x0E;
+/// Written once, right after `OUTPUT_OPCODE_HELLO` and still well ahead of the child's
+/// first byte of pty output, so a host can start resource accounting or attach a
+/// debugger to the child the moment it exists rather than waiting for it to produce
+/// something. Payload: a u32be child pid, then a u32be path length and that many bytes
+/// of the slave pty's device path (e.g. `/dev/pts/4`) — the same two facts
+/// `--status-fd`'s one-shot startup JSON carries, but on the framed stdout stream
+/// itself for a host that doesn't want to open a second fd just for this.
+pub const OUTPUT_OPCODE_STARTED: u8 = 0x0F;
+/// Written in place of relaying an input frame `parse_frames_strict` couldn't
+/// recognize (see `--strict-protocol` in `main.rs`): a plain protocol-mode client never
+/// sees this, since `parse_frames` there just drops the one leading byte and moves on.
+/// Payload: the single unrecognized opcode byte, so a client that's out of sync with
+/// this build's opcode set learns exactly which frame it sent didn't land, instead of
+/// the silent desync dropping one byte at a time would otherwise cause.
+pub const OUTPUT_OPCODE_NAK: u8 = 0x10;
+
+/// Bit set in `CAPABILITIES` when output is framed via `OUTPUT_OPCODE_DATA` rather than
+/// written raw (i.e. `--raw-output` was not passed).
+pub const CAP_OUTPUT_FRAMING: u32 = 1 << 0;
+/// Bit set when a final `OUTPUT_OPCODE_EXIT_STATUS` frame is emitted before exit.
+pub const CAP_EXIT_STATUS_FRAME: u32 = 1 << 1;
+/// Bit set when `OPCODE_SIGNAL` is accepted on the input side.
+pub const CAP_SIGNAL: u32 = 1 << 2;
+/// Bit set when `OPCODE_TOGGLE_INPUT_TEE` is accepted on the input side.
+pub const CAP_INPUT_TEE_TOGGLE: u32 = 1 << 3;
+/// Bit set when `OPCODE_SEARCH_SCROLLBACK`/`OPCODE_CAPTURE_SCROLLBACK` are accepted.
+pub const CAP_SCROLLBACK: u32 = 1 << 4;
+/// Bit set when `OPCODE_PAUSE`/`OPCODE_RESUME` are accepted.
+pub const CAP_OUTPUT_PAUSE: u32 = 1 << 5;
+/// Bit set when `OPCODE_ENABLE_FLOW_CONTROL`/`OPCODE_ACK` are accepted.
+pub const CAP_WINDOWED_FLOW_CONTROL: u32 = 1 << 6;
+/// Bit set when `OPCODE_RESIZE_PIXELS` is accepted.
+pub const CAP_RESIZE_PIXELS: u32 = 1 << 7;
+/// Bit set when `OPCODE_FLUSH` is accepted.
+pub const CAP_FLUSH: u32 = 1 << 8;
+/// Bit set when `OPCODE_DATA_CRC32` is accepted.
+pub const CAP_FRAME_CRC32: u32 = 1 << 9;
+/// Bit set when a client's `HELLO` may request `OUTPUT_OPCODE_DATA_SEQ` framing by
+/// setting this same bit in its own `client_capabilities`.
+pub const CAP_SEQUENCED_OUTPUT: u32 = 1 << 10;
+/// Bit set when a client's `HELLO` may request `OUTPUT_OPCODE_DATA_TIMESTAMPED`
+/// framing by setting this same bit in its own `client_capabilities`.
+pub const CAP_TIMESTAMPED_OUTPUT: u32 = 1 << 11;
+/// Bit set when `OPCODE_STATS` is accepted.
+pub const CAP_STATS: u32 = 1 << 12;
+/// Bit set when `OPCODE_DATA_COMPRESSED` is accepted on the input side, and a client's
+/// `HELLO` may request `OUTPUT_OPCODE_DATA_COMPRESSED` framing by setting this same bit
+/// in its own `client_capabilities`.
+pub const CAP_COMPRESSION: u32 = 1 << 13;
+/// Bit set when `OPCODE_CLOSE_GRACEFUL` is accepted.
+pub const CAP_CLOSE_GRACEFUL: u32 = 1 << 14;
+/// Bit set when `OPCODE_SET_IDLE_TIMEOUT` is accepted.
+pub const CAP_SET_IDLE_TIMEOUT: u32 = 1 << 15;
+
+/// This build's full capability bitmask, reported in the `HELLO` frame. There's no
+/// per-feature compile-time toggle in this crate today — every opcode above is always
+/// compiled in — so this is currently just the set of opcodes that postdate the
+/// original protocol and might plausibly be missing from an older client's own build,
+/// not a runtime-configurable feature set.
+pub const CAPABILITIES: u32 = CAP_OUTPUT_FRAMING
+    | CAP_EXIT_STATUS_FRAME
+    | CAP_SIGNAL
+    | CAP_INPUT_TEE_TOGGLE
+    | CAP_SCROLLBACK
+    | CAP_OUTPUT_PAUSE
+    | CAP_WINDOWED_FLOW_CONTROL
+    | CAP_RESIZE_PIXELS
+    | CAP_FLUSH
+    | CAP_FRAME_CRC32
+    | CAP_SEQUENCED_OUTPUT
+    | CAP_TIMESTAMPED_OUTPUT
+    | CAP_STATS
+    | CAP_COMPRESSION
+    | CAP_CLOSE_GRACEFUL
+    | CAP_SET_IDLE_TIMEOUT;
+
+fn frame_output(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(1 + 4 + payload.len());
+    framed.push(opcode);
+    framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Wraps a chunk of pty output in an `OUTPUT_OPCODE_DATA` frame for `--raw-output`'s
+/// default (framed) counterpart. See `main.rs`'s `run_default` doc comment.
+pub fn frame_output_data(payload: &[u8]) -> Vec<u8> {
+    frame_output(OUTPUT_OPCODE_DATA, payload)
+}
+
+/// Wraps a chunk of pty output in an `OUTPUT_OPCODE_DATA_SEQ` frame, once a session has
+/// negotiated `CAP_SEQUENCED_OUTPUT`. See `main.rs`'s `relay_master_chunk`.
+pub fn frame_output_data_seq(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + payload.len());
+    body.extend_from_slice(&seq.to_be_bytes());
+    body.extend_from_slice(payload);
+    frame_output(OUTPUT_OPCODE_DATA_SEQ, &body)
+}
+
+/// Wraps a chunk of pty output in an `OUTPUT_OPCODE_DATA_TIMESTAMPED` frame, once a
+/// session has negotiated `CAP_TIMESTAMPED_OUTPUT`. See `main.rs`'s `relay_master_chunk`.
+pub fn frame_output_data_timestamped(read_at_ms: i64, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(8 + payload.len());
+    body.extend_from_slice(&(read_at_ms as u64).to_be_bytes());
+    body.extend_from_slice(payload);
+    frame_output(OUTPUT_OPCODE_DATA_TIMESTAMPED, &body)
+}
+
+/// Builds an `OUTPUT_OPCODE_DATA_COMPRESSED` frame from an already-`zstd`-compressed
+/// payload and the length it decompresses back to.
+pub fn frame_output_data_compressed(original_len: u32, compressed: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + compressed.len());
+    body.extend_from_slice(&original_len.to_be_bytes());
+    body.extend_from_slice(compressed);
+    frame_output(OUTPUT_OPCODE_DATA_COMPRESSED, &body)
+}
+
+/// Wraps a `crash::ExitStatus` in an `OUTPUT_OPCODE_EXIT_STATUS` frame, written once,
+/// right before `ptyd` returns from `relay_loop`. See `main.rs`'s `emit_exit_status_frame`.
+pub fn frame_output_exit_status(exited: bool, signal: Option<i32>, core_dumped: bool) -> Vec<u8> {
+    let payload = [exited as u8, signal.unwrap_or(0) as u8, core_dumped as u8];
+    frame_output(OUTPUT_OPCODE_EXIT_STATUS, &payload)
+}
+
+/// Wraps a chunk of one channel's pty output in an `OUTPUT_OPCODE_CHANNEL_DATA` frame.
+/// See `multiplex.rs`.
+pub fn frame_output_channel_data(channel_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(8 + payload.len());
+    body.extend_from_slice(&channel_id.to_be_bytes());
+    body.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    body.extend_from_slice(payload);
+    frame_output(OUTPUT_OPCODE_CHANNEL_DATA, &body)
+}
+
+/// Wraps a channel's `crash::ExitStatus` in an `OUTPUT_OPCODE_CHANNEL_EXIT` frame,
+/// written once that channel's child has been reaped. See `multiplex.rs`.
+pub fn frame_output_channel_exit(channel_id: u32, exited: bool, signal: Option<i32>, core_dumped: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(7);
+    body.extend_from_slice(&channel_id.to_be_bytes());
+    body.push(exited as u8);
+    body.push(signal.unwrap_or(0) as u8);
+    body.push(core_dumped as u8);
+    frame_output(OUTPUT_OPCODE_CHANNEL_EXIT, &body)
+}
+
+/// Wraps a human-readable message in an `OUTPUT_OPCODE_CHANNEL_ERROR` frame, written
+/// when an `OPCODE_OPEN_CHANNEL` frame can't be honored. See `multiplex.rs`.
+pub fn frame_output_channel_error(channel_id: u32, message: &str) -> Vec<u8> {
+    let bytes = message.as_bytes();
+    let mut body = Vec::with_capacity(8 + bytes.len());
+    body.extend_from_slice(&channel_id.to_be_bytes());
+    body.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    body.extend_from_slice(bytes);
+    frame_output(OUTPUT_OPCODE_CHANNEL_ERROR, &body)
+}
+
+/// Wraps a chunk of the child's stderr (read off the separate pipe `--stderr-framed`
+/// puts it on) in an `OUTPUT_OPCODE_STDERR_DATA` frame. See `main.rs`'s `relay_loop`.
+pub fn frame_output_stderr_data(payload: &[u8]) -> Vec<u8> {
+    frame_output(OUTPUT_OPCODE_STDERR_DATA, payload)
+}
+
+/// Builds an `OUTPUT_OPCODE_ERROR` frame from a short category and an OS error string.
+/// See `main.rs`'s `emit_error_frame`/`emit_error_frame_direct`.
+pub fn frame_output_error(category: &str, message: &str) -> Vec<u8> {
+    let category_bytes = category.as_bytes();
+    let message_bytes = message.as_bytes();
+    let mut body = Vec::with_capacity(8 + category_bytes.len() + message_bytes.len());
+    body.extend_from_slice(&(category_bytes.len() as u32).to_be_bytes());
+    body.extend_from_slice(category_bytes);
+    body.extend_from_slice(&(message_bytes.len() as u32).to_be_bytes());
+    body.extend_from_slice(message_bytes);
+    frame_output(OUTPUT_OPCODE_ERROR, &body)
+}
+
+/// Builds the `OUTPUT_OPCODE_REPLAY_BEGIN` frame announcing the byte count of the
+/// `OUTPUT_OPCODE_DATA` frame that follows it. See `main.rs`'s `emit_replay`.
+pub fn frame_output_replay_begin(len: u32) -> Vec<u8> {
+    frame_output(OUTPUT_OPCODE_REPLAY_BEGIN, &len.to_be_bytes())
+}
+
+/// Builds the `OUTPUT_OPCODE_REPLAY_END` frame closing an `OPCODE_REPLAY` reply.
+pub fn frame_output_replay_end() -> Vec<u8> {
+    frame_output(OUTPUT_OPCODE_REPLAY_END, &[])
+}
+
+/// Builds the `OUTPUT_OPCODE_HELLO` frame `main.rs` writes before anything else on
+/// stdout. See `main.rs`'s `run_default`.
+pub fn frame_output_hello(version: u32, capabilities: u32) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(8);
+    payload.extend_from_slice(&version.to_be_bytes());
+    payload.extend_from_slice(&capabilities.to_be_bytes());
+    frame_output(OUTPUT_OPCODE_HELLO, &payload)
+}
+
+/// Builds the `OUTPUT_OPCODE_STARTED` frame `main.rs` writes right after
+/// `OUTPUT_OPCODE_HELLO`. See `main.rs`'s `run_default`.
+pub fn frame_output_started(child_pid: pid_t, pty_path: &str) -> Vec<u8> {
+    let path_bytes = pty_path.as_bytes();
+    let mut payload = Vec::with_capacity(8 + path_bytes.len());
+    payload.extend_from_slice(&(child_pid as u32).to_be_bytes());
+    payload.extend_from_slice(&(path_bytes.len() as u32).to_be_bytes());
+    payload.extend_from_slice(path_bytes);
+    frame_output(OUTPUT_OPCODE_STARTED, &payload)
+}
+
+/// Builds the `OUTPUT_OPCODE_FLUSH_ACK` frame replying to an `OPCODE_FLUSH`. See
+/// `main.rs`'s `drain_master_for_flush`.
+pub fn frame_output_flush_ack(correlation_id: u32) -> Vec<u8> {
+    frame_output(OUTPUT_OPCODE_FLUSH_ACK, &correlation_id.to_be_bytes())
+}
+
+/// Builds the `OUTPUT_OPCODE_NAK` frame `parse_frames_strict` causes `main.rs` to send
+/// back for each unrecognized strict-mode frame. See `main.rs`'s `relay_loop`.
+pub fn frame_output_nak(opcode: u8) -> Vec<u8> {
+    frame_output(OUTPUT_OPCODE_NAK, &[opcode])
+}
+
+/// Min/max cols and rows a `RESIZE` frame is allowed to set. Requests outside these
+/// bounds are clamped into range rather than passed straight into `TIOCSWINSZ` —
+/// `0x0` leaves full-screen apps unable to tell how much room they have, and an
+/// absurdly large side (e.g. 10000 cols) can make a client-side renderer allocate far
+/// more than it meant to. See `--min-cols`/`--max-cols`/`--min-rows`/`--max-rows` in
+/// `main.rs`.
+#[derive(Clone, Copy)]
+pub struct ResizeBounds {
+    pub min_cols: u16,
+    pub max_cols: u16,
+    pub min_rows: u16,
+    pub max_rows: u16,
+}
+
+impl ResizeBounds {
+    pub const DEFAULT: ResizeBounds = ResizeBounds {
+        min_cols: 1,
+        max_cols: 1000,
+        min_rows: 1,
+        max_rows: 1000,
+    };
+
+    /// Clamps `cols`/`rows` into bounds, returning the applied size and whether
+    /// clamping actually changed anything.
+    pub fn clamp(&self, cols: u16, rows: u16) -> (u16, u16, bool) {
+        let clamped_cols = cols.clamp(self.min_cols, self.max_cols);
+        let clamped_rows = rows.clamp(self.min_rows, self.max_rows);
+        (clamped_cols, clamped_rows, clamped_cols != cols || clamped_rows != rows)
+    }
+}
+
+/// Side effects produced while parsing frames that the caller must act on outside
+/// the protocol layer (e.g. replying on a different fd).
+pub enum FrameEvent {
+    QueryCommand { index: u32 },
+    /// A `DATA_TAGGED` frame was relayed to the child; its correlation id should be
+    /// attached to the next command boundary the caller observes.
+    TaggedInput { correlation_id: u32 },
+    /// The client asked for a snapshot of session state (currently just cwd).
+    QueryState,
+    /// A `PING` frame arrived carrying the client's own timestamp, to be echoed back.
+    Ping { client_ts: u64 },
+    /// The client asked for a regex search of retained scrollback.
+    SearchScrollback { correlation_id: u32, pattern: String },
+    /// The client asked for retained scrollback rendered in the given format byte
+    /// (`0` plain, `1` ANSI-preserving, `2` HTML).
+    CaptureScrollback { correlation_id: u32, format: u8 },
+    /// A `RESIZE` frame's requested size fell outside `ResizeBounds` and was clamped
+    /// before being applied.
+    ResizeClamped { requested_cols: u16, requested_rows: u16, applied_cols: u16, applied_rows: u16 },
+    /// A `RESIZE` frame arrived with this (already-clamped) size; the caller debounces
+    /// it rather than applying it immediately. `xpixel`/`ypixel` are `0` for a plain
+    /// `OPCODE_RESIZE` frame and whatever an `OPCODE_RESIZE_PIXELS` frame carried
+    /// otherwise — passed straight through to `TIOCSWINSZ`, unclamped.
+    Resize { cols: u16, rows: u16, xpixel: u16, ypixel: u16 },
+    /// Input exceeded `--max-input-bytes-per-sec` and this many bytes were dropped
+    /// rather than relayed to the child.
+    InputThrottled { dropped_bytes: usize },
+    /// An `OPCODE_TOGGLE_INPUT_TEE` frame switched `--input-tee-file` recording on or
+    /// off; already applied to `input_tee` by the time this is pushed, so the caller
+    /// only needs it to report the change.
+    InputTeeToggled { enabled: bool },
+    /// The client's input stream is done — via an explicit `OPCODE_HALF_CLOSE_INPUT`
+    /// frame or plain stdin EOF. Output keeps relaying and the child is left alone;
+    /// the caller only needs this to report the half-close instead of staying silent.
+    InputHalfClosed,
+    /// An `OPCODE_SIGNAL` frame was delivered to the child's process group; already
+    /// applied by the time this is pushed, same as `InputTeeToggled`.
+    SignalSent { signal: i32 },
+    /// An `OPCODE_HELLO` frame arrived, declaring the client's protocol version and
+    /// capability bitmask. The caller decides whether `client_version` is one this
+    /// build can safely speak to.
+    Hello { client_version: u32, client_capabilities: u32 },
+    /// An `OPCODE_PAUSE` frame arrived; the caller stops reading the master fd.
+    OutputPauseRequested,
+    /// An `OPCODE_RESUME` frame arrived; the caller resumes reading the master fd
+    /// (subject to the usual watermark backpressure).
+    OutputResumeRequested,
+    /// An `OPCODE_ENABLE_FLOW_CONTROL` frame arrived, opting the session into
+    /// credit-based flow control with this many bytes of initial window.
+    FlowControlEnabled { window_bytes: u32 },
+    /// An `OPCODE_ACK` frame returned this many bytes of credit.
+    Ack { acked_bytes: u32 },
+    /// An `OPCODE_QUERY_WINSIZE` frame arrived; the caller reads `TIOCGWINSZ` off the
+    /// master and reports it.
+    QueryWinsize,
+    /// An `OPCODE_QUERY_INFO` frame arrived; the caller reports the child's pid, pgid,
+    /// and slave ttyname.
+    QueryInfo,
+    /// An `OPCODE_EOF` frame arrived; the caller writes `VEOF` to the master.
+    Eof,
+    /// An `OPCODE_FLUSH` frame arrived; the caller drains the master and replies with
+    /// `OUTPUT_OPCODE_FLUSH_ACK` carrying the same correlation id.
+    Flush { correlation_id: u32 },
+    /// An `OPCODE_DATA_CRC32` frame's checksum didn't match its payload; the payload
+    /// was dropped rather than relayed to the child.
+    FrameCrcMismatch { expected: u32, computed: u32, length: usize },
+    /// An `OPCODE_STATS` frame arrived; the caller reports runtime telemetry.
+    StatsRequested,
+    /// An `OPCODE_DATA_COMPRESSED` frame's payload didn't decompress to its declared
+    /// length (corrupt or truncated); dropped rather than relayed to the child.
+    DecompressionFailed { length: usize },
+    /// The leading byte didn't match any known opcode; it (and nothing else, since
+    /// there's no way to know how long an unrecognized frame is meant to be) was
+    /// skipped rather than relayed anywhere.
+    UnknownOpcode { opcode: u8 },
+    /// An `OPCODE_CLOSE_GRACEFUL` frame arrived with this grace period (`0` meaning
+    /// "use ptyd's own default"); the caller sends `SIGTERM` immediately and schedules
+    /// the `SIGKILL` escalation.
+    CloseGraceful { grace_ms: u32 },
+    /// An `OPCODE_SET_IDLE_TIMEOUT` frame arrived with this timeout in milliseconds
+    /// (`0` disables it), overriding whatever `--idle-timeout-ms` was (or wasn't)
+    /// configured with at startup.
+    IdleTimeoutSet { idle_timeout_ms: u32 },
+    /// An `OPCODE_SET_TERMIOS` frame arrived; the caller applies `value`'s bits (for
+    /// whichever are set in `mask`) to the slave's termios via `tcsetattr`.
+    SetTermios { mask: u8, value: u8 },
+    /// An `OPCODE_QUERY_TERMIOS` frame arrived; the caller reads the slave's current
+    /// termios off the master via `read_termios_flags` and reports it.
+    QueryTermios,
+    /// An `OPCODE_PASTE` frame arrived; the caller wraps `payload` in bracketed-paste
+    /// markers (if the application has enabled them — see `bracketed_paste.rs`) and
+    /// writes it to the master, instead of relaying it through the plain `OPCODE_DATA`
+    /// path this frame is parsed separately from.
+    Paste { payload: Vec<u8> },
+    /// An `OPCODE_REPLAY` frame arrived; the caller replays retained scrollback (if
+    /// any) bracketed in `OUTPUT_OPCODE_REPLAY_BEGIN`/`OUTPUT_OPCODE_REPLAY_END`.
+    ReplayRequested,
+}
+
+/// Applies a window size to the pty and signals the child, once the caller has
+/// decided a debounced `RESIZE` should actually take effect. `xpixel`/`ypixel` are `0`
+/// for a session that's only ever seen plain `OPCODE_RESIZE` frames.
+pub fn apply_resize(master_fd: c_int, child_pid: pid_t, cols: u16, rows: u16, xpixel: u16, ypixel: u16) -> Result<(), ()> {
+    let mut ws: libc::winsize = unsafe { mem::zeroed() };
+    ws.ws_col = cols;
+    ws.ws_row = rows;
+    ws.ws_xpixel = xpixel;
+    ws.ws_ypixel = ypixel;
+    let rc = unsafe { libc::ioctl(master_fd, libc::TIOCSWINSZ, &ws) };
+    if rc < 0 {
+        return Err(());
+    }
+    signal_child(child_pid, libc::SIGWINCH);
+    Ok(())
+}
+
+/// Reads back the window size the pty master currently reports via `TIOCGWINSZ` — the
+/// size the child itself sees, which may lag a just-sent `RESIZE` until its debounce
+/// window elapses (see `apply_resize`) or predate this session's own client entirely if
+/// a host is reconnecting to one it didn't start.
+pub fn read_winsize(master_fd: c_int) -> Result<(u16, u16, u16, u16), ()> {
+    let mut ws: libc::winsize = unsafe { mem::zeroed() };
+    let rc = unsafe { libc::ioctl(master_fd, libc::TIOCGWINSZ, &mut ws) };
+    if rc < 0 {
+        return Err(());
+    }
+    Ok((ws.ws_col, ws.ws_row, ws.ws_xpixel, ws.ws_ypixel))
+}
+
+/// Applies an `OPCODE_SET_TERMIOS` frame's mask/value to the slave's `c_lflag` via
+/// `tcgetattr`/`tcsetattr` on the master fd — the same fd `send_veof` in `pty.rs` reads
+/// `VEOF` off, since a pty master always speaks for its slave's line discipline without
+/// needing the slave fd itself open. Only the `TERMIOS_FLAG_*` bits set in `mask` are
+/// touched; every other flag (including any the client doesn't know about) is left
+/// exactly as the child last set it.
+pub fn set_termios_flags(master_fd: c_int, mask: u8, value: u8) -> Result<(), ()> {
+    let mut term: libc::termios = unsafe { mem::zeroed() };
+    if unsafe { libc::tcgetattr(master_fd, &mut term) } < 0 {
+        return Err(());
+    }
+    let mut apply = |flag_bit: u8, termios_bit: libc::tcflag_t| {
+        if mask & flag_bit != 0 {
+            if value & flag_bit != 0 {
+                term.c_lflag |= termios_bit;
+            } else {
+                term.c_lflag &= !termios_bit;
+            }
+        }
+    };
+    apply(TERMIOS_FLAG_ECHO, libc::ECHO);
+    apply(TERMIOS_FLAG_ICANON, libc::ICANON);
+    apply(TERMIOS_FLAG_ISIG, libc::ISIG);
+    if unsafe { libc::tcsetattr(master_fd, libc::TCSANOW, &term) } < 0 {
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Reads the slave's current termios back off the master, for an `OPCODE_QUERY_TERMIOS`
+/// reply. Returns the `TERMIOS_FLAG_*` bits set in `c_lflag` plus the raw `VEOF`/`VINTR`/
+/// `VSUSP` special-character bytes, so a host can notice a child-driven change (a
+/// password prompt clearing `ECHO` on its own, say) that `OPCODE_SET_TERMIOS` alone
+/// gives no way to detect.
+pub fn read_termios_flags(master_fd: c_int) -> Result<(u8, u8, u8, u8), ()> {
+    let mut term: libc::termios = unsafe { mem::zeroed() };
+    if unsafe { libc::tcgetattr(master_fd, &mut term) } < 0 {
+        return Err(());
+    }
+    let mut flags = 0_u8;
+    if term.c_lflag & libc::ECHO != 0 {
+        flags |= TERMIOS_FLAG_ECHO;
+    }
+    if term.c_lflag & libc::ICANON != 0 {
+        flags |= TERMIOS_FLAG_ICANON;
+    }
+    if term.c_lflag & libc::ISIG != 0 {
+        flags |= TERMIOS_FLAG_ISIG;
+    }
+    Ok((flags, term.c_cc[libc::VEOF], term.c_cc[libc::VINTR], term.c_cc[libc::VSUSP]))
+}
+
+/// What a `--defer-exec` setup-phase frame (`OPCODE_SET_ENV`/`OPCODE_SET_CWD`/
+/// `OPCODE_EXEC`) asked for — see `parse_defer_exec_frames` and
+/// `wait_for_defer_exec_setup` in `main.rs`. A distinct, much smaller event type from
+/// `FrameEvent`: these three opcodes are only ever sent before a child exists, so they
+/// never reach `parse_frames` at all.
+pub enum DeferExecEvent {
+    SetEnv { key: String, value: String },
+    SetCwd { path: String },
+    Exec { argv: Vec<String> },
+}
+
+/// Drains as many complete `OPCODE_SET_ENV`/`OPCODE_SET_CWD`/`OPCODE_EXEC` frames as
+/// `buf` holds, leaving any trailing partial frame in place for the next read — the
+/// same incremental-parse shape `parse_frames` uses for the main protocol, just over
+/// the handful of opcodes that matter before `--defer-exec` has forked a child.
+/// Anything else arriving during this phase is silently skipped, one byte at a time,
+/// rather than treated as a fatal framing error: a host accidentally leading with a
+/// normal-mode frame (e.g. `HELLO`) shouldn't wedge the setup phase.
+pub fn parse_defer_exec_frames(buf: &mut Vec<u8>) -> Vec<DeferExecEvent> {
+    let mut events = Vec::new();
+    let mut pos = 0;
+    loop {
+        let rest = &buf[pos..];
+        if rest.is_empty() {
+            break;
+        }
+        match rest[0] {
+            OPCODE_SET_ENV => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let key_len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+                if rest.len() < 9 + key_len {
+                    break;
+                }
+                let value_len_start = 5 + key_len;
+                let value_len = u32::from_be_bytes([
+                    rest[value_len_start],
+                    rest[value_len_start + 1],
+                    rest[value_len_start + 2],
+                    rest[value_len_start + 3],
+                ]) as usize;
+                let value_start = value_len_start + 4;
+                if rest.len() < value_start + value_len {
+                    break;
+                }
+                let key = String::from_utf8_lossy(&rest[5..5 + key_len]).into_owned();
+                let value = String::from_utf8_lossy(&rest[value_start..value_start + value_len]).into_owned();
+                events.push(DeferExecEvent::SetEnv { key, value });
+                pos += value_start + value_len;
+            }
+            OPCODE_SET_CWD => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let n = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+                if rest.len() < 5 + n {
+                    break;
+                }
+                let path = String::from_utf8_lossy(&rest[5..5 + n]).into_owned();
+                events.push(DeferExecEvent::SetCwd { path });
+                pos += 5 + n;
+            }
+            OPCODE_EXEC => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let argc = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+                let mut argv = Vec::with_capacity(argc);
+                let mut cursor = 5;
+                let mut complete = true;
+                for _ in 0..argc {
+                    if rest.len() < cursor + 4 {
+                        complete = false;
+                        break;
+                    }
+                    let arg_len =
+                        u32::from_be_bytes([rest[cursor], rest[cursor + 1], rest[cursor + 2], rest[cursor + 3]]) as usize;
+                    let arg_start = cursor + 4;
+                    if rest.len() < arg_start + arg_len {
+                        complete = false;
+                        break;
+                    }
+                    argv.push(String::from_utf8_lossy(&rest[arg_start..arg_start + arg_len]).into_owned());
+                    cursor = arg_start + arg_len;
+                }
+                if !complete {
+                    break;
+                }
+                events.push(DeferExecEvent::Exec { argv });
+                pos += cursor;
+            }
+            _ => {
+                pos += 1;
+            }
+        }
+    }
+    buf.drain(..pos);
+    events
+}
+
+/// What a channel-multiplexing frame (`OPCODE_OPEN_CHANNEL`/`OPCODE_CLOSE_CHANNEL`/
+/// `OPCODE_CHANNEL_DATA`/`OPCODE_CHANNEL_RESIZE`) asked for — see `parse_channel_frames`
+/// and `multiplex.rs`. A distinct, much smaller event type from `FrameEvent`, the same
+/// way `DeferExecEvent` is: these opcodes are only ever sent to `ptyd multiplex`, so
+/// they never reach `parse_frames` at all. `Data`'s payload is copied into an owned
+/// `Vec<u8>` rather than deferred the way `parse_frames` defers `OPCODE_DATA` — this is
+/// a lower-volume side protocol that needs each chunk dispatched to the right channel's
+/// master fd immediately, not batched the way the single-pty hot path is.
+pub enum ChannelEvent {
+    Open { channel_id: u32, argv: Vec<String> },
+    Close { channel_id: u32 },
+    Data { channel_id: u32, payload: Vec<u8> },
+    Resize { channel_id: u32, cols: u16, rows: u16 },
+}
+
+/// Drains as many complete channel-multiplexing frames as `buf` holds, leaving any
+/// trailing partial frame in place for the next read — the same incremental-parse shape
+/// `parse_defer_exec_frames` uses, over the handful of opcodes `ptyd multiplex` speaks.
+/// An unrecognized leading byte is skipped one byte at a time rather than treated as a
+/// fatal framing error, for the same reason `parse_defer_exec_frames` does.
+pub fn parse_channel_frames(buf: &mut Vec<u8>) -> Vec<ChannelEvent> {
+    let mut events = Vec::new();
+    let mut pos = 0;
+    loop {
+        let rest = &buf[pos..];
+        if rest.is_empty() {
+            break;
+        }
+        match rest[0] {
+            OPCODE_OPEN_CHANNEL => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let channel_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let argc = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]) as usize;
+                let mut argv = Vec::with_capacity(argc);
+                let mut cursor = 9;
+                let mut complete = true;
+                for _ in 0..argc {
+                    if rest.len() < cursor + 4 {
+                        complete = false;
+                        break;
+                    }
+                    let arg_len =
+                        u32::from_be_bytes([rest[cursor], rest[cursor + 1], rest[cursor + 2], rest[cursor + 3]]) as usize;
+                    let arg_start = cursor + 4;
+                    if rest.len() < arg_start + arg_len {
+                        complete = false;
+                        break;
+                    }
+                    argv.push(String::from_utf8_lossy(&rest[arg_start..arg_start + arg_len]).into_owned());
+                    cursor = arg_start + arg_len;
+                }
+                if !complete {
+                    break;
+                }
+                events.push(ChannelEvent::Open { channel_id, argv });
+                pos += cursor;
+            }
+            OPCODE_CLOSE_CHANNEL => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let channel_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                events.push(ChannelEvent::Close { channel_id });
+                pos += 5;
+            }
+            OPCODE_CHANNEL_DATA => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let channel_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let len = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]) as usize;
+                if rest.len() < 9 + len {
+                    break;
+                }
+                let payload = rest[9..9 + len].to_vec();
+                events.push(ChannelEvent::Data { channel_id, payload });
+                pos += 9 + len;
+            }
+            OPCODE_CHANNEL_RESIZE => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let channel_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let cols = u16::from_be_bytes([rest[5], rest[6]]);
+                let rows = u16::from_be_bytes([rest[7], rest[8]]);
+                events.push(ChannelEvent::Resize { channel_id, cols, rows });
+                pos += 9;
+            }
+            _ => {
+                pos += 1;
+            }
+        }
+    }
+    buf.drain(..pos);
+    events
+}
+
+fn errno_code() -> Option<i32> {
+    std::io::Error::last_os_error().raw_os_error()
+}
+
+/// Writes a DATA-family frame's payload to the pty master, first dropping whatever
+/// `rate_limiter` won't currently admit (see `throttle.rs`) and then transcoding the
+/// rest from UTF-8 to `transcoder`'s legacy encoding if one was configured (see
+/// `transcode.rs`) — the host always frames input as UTF-8; transcoding is only
+/// needed for vendor CLIs that expect a different encoding on their stdin. Whatever
+/// ends up relayed is also mirrored to `input_tee` (see `input_tee.rs`), if enabled.
+fn relay_input(
+    master_fd: c_int,
+    payload: &[u8],
+    transcoder: Option<&mut InputTranscoder>,
+    rate_limiter: Option<&mut InputRateLimiter>,
+    input_tee: Option<&mut InputTee>,
+    events: &mut Vec<FrameEvent>,
+) -> Result<(), ()> {
+    let admitted = match rate_limiter {
+        Some(limiter) => {
+            let (admitted, dropped) = limiter.admit(payload.len());
+            if dropped > 0 {
+                events.push(FrameEvent::InputThrottled { dropped_bytes: dropped });
+            }
+            &payload[..admitted]
+        }
+        None => payload,
+    };
+    match transcoder {
+        Some(transcoder) => {
+            let encoded = transcoder.encode(admitted);
+            if let Some(tee) = input_tee {
+                let _ = tee.write(&encoded);
+            }
+            write_all_fd(master_fd, &encoded)
+        }
+        None => {
+            if let Some(tee) = input_tee {
+                let _ = tee.write(admitted);
+            }
+            write_all_fd(master_fd, admitted)
+        }
+    }
+}
+
+pub fn write_all_fd(fd: c_int, mut buf: &[u8]) -> Result<(), ()> {
+    while !buf.is_empty() {
+        let written = unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) };
+        if written < 0 {
+            if errno_code() == Some(libc::EINTR) {
+                continue;
+            }
+            return Err(());
+        }
+        let w = written as usize;
+        buf = &buf[w..];
+    }
+    Ok(())
+}
+
+/// A DATA-family payload queued in `deferred_data` below: a zero-copy slice straight
+/// out of `data` for every plain opcode, or an owned buffer for `OPCODE_DATA_COMPRESSED`
+/// (whose payload doesn't exist until it's been decompressed).
+enum DeferredPayload<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+}
+
+impl DeferredPayload<'_> {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            DeferredPayload::Borrowed(bytes) => bytes,
+            DeferredPayload::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Parses and applies as many complete frames as are present in `data`, starting at
+/// its front. Returns the number of leading bytes consumed; anything left over is an
+/// incomplete trailing frame the caller must retain for the next read.
+///
+/// DATA-family payloads are not relayed as they're encountered; they're queued in
+/// `deferred_data` and only relayed once the whole buffer has been walked, so a
+/// RESIZE/PING/SIGNAL/etc. frame that arrives behind a burst of bulk input still gets
+/// its effect applied or its event pushed immediately, rather than waiting on
+/// `relay_input` (which can block on a full pty write buffer) for everything ahead of
+/// it in the same read. Relative order between DATA-family frames themselves is
+/// unchanged — only their priority against control frames shifts.
+#[allow(clippy::too_many_arguments)]
+fn parse_frames(
+    data: &[u8],
+    master_fd: c_int,
+    child_pid: pid_t,
+    events: &mut Vec<FrameEvent>,
+    mut transcoder: Option<&mut InputTranscoder>,
+    resize_bounds: &ResizeBounds,
+    mut rate_limiter: Option<&mut InputRateLimiter>,
+    mut input_tee: Option<&mut InputTee>,
+) -> Result<usize, ()> {
+    let mut pos = 0;
+    let mut deferred_data: Vec<DeferredPayload> = Vec::new();
+    loop {
+        let rest = &data[pos..];
+        if rest.is_empty() {
+            break;
+        }
+
+        match rest[0] {
+            OPCODE_DATA => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let n = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+                if rest.len() < 5 + n {
+                    break;
+                }
+
+                if n > 0 {
+                    deferred_data.push(DeferredPayload::Borrowed(&rest[5..5 + n]));
+                }
+                pos += 5 + n;
+            }
+            OPCODE_DATA_CRC32 => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let expected = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let n = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]) as usize;
+                if rest.len() < 9 + n {
+                    break;
+                }
+                let payload = &rest[9..9 + n];
+                let computed = crc32::checksum(payload);
+                if computed == expected {
+                    if n > 0 {
+                        deferred_data.push(DeferredPayload::Borrowed(payload));
+                    }
+                } else {
+                    events.push(FrameEvent::FrameCrcMismatch { expected, computed, length: n });
+                }
+                pos += 9 + n;
+            }
+            OPCODE_DATA_COMPRESSED => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let original_len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+                let compressed_len = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]) as usize;
+                if rest.len() < 9 + compressed_len {
+                    break;
+                }
+                let compressed = &rest[9..9 + compressed_len];
+                match compression::decompress(compressed, original_len) {
+                    Ok(decompressed) if decompressed.len() == original_len => {
+                        if !decompressed.is_empty() {
+                            deferred_data.push(DeferredPayload::Owned(decompressed));
+                        }
+                    }
+                    _ => {
+                        events.push(FrameEvent::DecompressionFailed { length: original_len });
+                    }
+                }
+                pos += 9 + compressed_len;
+            }
+            OPCODE_CLOSE_GRACEFUL => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let grace_ms = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                events.push(FrameEvent::CloseGraceful { grace_ms });
+                pos += 5;
+            }
+            OPCODE_SET_IDLE_TIMEOUT => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let idle_timeout_ms = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                events.push(FrameEvent::IdleTimeoutSet { idle_timeout_ms });
+                pos += 5;
+            }
+            OPCODE_SET_TERMIOS => {
+                if rest.len() < 3 {
+                    break;
+                }
+                let mask = rest[1];
+                let value = rest[2];
+                events.push(FrameEvent::SetTermios { mask, value });
+                pos += 3;
+            }
+            OPCODE_QUERY_TERMIOS => {
+                events.push(FrameEvent::QueryTermios);
+                pos += 1;
+            }
+            OPCODE_PASTE => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let n = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+                if rest.len() < 5 + n {
+                    break;
+                }
+                events.push(FrameEvent::Paste { payload: rest[5..5 + n].to_vec() });
+                pos += 5 + n;
+            }
+            OPCODE_REPLAY => {
+                events.push(FrameEvent::ReplayRequested);
+                pos += 1;
+            }
+            OPCODE_RESIZE | OPCODE_RESIZE_PIXELS => {
+                // A window drag can enqueue dozens of these in the same read; only the
+                // last geometry before something else (or the end of the buffer)
+                // matters, so directly consecutive RESIZE/RESIZE_PIXELS frames are
+                // coalesced into a single `ResizeClamped`/`Resize` event pair rather
+                // than one pair per frame. Not applied here regardless: the caller
+                // debounces rapid resizes (see `RESIZE_DEBOUNCE` in `main.rs`) and calls
+                // `apply_resize` once the debounce window elapses, rather than hitting
+                // `TIOCSWINSZ` and `SIGWINCH` once per frame during a window drag.
+                let mut cursor = pos;
+                let mut last: Option<(u16, u16, u16, u16, u16, u16, bool)> = None;
+                loop {
+                    let rest = &data[cursor..];
+                    if rest.is_empty() {
+                        break;
+                    }
+                    match rest[0] {
+                        OPCODE_RESIZE => {
+                            if rest.len() < 5 {
+                                break;
+                            }
+                            let requested_cols = u16::from_be_bytes([rest[1], rest[2]]);
+                            let requested_rows = u16::from_be_bytes([rest[3], rest[4]]);
+                            let (cols, rows, clamped) = resize_bounds.clamp(requested_cols, requested_rows);
+                            last = Some((requested_cols, requested_rows, cols, rows, 0, 0, clamped));
+                            cursor += 5;
+                        }
+                        OPCODE_RESIZE_PIXELS => {
+                            if rest.len() < 9 {
+                                break;
+                            }
+                            let requested_cols = u16::from_be_bytes([rest[1], rest[2]]);
+                            let requested_rows = u16::from_be_bytes([rest[3], rest[4]]);
+                            let xpixel = u16::from_be_bytes([rest[5], rest[6]]);
+                            let ypixel = u16::from_be_bytes([rest[7], rest[8]]);
+                            let (cols, rows, clamped) = resize_bounds.clamp(requested_cols, requested_rows);
+                            last = Some((requested_cols, requested_rows, cols, rows, xpixel, ypixel, clamped));
+                            cursor += 9;
+                        }
+                        _ => break,
+                    }
+                }
+                let Some((requested_cols, requested_rows, cols, rows, xpixel, ypixel, clamped)) = last else {
+                    break;
+                };
+                if clamped {
+                    events.push(FrameEvent::ResizeClamped { requested_cols, requested_rows, applied_cols: cols, applied_rows: rows });
+                }
+                events.push(FrameEvent::Resize { cols, rows, xpixel, ypixel });
+                pos = cursor;
+            }
+            OPCODE_CLOSE => {
+                signal_child(child_pid, libc::SIGHUP);
+                pos += 1;
+            }
+            OPCODE_DATA_TAGGED => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let correlation_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let n = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]) as usize;
+                if rest.len() < 9 + n {
+                    break;
+                }
+
+                if n > 0 {
+                    deferred_data.push(DeferredPayload::Borrowed(&rest[9..9 + n]));
+                }
+                events.push(FrameEvent::TaggedInput { correlation_id });
+                pos += 9 + n;
+            }
+            OPCODE_QUERY_COMMAND => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let index = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                events.push(FrameEvent::QueryCommand { index });
+                pos += 5;
+            }
+            OPCODE_QUERY_STATE => {
+                events.push(FrameEvent::QueryState);
+                pos += 1;
+            }
+            OPCODE_QUERY_WINSIZE => {
+                events.push(FrameEvent::QueryWinsize);
+                pos += 1;
+            }
+            OPCODE_QUERY_INFO => {
+                events.push(FrameEvent::QueryInfo);
+                pos += 1;
+            }
+            OPCODE_EOF => {
+                events.push(FrameEvent::Eof);
+                pos += 1;
+            }
+            OPCODE_STATS => {
+                events.push(FrameEvent::StatsRequested);
+                pos += 1;
+            }
+            OPCODE_FLUSH => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let correlation_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                events.push(FrameEvent::Flush { correlation_id });
+                pos += 5;
+            }
+            OPCODE_DATA_BEGIN | OPCODE_DATA_END => {
+                pos += 1;
+            }
+            OPCODE_PING => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let client_ts = u64::from_be_bytes([
+                    rest[1], rest[2], rest[3], rest[4], rest[5], rest[6], rest[7], rest[8],
+                ]);
+                events.push(FrameEvent::Ping { client_ts });
+                pos += 9;
+            }
+            OPCODE_SEARCH_SCROLLBACK => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let correlation_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let n = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]) as usize;
+                if rest.len() < 9 + n {
+                    break;
+                }
+                let pattern = String::from_utf8_lossy(&rest[9..9 + n]).into_owned();
+                events.push(FrameEvent::SearchScrollback { correlation_id, pattern });
+                pos += 9 + n;
+            }
+            OPCODE_CAPTURE_SCROLLBACK => {
+                if rest.len() < 6 {
+                    break;
+                }
+                let correlation_id = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let format = rest[5];
+                events.push(FrameEvent::CaptureScrollback { correlation_id, format });
+                pos += 6;
+            }
+            OPCODE_DATA_CONTINUE => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let n = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+                if rest.len() < 5 + n {
+                    break;
+                }
+
+                if n > 0 {
+                    deferred_data.push(DeferredPayload::Borrowed(&rest[5..5 + n]));
+                }
+                pos += 5 + n;
+            }
+            OPCODE_TOGGLE_INPUT_TEE => {
+                if rest.len() < 2 {
+                    break;
+                }
+                let enabled = rest[1] != 0;
+                if let Some(tee) = input_tee.as_deref_mut() {
+                    tee.set_enabled(enabled);
+                }
+                events.push(FrameEvent::InputTeeToggled { enabled });
+                pos += 2;
+            }
+            OPCODE_HALF_CLOSE_INPUT => {
+                events.push(FrameEvent::InputHalfClosed);
+                pos += 1;
+            }
+            OPCODE_SIGNAL => {
+                if rest.len() < 2 {
+                    break;
+                }
+                let signal = rest[1] as i32;
+                signal_child(child_pid, signal);
+                events.push(FrameEvent::SignalSent { signal });
+                pos += 2;
+            }
+            OPCODE_HELLO => {
+                if rest.len() < 9 {
+                    break;
+                }
+                let client_version = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                let client_capabilities = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]);
+                events.push(FrameEvent::Hello { client_version, client_capabilities });
+                pos += 9;
+            }
+            OPCODE_PAUSE => {
+                events.push(FrameEvent::OutputPauseRequested);
+                pos += 1;
+            }
+            OPCODE_RESUME => {
+                events.push(FrameEvent::OutputResumeRequested);
+                pos += 1;
+            }
+            OPCODE_ENABLE_FLOW_CONTROL => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let window_bytes = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                events.push(FrameEvent::FlowControlEnabled { window_bytes });
+                pos += 5;
+            }
+            OPCODE_ACK => {
+                if rest.len() < 5 {
+                    break;
+                }
+                let acked_bytes = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+                events.push(FrameEvent::Ack { acked_bytes });
+                pos += 5;
+            }
+            _ => {
+                events.push(FrameEvent::UnknownOpcode { opcode: rest[0] });
+                pos += 1;
+            }
+        }
+    }
+
+    for payload in &deferred_data {
+        relay_input(master_fd, payload.as_slice(), transcoder.as_deref_mut(), rate_limiter.as_deref_mut(), input_tee.as_deref_mut(), events)?;
+    }
+    Ok(pos)
+}
+
+/// Parses and applies as many complete frames as are present in `incoming`, leaving any
+/// trailing partial frame buffered for the next read.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_and_apply_frames(
+    incoming: &mut Vec<u8>,
+    master_fd: c_int,
+    child_pid: pid_t,
+    events: &mut Vec<FrameEvent>,
+    transcoder: Option<&mut InputTranscoder>,
+    resize_bounds: &ResizeBounds,
+    rate_limiter: Option<&mut InputRateLimiter>,
+    input_tee: Option<&mut InputTee>,
+) -> Result<(), ()> {
+    let consumed = parse_frames(incoming, master_fd, child_pid, events, transcoder, resize_bounds, rate_limiter, input_tee)?;
+    incoming.drain(0..consumed);
+    Ok(())
+}
+
+/// Zero-copy fast path: parses complete frames directly out of a just-read slice,
+/// writing their payloads straight from the read buffer instead of first copying them
+/// into an accumulation buffer. Returns the number of leading bytes consumed; the
+/// caller is only responsible for buffering the unconsumed tail (a partial frame).
+#[allow(clippy::too_many_arguments)]
+pub fn parse_frames_from_slice(
+    data: &[u8],
+    master_fd: c_int,
+    child_pid: pid_t,
+    events: &mut Vec<FrameEvent>,
+    transcoder: Option<&mut InputTranscoder>,
+    resize_bounds: &ResizeBounds,
+    rate_limiter: Option<&mut InputRateLimiter>,
+    input_tee: Option<&mut InputTee>,
+) -> Result<usize, ()> {
+    parse_frames(data, master_fd, child_pid, events, transcoder, resize_bounds, rate_limiter, input_tee)
+}
+
+/// Strict-mode counterpart to `parse_frames`: every frame, even one whose classic shape
+/// needs no internal length field at all (`OPCODE_CLOSE`, `OPCODE_QUERY_WINSIZE`, ...),
+/// must carry an explicit `[opcode][u32be length][length bytes]` wrapper. Each wrapped
+/// frame is re-assembled into its classic `[opcode][payload]` shape and handed to
+/// `parse_frames` to decode, so strict mode reuses the exact same per-opcode decoding
+/// `parse_frames` already has rather than duplicating it — the wrapper's only job is
+/// making the frame boundary explicit. An opcode `parse_frames` doesn't recognize still
+/// pushes `FrameEvent::UnknownOpcode`, but the wrapper's length lets the whole frame be
+/// skipped in one step instead of the single byte plain `parse_frames` drops, so a
+/// protocol bug (or a client speaking a dialect this build doesn't) can't silently
+/// desync the byte stream one opcode at a time; its opcode is also pushed onto `naks`,
+/// for the caller to answer with an `OUTPUT_OPCODE_NAK` frame per dropped frame instead
+/// of staying silent about it.
+#[allow(clippy::too_many_arguments)]
+fn parse_frames_strict(
+    data: &[u8],
+    master_fd: c_int,
+    child_pid: pid_t,
+    events: &mut Vec<FrameEvent>,
+    mut transcoder: Option<&mut InputTranscoder>,
+    resize_bounds: &ResizeBounds,
+    mut rate_limiter: Option<&mut InputRateLimiter>,
+    mut input_tee: Option<&mut InputTee>,
+    naks: &mut Vec<u8>,
+) -> Result<usize, ()> {
+    let mut pos = 0;
+    loop {
+        let rest = &data[pos..];
+        if rest.len() < 5 {
+            break;
+        }
+        let opcode = rest[0];
+        let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+        if rest.len() < 5 + len {
+            break;
+        }
+        let known = matches!(
+            opcode,
+            OPCODE_DATA..=OPCODE_QUERY_INFO | OPCODE_EOF..=OPCODE_SET_IDLE_TIMEOUT | OPCODE_SET_TERMIOS..=OPCODE_REPLAY
+        );
+        if !known {
+            // Answered here rather than by `parse_frames`, which would go on to read the
+            // payload's bytes as frames of their own.
+            events.push(FrameEvent::UnknownOpcode { opcode });
+            naks.push(opcode);
+            pos += 5 + len;
+            continue;
+        }
+        let mut frame = Vec::with_capacity(1 + len);
+        frame.push(opcode);
+        frame.extend_from_slice(&rest[5..5 + len]);
+        let before = events.len();
+        parse_frames(
+            &frame,
+            master_fd,
+            child_pid,
+            events,
+            transcoder.as_deref_mut(),
+            resize_bounds,
+            rate_limiter.as_deref_mut(),
+            input_tee.as_deref_mut(),
+        )?;
+        if matches!(events.get(before), Some(FrameEvent::UnknownOpcode { opcode: o }) if *o == opcode) {
+            naks.push(opcode);
+        }
+        pos += 5 + len;
+    }
+    Ok(pos)
+}
+
+/// Parses and applies as many complete strict-mode frames as are present in `incoming`
+/// (see `parse_frames_strict`), leaving any trailing partial frame buffered for the
+/// next read.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_and_apply_frames_strict(
+    incoming: &mut Vec<u8>,
+    master_fd: c_int,
+    child_pid: pid_t,
+    events: &mut Vec<FrameEvent>,
+    transcoder: Option<&mut InputTranscoder>,
+    resize_bounds: &ResizeBounds,
+    rate_limiter: Option<&mut InputRateLimiter>,
+    input_tee: Option<&mut InputTee>,
+    naks: &mut Vec<u8>,
+) -> Result<(), ()> {
+    let consumed = parse_frames_strict(incoming, master_fd, child_pid, events, transcoder, resize_bounds, rate_limiter, input_tee, naks)?;
+    incoming.drain(0..consumed);
+    Ok(())
+}
+
+/// Zero-copy strict-mode counterpart to `parse_frames_from_slice`.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_frames_from_slice_strict(
+    data: &[u8],
+    master_fd: c_int,
+    child_pid: pid_t,
+    events: &mut Vec<FrameEvent>,
+    transcoder: Option<&mut InputTranscoder>,
+    resize_bounds: &ResizeBounds,
+    rate_limiter: Option<&mut InputRateLimiter>,
+    input_tee: Option<&mut InputTee>,
+    naks: &mut Vec<u8>,
+) -> Result<usize, ()> {
+    parse_frames_strict(data, master_fd, child_pid, events, transcoder, resize_bounds, rate_limiter, input_tee, naks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bounds_pass_ordinary_sizes_through_unchanged() {
+        let (cols, rows, clamped) = ResizeBounds::DEFAULT.clamp(80, 24);
+        assert_eq!((cols, rows), (80, 24));
+        assert!(!clamped);
+    }
+
+    #[test]
+    fn clamps_below_the_minimum() {
+        let bounds = ResizeBounds { min_cols: 10, max_cols: 1000, min_rows: 5, max_rows: 1000 };
+        let (cols, rows, clamped) = bounds.clamp(1, 1);
+        assert_eq!((cols, rows), (10, 5));
+        assert!(clamped);
+    }
+
+    #[test]
+    fn clamps_above_the_maximum() {
+        let bounds = ResizeBounds { min_cols: 1, max_cols: 200, min_rows: 1, max_rows: 60 };
+        let (cols, rows, clamped) = bounds.clamp(9999, 9999);
+        assert_eq!((cols, rows), (200, 60));
+        assert!(clamped);
+    }
+
+    #[test]
+    fn reports_unclamped_when_already_in_bounds() {
+        let bounds = ResizeBounds { min_cols: 10, max_cols: 200, min_rows: 5, max_rows: 60 };
+        let (_, _, clamped) = bounds.clamp(80, 24);
+        assert!(!clamped);
+    }
+}
@@ -0,0 +1,229 @@
+use libc::{c_char, c_int, pid_t};
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+
+/// A freshly allocated pty pair, before a child has been forked onto the slave side.
+pub struct PtyPair {
+    pub master_fd: c_int,
+    pub slave_fd: c_int,
+}
+
+pub fn open_pty() -> Option<PtyPair> {
+    let mut master_fd: c_int = 0;
+    let mut slave_fd: c_int = 0;
+    let rc = unsafe {
+        libc::openpty(
+            &mut master_fd,
+            &mut slave_fd,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    Some(PtyPair { master_fd, slave_fd })
+}
+
+/// Applies a window size to a freshly opened pty (via `master_fd`) before a child is
+/// ever forked onto it, so that child's first `TIOCGWINSZ` — and anything it writes
+/// before the next `RESIZE` frame arrives, if ever — sees real geometry instead of
+/// `openpty`'s all-zero default. Unlike `protocol::apply_resize`, sends no `SIGWINCH`:
+/// there's no child yet to receive one, and a process that hasn't exec'd its real
+/// program yet has no handler installed regardless.
+pub fn set_initial_winsize(master_fd: c_int, cols: u16, rows: u16, xpixel: u16, ypixel: u16) -> Result<(), ()> {
+    let mut ws: libc::winsize = unsafe { mem::zeroed() };
+    ws.ws_col = cols;
+    ws.ws_row = rows;
+    ws.ws_xpixel = xpixel;
+    ws.ws_ypixel = ypixel;
+    let rc = unsafe { libc::ioctl(master_fd, libc::TIOCSWINSZ, &ws) };
+    if rc < 0 {
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Forks a child attached to `slave_fd` as its controlling terminal and execs `argv`.
+/// Returns the child pid in the parent; never returns in the child.
+pub fn fork_and_exec(argv: &[String], master_fd: c_int, slave_fd: c_int) -> Option<pid_t> {
+    fork_and_exec_with_stderr(argv, master_fd, slave_fd, None, false, None, None)
+}
+
+/// Like `fork_and_exec`, but when `stderr_pipe_write` is set the child's stderr is
+/// dup'd onto that fd instead of the pty slave, leaving stdin/stdout on the pty. When
+/// `trace` is set the child calls `PTRACE_TRACEME` before `exec`, stopping itself at
+/// the exec trap so a `SyscallAuditor` (see `audit.rs`) can attach before it runs any
+/// of the command's own code. When `cwd` is set the child `chdir`s there before exec;
+/// when `env` is set the child execs with exactly that environment (`KEY=VALUE` pairs)
+/// instead of inheriting ptyd's own — both are `None` for every caller except
+/// `--defer-exec`'s `OPCODE_EXEC` handling in `main.rs`, which builds them up from
+/// `SET_ENV`/`SET_CWD` frames sent before the exec. A supplied `env` switches the exec
+/// call from `execvp` to `execve`, which unlike `execvp` never searches `PATH` — the
+/// host is expected to send an absolute path (or a `PATH` among its `SET_ENV` frames
+/// and resolve it itself) when using `--defer-exec`.
+pub fn fork_and_exec_with_stderr(
+    argv: &[String],
+    master_fd: c_int,
+    slave_fd: c_int,
+    stderr_pipe_write: Option<c_int>,
+    trace: bool,
+    cwd: Option<&str>,
+    env: Option<&[(String, String)]>,
+) -> Option<pid_t> {
+    let cstrings: Vec<CString> = argv.iter().map(|a| CString::new(a.as_str())).collect::<Result<_, _>>().ok()?;
+    let mut c_argv: Vec<*const c_char> = cstrings.iter().map(|s| s.as_ptr()).collect();
+    c_argv.push(ptr::null());
+
+    let cwd_cstring = cwd.map(CString::new).transpose().ok()?;
+    let env_cstrings: Option<Vec<CString>> = env
+        .map(|pairs| pairs.iter().map(|(k, v)| CString::new(format!("{k}={v}"))).collect::<Result<_, _>>())
+        .transpose()
+        .ok()?;
+    let mut c_envp: Vec<*const c_char> = env_cstrings.as_ref().map_or_else(Vec::new, |strings| {
+        let mut ptrs: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+        ptrs.push(ptr::null());
+        ptrs
+    });
+
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return None;
+    }
+
+    if pid == 0 {
+        if trace && unsafe { libc::syscall(libc::SYS_ptrace, libc::PTRACE_TRACEME, 0, 0, 0) } < 0 {
+            unsafe { libc::_exit(1) };
+        }
+        if unsafe { libc::setsid() } < 0 {
+            unsafe { libc::_exit(1) };
+        }
+        if unsafe { libc::ioctl(slave_fd, libc::TIOCSCTTY as libc::c_ulong, 0) } < 0 {
+            unsafe { libc::_exit(1) };
+        }
+        if unsafe { libc::dup2(slave_fd, libc::STDIN_FILENO) } < 0 {
+            unsafe { libc::_exit(1) };
+        }
+        if unsafe { libc::dup2(slave_fd, libc::STDOUT_FILENO) } < 0 {
+            unsafe { libc::_exit(1) };
+        }
+        let stderr_source = stderr_pipe_write.unwrap_or(slave_fd);
+        if unsafe { libc::dup2(stderr_source, libc::STDERR_FILENO) } < 0 {
+            unsafe { libc::_exit(1) };
+        }
+        if let Some(cwd) = &cwd_cstring {
+            if unsafe { libc::chdir(cwd.as_ptr()) } < 0 {
+                unsafe { libc::_exit(1) };
+            }
+        }
+        unsafe {
+            libc::close(master_fd);
+            libc::close(slave_fd);
+            if let Some(pipe_fd) = stderr_pipe_write {
+                libc::close(pipe_fd);
+            }
+            if env_cstrings.is_some() {
+                libc::execve(c_argv[0], c_argv.as_ptr(), c_envp.as_mut_ptr());
+            } else {
+                libc::execvp(c_argv[0], c_argv.as_ptr());
+            }
+            libc::_exit(127);
+        }
+    }
+
+    unsafe {
+        libc::close(slave_fd);
+        if let Some(pipe_fd) = stderr_pipe_write {
+            libc::close(pipe_fd);
+        }
+    }
+    Some(pid)
+}
+
+/// Opens a pidfd for `pid` so the child's exit can be waited for via `poll`/`epoll`
+/// instead of periodic `waitpid(WNOHANG)` polling. Returns `None` on kernels without
+/// `pidfd_open` (pre-5.3) or on failure.
+pub fn pidfd_open(pid: pid_t) -> Option<c_int> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return None;
+    }
+    Some(fd as c_int)
+}
+
+/// Opens a pipe for relaying a child's stderr separately from its pty. Returns
+/// `(read_fd, write_fd)`.
+pub fn open_stderr_pipe() -> Option<(c_int, c_int)> {
+    let mut fds: [c_int; 2] = [0, 0];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    Some((fds[0], fds[1]))
+}
+
+pub fn signal_child(child_pid: pid_t, sig: c_int) {
+    let pgid = unsafe { libc::getpgid(child_pid) };
+    if pgid < 0 {
+        return;
+    }
+
+    if pgid == child_pid {
+        let _ = unsafe { libc::killpg(pgid, sig) };
+    } else {
+        let _ = unsafe { libc::kill(child_pid, sig) };
+    }
+}
+
+/// Looks up the slave-side device path (e.g. `/dev/pts/4`) for an open pty master fd,
+/// for reporting in `--status-fd` startup info.
+pub fn pty_path(master_fd: c_int) -> Option<String> {
+    let mut buf = [0 as c_char; 64];
+    let rc = unsafe { libc::ptsname_r(master_fd, buf.as_mut_ptr(), buf.len()) };
+    if rc != 0 {
+        return None;
+    }
+    let cstr = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    cstr.to_str().ok().map(str::to_owned)
+}
+
+/// Reads the child's current working directory via `/proc/<pid>/cwd`, used as a
+/// fallback when the shell hasn't reported one via OSC 7.
+pub fn proc_cwd(pid: pid_t) -> Option<String> {
+    std::fs::read_link(format!("/proc/{pid}/cwd"))
+        .ok()
+        .map(|p| p.to_string_lossy().into_owned())
+}
+
+/// Writes the slave's current `VEOF` character (usually Ctrl-D) to the pty master, the
+/// same way a terminal driver delivers end-of-input when a user types it: a
+/// line-disciplined child blocked in `read()` on the slave (e.g. `cat` with no args)
+/// sees EOF once the line containing it is flushed, without the master side having to
+/// close anything. Distinct from closing stdin or `OPCODE_CLOSE`'s `SIGHUP` — the
+/// session and the child both keep running; only that one read unblocks.
+pub fn send_veof(master_fd: c_int) -> Result<(), ()> {
+    let mut term: libc::termios = unsafe { mem::zeroed() };
+    if unsafe { libc::tcgetattr(master_fd, &mut term) } < 0 {
+        return Err(());
+    }
+    let veof = term.c_cc[libc::VEOF];
+    if veof == 0 {
+        return Err(());
+    }
+    let rc = unsafe { libc::write(master_fd, [veof].as_ptr().cast(), 1) };
+    if rc != 1 {
+        return Err(());
+    }
+    Ok(())
+}
+
+pub fn child_exit_code(status: c_int) -> i32 {
+    if libc::WIFEXITED(status) {
+        return libc::WEXITSTATUS(status);
+    }
+    if libc::WIFSIGNALED(status) {
+        return 128 + libc::WTERMSIG(status);
+    }
+    1
+}
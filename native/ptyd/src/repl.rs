@@ -0,0 +1,61 @@
+//! Detects when the foreground process in a session's pty is a well-known
+//! interactive interpreter (python, node, irb, psql, ...), so a harness can switch
+//! from "shell command" input handling to "REPL input" handling without having to
+//! guess from output alone. Process-name matching is the bulk of it, since it's the
+//! signal every shell already gives `ptyd` for free via the controlling terminal's
+//! foreground process group; prompt-pattern matching (e.g. psql's trailing `=> `) is
+//! left for a future pass, once there's a concrete client that needs cases the
+//! process name alone can't settle.
+
+use libc::pid_t;
+
+const KNOWN_REPLS: &[&str] = &["python", "python3", "node", "irb", "psql"];
+
+fn foreground_process_name(master_fd: libc::c_int) -> Option<String> {
+    let pgrp = unsafe { libc::tcgetpgrp(master_fd) };
+    if pgrp <= 0 {
+        return None;
+    }
+    read_comm(pgrp)
+}
+
+fn read_comm(pid: pid_t) -> Option<String> {
+    let raw = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(raw.trim_end().to_string())
+}
+
+/// Tracks whether the session is currently "inside" a known REPL, re-checking the
+/// foreground process each time a command boundary crosses (see `commands.rs`), since
+/// that's when the foreground process is most likely to have changed.
+#[derive(Default)]
+pub struct ReplDetector {
+    active: Option<String>,
+}
+
+impl ReplDetector {
+    /// Call after a command-start boundary. Returns the REPL name to report entering,
+    /// if the new foreground process is a known interpreter we weren't already inside.
+    pub fn on_command_start(&mut self, master_fd: libc::c_int) -> Option<String> {
+        let name = foreground_process_name(master_fd)?;
+        if !KNOWN_REPLS.contains(&name.as_str()) {
+            return None;
+        }
+        if self.active.as_deref() == Some(name.as_str()) {
+            return None;
+        }
+        self.active = Some(name.clone());
+        Some(name)
+    }
+
+    /// Call after a command-end boundary. Returns the REPL name to report exiting, if
+    /// we were inside one and the foreground process is no longer it.
+    pub fn on_command_end(&mut self, master_fd: libc::c_int) -> Option<String> {
+        let active = self.active.clone()?;
+        let still_active = foreground_process_name(master_fd).as_deref() == Some(active.as_str());
+        if still_active {
+            return None;
+        }
+        self.active = None;
+        Some(active)
+    }
+}
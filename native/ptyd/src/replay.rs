@@ -0,0 +1,40 @@
+//! Bounded in-memory backlog of a `serve` session's pty output, kept so a reattaching
+//! client can be handed the last `N` bytes instead of a blank screen. Unlike
+//! `scrollback::Scrollback`, this has no on-disk backing and no search support — it
+//! exists purely to answer "what did I just miss", not to retain history across a
+//! daemon restart or let a client grep through it.
+
+use std::collections::VecDeque;
+
+pub struct ReplayBuffer {
+    capacity: usize,
+    buf: VecDeque<u8>,
+}
+
+impl ReplayBuffer {
+    pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+    pub fn new(capacity: usize) -> Self {
+        ReplayBuffer { capacity: capacity.max(1), buf: VecDeque::new() }
+    }
+
+    /// Appends `bytes`, evicting from the front as needed so the buffer never holds
+    /// more than `capacity` bytes of the most recent output.
+    pub fn push(&mut self, bytes: &[u8]) {
+        if bytes.len() >= self.capacity {
+            self.buf.clear();
+            self.buf.extend(bytes[bytes.len() - self.capacity..].iter().copied());
+            return;
+        }
+        let overflow = (self.buf.len() + bytes.len()).saturating_sub(self.capacity);
+        if overflow > 0 {
+            self.buf.drain(..overflow);
+        }
+        self.buf.extend(bytes.iter().copied());
+    }
+
+    /// Retained bytes in chronological order (oldest first).
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.buf.iter().copied().collect()
+    }
+}
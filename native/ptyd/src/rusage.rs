@@ -0,0 +1,55 @@
+//! Wall/CPU/RSS accounting for commands, sourced from `getrusage(RUSAGE_CHILDREN)`.
+//!
+//! This aggregates across *all* reaped children, not just the pty child, so deltas
+//! taken immediately around a command boundary are an approximation rather than a
+//! cgroup-exact figure; it's the cheapest signal available without cgroup setup.
+
+#[derive(Clone, Copy)]
+pub struct ResourceUsage {
+    user_us: i64,
+    sys_us: i64,
+    max_rss_kb: i64,
+}
+
+impl ResourceUsage {
+    pub fn children_now() -> Self {
+        Self::from_who(libc::RUSAGE_CHILDREN)
+    }
+
+    /// This process's own usage, as opposed to `children_now`'s reaped-children
+    /// aggregate. Used to measure ptyd's own idle-loop CPU cost.
+    pub fn self_now() -> Self {
+        Self::from_who(libc::RUSAGE_SELF)
+    }
+
+    fn from_who(who: libc::c_int) -> Self {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        unsafe { libc::getrusage(who, &mut usage) };
+        ResourceUsage {
+            user_us: usage.ru_utime.tv_sec * 1_000_000 + usage.ru_utime.tv_usec,
+            sys_us: usage.ru_stime.tv_sec * 1_000_000 + usage.ru_stime.tv_usec,
+            max_rss_kb: usage.ru_maxrss,
+        }
+    }
+}
+
+pub struct ResourceDelta {
+    pub user_ms: i64,
+    pub sys_ms: i64,
+    pub max_rss_kb: i64,
+}
+
+/// Diffs one usage snapshot against an earlier one. Peak RSS is not cumulative in the
+/// kernel's accounting, so the max observed is reported rather than a delta.
+pub fn usage_delta(baseline: &ResourceUsage, now: &ResourceUsage) -> ResourceDelta {
+    ResourceDelta {
+        user_ms: (now.user_us - baseline.user_us).max(0) / 1_000,
+        sys_ms: (now.sys_us - baseline.sys_us).max(0) / 1_000,
+        max_rss_kb: now.max_rss_kb.max(baseline.max_rss_kb),
+    }
+}
+
+/// Diffs the current `RUSAGE_CHILDREN` snapshot against one captured earlier.
+pub fn rusage_children_delta(baseline: &ResourceUsage) -> ResourceDelta {
+    usage_delta(baseline, &ResourceUsage::children_now())
+}
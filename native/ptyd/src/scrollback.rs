@@ -0,0 +1,166 @@
+//! Per-session scrollback retention beyond the relay loop's small in-flight buffers.
+//! Master output is appended to a fixed-size circular file, memory-mapped so the
+//! retained bytes never have to be fully resident to be written or read back — a
+//! long-lived session can retain tens of thousands of lines without unbounded RAM use.
+//! Once the buffer wraps, the oldest bytes are overwritten in place, so there's no
+//! growth to cap beyond the file's fixed size (see `--scrollback-file`/
+//! `--scrollback-bytes` in `main.rs`).
+
+use std::collections::VecDeque;
+
+use memmap2::MmapMut;
+use regex::Regex;
+
+/// Bounded history of (cumulative byte offset, wall-clock time) pairs, one per
+/// `append` call, used to approximate a timestamp for a match found later by
+/// `search`. Capped independently of the scrollback buffer itself, since a session
+/// that calls `append` far more often than its buffer wraps shouldn't grow this
+/// unbounded.
+const MAX_CHECKPOINTS: usize = 4096;
+
+struct Checkpoint {
+    cumulative_offset: u64,
+    unix_ms: i64,
+}
+
+pub struct Match {
+    pub line: String,
+    pub offset: u64,
+    pub unix_ms: Option<i64>,
+}
+
+pub struct Scrollback {
+    mmap: MmapMut,
+    capacity: usize,
+    write_pos: usize,
+    wrapped: bool,
+    total_written: u64,
+    checkpoints: VecDeque<Checkpoint>,
+}
+
+impl Scrollback {
+    pub fn open(path: &str, capacity: usize) -> Option<Self> {
+        let capacity = capacity.max(1);
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .ok()?;
+        file.set_len(capacity as u64).ok()?;
+        let mmap = unsafe { MmapMut::map_mut(&file) }.ok()?;
+        Some(Scrollback {
+            mmap,
+            capacity,
+            write_pos: 0,
+            wrapped: false,
+            total_written: 0,
+            checkpoints: VecDeque::new(),
+        })
+    }
+
+    pub fn append(&mut self, bytes: &[u8], unix_ms: i64) {
+        if self.checkpoints.len() >= MAX_CHECKPOINTS {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(Checkpoint {
+            cumulative_offset: self.total_written,
+            unix_ms,
+        });
+
+        for chunk in bytes.chunks(self.capacity) {
+            let remaining = self.capacity - self.write_pos;
+            if chunk.len() <= remaining {
+                self.mmap[self.write_pos..self.write_pos + chunk.len()].copy_from_slice(chunk);
+                self.write_pos += chunk.len();
+            } else {
+                self.mmap[self.write_pos..self.capacity].copy_from_slice(&chunk[..remaining]);
+                let rest = &chunk[remaining..];
+                self.mmap[..rest.len()].copy_from_slice(rest);
+                self.write_pos = rest.len();
+                self.wrapped = true;
+            }
+            if self.write_pos == self.capacity {
+                self.write_pos = 0;
+                self.wrapped = true;
+            }
+        }
+        self.total_written += bytes.len() as u64;
+    }
+
+    /// Retained bytes in chronological order (oldest first).
+    pub fn snapshot(&self) -> Vec<u8> {
+        if !self.wrapped {
+            return self.mmap[..self.write_pos].to_vec();
+        }
+        let mut out = Vec::with_capacity(self.capacity);
+        out.extend_from_slice(&self.mmap[self.write_pos..]);
+        out.extend_from_slice(&self.mmap[..self.write_pos]);
+        out
+    }
+
+    /// Nearest checkpoint at or before `offset`, approximating when the byte at that
+    /// cumulative offset was written.
+    fn timestamp_at(&self, offset: u64) -> Option<i64> {
+        self.checkpoints.iter().rev().find(|c| c.cumulative_offset <= offset).map(|c| c.unix_ms)
+    }
+
+    /// Regex-searches retained lines, returning up to `limit` matches in chronological
+    /// order with each match's byte offset (relative to the start of the session, not
+    /// the buffer's circular layout) and an approximate timestamp.
+    pub fn search(&self, pattern: &Regex, limit: usize) -> Vec<Match> {
+        let data = self.snapshot();
+        let retained_start_offset = self.total_written - data.len() as u64;
+        let mut matches = Vec::new();
+        let mut line_start = 0usize;
+        for (i, &byte) in data.iter().enumerate() {
+            if byte != b'\n' {
+                continue;
+            }
+            let line = String::from_utf8_lossy(&data[line_start..i]);
+            let line = line.trim_end_matches('\r');
+            if pattern.is_match(line) {
+                let offset = retained_start_offset + line_start as u64;
+                matches.push(Match {
+                    line: line.to_string(),
+                    offset,
+                    unix_ms: self.timestamp_at(offset),
+                });
+                if matches.len() >= limit {
+                    return matches;
+                }
+            }
+            line_start = i + 1;
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, FakeClock};
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("ptyd-scrollback-test-{name}-{}", std::process::id())).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn search_reports_the_timestamp_recorded_at_append_time() {
+        let path = temp_path("timestamps");
+        let mut sb = Scrollback::open(&path, 4096).unwrap();
+        let clock = FakeClock::new(1_000);
+
+        sb.append(b"first line\n", clock.unix_ms());
+        clock.advance_ms(500);
+        sb.append(b"second line\n", clock.unix_ms());
+
+        let pattern = Regex::new("second").unwrap();
+        let matches = sb.search(&pattern, 10);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].unix_ms, Some(1_500));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,32 @@
+//! The systemd `sd_notify(3)` protocol, reimplemented directly over a `UnixDatagram`
+//! rather than linking `libsystemd`: it's a one-line wire format (send `KEY=value\n...`
+//! to the path in `$NOTIFY_SOCKET`) not worth a C dependency for. Used by `serve.rs` to
+//! report `READY=1` once its listeners are up and `WATCHDOG=1` on a heartbeat, so a
+//! unit with `Type=notify` and `WatchdogSec=` set gets both an accurate "started" signal
+//! and automatic restart if the daemon ever wedges.
+//!
+//! A no-op everywhere `$NOTIFY_SOCKET` isn't set, which is every run that isn't actually
+//! under systemd — nothing here changes behavior outside a systemd unit.
+
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Sends `state` (e.g. `"READY=1"`, `"WATCHDOG=1"`) to `$NOTIFY_SOCKET`. Silently does
+/// nothing if the variable isn't set (not running under systemd) or the send fails
+/// (systemd itself died, or never cared) — a readiness/watchdog ping is advisory, never
+/// something worth failing the daemon over.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else { return };
+    let Ok(socket) = UnixDatagram::unbound() else { return };
+    let _ = socket.send_to(state.as_bytes(), path);
+}
+
+/// How often to send `WATCHDOG=1`, derived from `$WATCHDOG_USEC` (set by systemd
+/// alongside `$NOTIFY_SOCKET` when the unit has `WatchdogSec=` configured). Systemd's
+/// own recommendation is to ping at half the configured timeout, so a single missed
+/// heartbeat doesn't immediately trip the watchdog. `None` if the unit has no watchdog
+/// configured, or the daemon isn't running under systemd at all.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec) / 2)
+}
@@ -0,0 +1,2132 @@
+//! `ptyd serve --socket PATH` (and/or `--tcp HOST:PORT`): a long-running daemon that
+//! accepts any number of client connections, each of which can create, attach to, or
+//! destroy pty sessions via a small binary control protocol (see `OP_*` below) — unlike
+//! every other subcommand in this crate, a session here outlives any one connection.
+//! This is the `serve` daemon mode `main.rs`'s module doc comment has been pointing at:
+//! `wait`/`send`/`resize`/`signal`/`ui` all want a session registry and a control socket
+//! reachable from a second process, and this is where that registry and socket start
+//! existing. The Unix and TCP listeners run the identical protocol over
+//! `handle_connection`, generic over anything `Read + AsRawFd` — `--tcp` exists so a
+//! harness on another machine (a container or VM without a shared filesystem to put a
+//! Unix socket on) can reach the same create/attach/destroy surface. `--ws` runs the
+//! same create/attach/destroy/relay logic over a third connection kind, `websocket.rs`'s
+//! binary-message framing in place of raw bytes, so a browser can speak the protocol
+//! directly with no separate proxy translating frames for it. `--tls-cert`/`--tls-key`
+//! (and optionally `--tls-client-ca` for mutual TLS) wrap `--tcp` and `--ws` in
+//! `tls::TlsStream`, since a session relayed to a client on another machine over either
+//! of those would otherwise be plaintext on the wire; `--socket` is already local-only
+//! and unaffected.
+//!
+//! A session's pty and child are created by `OP_CREATE` and torn down only by
+//! `OP_DESTROY` or the child exiting on its own — never by a client merely
+//! disconnecting. `OP_ATTACH` joins a connection to a session's relay for as long as
+//! that connection stays open; when it closes, it's dropped from the session's
+//! attached set and any others stay live uninterrupted. Output produced while nobody
+//! is attached (and the most recent output regardless) is kept in a bounded
+//! `replay::ReplayBuffer` per session, sized by `--replay-bytes`; a successful
+//! `OP_ATTACH` sends that backlog to the client before settling into the live relay, so
+//! reattaching repopulates the screen with recent context instead of starting blank.
+//!
+//! Attaching is mirror-like, not exclusive-lock-like: any number of connections can
+//! `OP_ATTACH` the same session at once, each getting every byte of output and able to
+//! send input, rather than the latest `OP_ATTACH` stealing the session from whoever
+//! held it before. A slow client is dropped from the attached set (see
+//! `AttachedClient`) rather than allowed to stall delivery to the others. "Detach" is
+//! still a thing a client does on purpose (just stop reading/writing and close) as well
+//! as a thing that happens to it (the client process dies and its slot is cleaned up).
+//!
+//! `--token`/`--token-file` require `OP_AUTH` with the matching pre-shared token before
+//! `OP_CREATE`/`OP_ATTACH`/`OP_DESTROY` are honored on a connection, over every
+//! transport including `--socket` — a stray local process that can open the Unix socket
+//! shouldn't get to create or steal sessions any more easily than one reaching in over
+//! the network would.
+//!
+//! Under a systemd unit with `Type=notify`, `sd_notify::notify("READY=1")` fires once
+//! every requested listener is bound; with `WatchdogSec=` also set, a background thread
+//! pings `WATCHDOG=1` at half that interval so systemd restarts the daemon if it ever
+//! stops servicing its own heartbeat. Both are no-ops outside systemd.
+//!
+//! `OP_LIST` is read-only introspection over the same registry: every session's id,
+//! name if it has one, argv, pid, current pty size, attach state, idle time, and byte
+//! counters, in one response. `ptyd ls` (`ls.rs`) is its client, and the first
+//! standalone subcommand in this crate that talks to `serve.rs`'s control protocol from
+//! a second process rather than being `serve.rs` itself.
+//!
+//! `--daemon` double-forks and detaches from the controlling terminal (`daemon::daemonize`)
+//! before any listener binds, so traditional (non-systemd) init tooling gets a real
+//! background process rather than one still attached to whatever shell started it.
+//! `--pidfile PATH` writes and locks a pidfile with the daemon's final pid, for that same
+//! init tooling to find and signal; it works with or without `--daemon`, since a
+//! supervisor that backgrounds `ptyd` itself still wants a pidfile to watch.
+//!
+//! `OP_CREATE` can optionally give a session a human-readable name instead of relying
+//! on its opaque id alone, plus a conflict policy for what to do if that name is
+//! already taken (error, or hand back the already-running session) — so a script can
+//! idempotently "get me the `build` session" without tracking ids itself. `OP_ATTACH`
+//! and `OP_DESTROY` accept that name anywhere they'd accept an id (`resolve_session_id`),
+//! with no change to either op's wire format.
+//!
+//! `--reap-idle-ms` has `sweep_idle_sessions` terminate, with the same `SIGTERM`-then-
+//! `SIGKILL` escalation `OPCODE_CLOSE_GRACEFUL` uses, any session that's both detached
+//! (`attached` empty) and idle for at least that long — a long-running `serve` process
+//! would otherwise accumulate abandoned shells forever, since nothing short of
+//! `OP_DESTROY` or the child exiting on its own ever removed a session before this.
+//! Every session's actual end, however it happens, is recorded as a `Tombstone` (final
+//! exit code, when it happened) kept around for `--tombstone-ttl-ms` and queryable via
+//! `OP_LIST_TOMBSTONES`, so a caller watching a session that just got idle-reaped (or
+//! `OP_DESTROY`ed) can still find out how it ended.
+//!
+//! `--state-file PATH` has a background thread periodically write the registry's
+//! metadata (id, name, argv, pid — never `master_fd`) to disk via `persist::write`, and
+//! has startup report what that file says was running via
+//! `persist::report_previous_sessions` before the new registry starts empty. This is
+//! deliberately not session resurrection: once this process's fds are gone, so is any
+//! way to reopen the ptys they pointed at. It's a forensic aid for an operator (or a
+//! restart wrapper) deciding what to relaunch, not a transparent upgrade path.
+//!
+//! `OP_RESIZE` and `OP_SIGNAL` are admin ops on the same control protocol rather than a
+//! separate socket: both act on a session directly (`TIOCSWINSZ`/`SIGWINCH`, or an
+//! arbitrary signal via `pty::signal_child`) without going through whoever's
+//! `OP_ATTACH`ed, so an operator can fix a wedged or gone client's session from a
+//! second connection the same way `OP_DESTROY` already could kill one outright.
+//!
+//! `OP_TAP` hands a session's pty master fd to the caller over `SCM_RIGHTS`
+//! (`send_fd`) instead of relaying its bytes — a recorder or debugger can read (and
+//! write) the terminal directly with no `ptyd` proxying in the middle. Only meaningful
+//! on a plain `AF_UNIX` `--socket` connection, since `SCM_RIGHTS` is a Unix-domain-
+//! socket mechanism; every other transport (`--tcp` without TLS, `handle_tls_connection`,
+//! `handle_ws_connection`, and `handle_ws_tls_connection`) rejects it with a specific
+//! error rather than let the underlying `sendmsg` fail confusingly or, for plain
+//! `--tcp`, attempt `SCM_RIGHTS` over `AF_INET` at all.
+//!
+//! `proto/ptyd.proto` (alongside this crate's `Cargo.toml`) defines a gRPC service
+//! covering part of the `OP_*` protocol above — one RPC each for `OP_CREATE`,
+//! `OP_ATTACH`, `OP_RESIZE`, `OP_SIGNAL`, `OP_DESTROY` and `OP_LIST`, none for `OP_AUTH`,
+//! `OP_BROADCAST`, `OP_LIST_TOMBSTONES` or `OP_TAP` — for orchestration tooling that wants a generated client instead of hand-rolling this
+//! module's framing. It's the wire contract only — there's no gRPC server in this
+//! binary yet. Every transport `run` actually listens on (`--socket`/`--tcp`/`--ws`)
+//! is synchronous: one OS thread per connection, blocking reads, no async runtime
+//! anywhere in this crate. `tonic`, the only mature Rust gRPC server, is built on
+//! `tokio` and a `Service` trait that assumes `async fn` all the way down; standing
+//! one up would mean either running a whole second, async copy of the session registry
+//! and relay logic next to this synchronous one, or rewriting this module onto `tokio`
+//! wholesale — too large a change to fold into the same commit as the contract it'd
+//! implement. The `.proto` is checked in so that rewrite (or a hand-written HTTP/2
+//! front end, if `tokio` turns out to be unwanted) has an agreed-upon shape to build
+//! toward.
+//!
+//! `--sse HOST:PORT` is a second, much smaller front end that exists precisely because
+//! it doesn't need a rewrite: a `GET /sessions/<id>` (`?token=` in place of `OP_AUTH`,
+//! since there's no frame to send one in) attaches exactly like `OP_ATTACH` does —
+//! same registry, `AttachedSink::Sse` fanned out to alongside every other sink kind —
+//! and streams output as base64-framed Server-Sent Events for a browser's
+//! `EventSource` or any dashboard that would rather not implement the binary protocol
+//! at all. Read-only: the connection is only ever read from again to notice it close.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{c_int, pid_t};
+use rustls::ServerConfig;
+
+use crate::daemon;
+use crate::persist::{self, SessionSnapshot};
+use crate::protocol::write_all_fd;
+use crate::pty;
+use crate::replay::ReplayBuffer;
+use crate::sd_notify;
+use crate::session::SessionContext;
+use crate::sse;
+use crate::tls::{self, TlsStream};
+use crate::websocket;
+
+/// Creates a session: payload is a u32be argument count, then for each argument a
+/// u32be length and its bytes — the same argv wire shape `OPCODE_EXEC` uses in
+/// `protocol.rs`. Replies `OP_OK` with the new session's id (UTF-8, no length prefix
+/// needed beyond the frame's own) or `OP_ERROR` with a short reason.
+const OP_CREATE: u8 = 0x01;
+/// Attaches this connection to an existing session until the connection closes. Payload
+/// is the session id, UTF-8. Replies `OP_OK` with an empty payload, then the session's
+/// retained replay backlog (however many bytes that happens to be, written raw with no
+/// length prefix since the connection is about to become a raw relay anyway), then
+/// settles into a raw duplex relay of the session's pty. Replies `OP_ERROR` if the id is
+/// unknown. Joins whoever else is already attached rather than stealing from them —
+/// every attached connection gets the session's output and can send it input.
+const OP_ATTACH: u8 = 0x02;
+/// Signals the session's child (`pty::signal_child` with `SIGHUP`, the same signal
+/// `OPCODE_CLOSE` sends in the default relay) and removes it from the registry right
+/// away, without waiting for the child to exit (see `destroy_session`). Payload is the session id, UTF-8. Replies `OP_OK` or `OP_ERROR`.
+const OP_DESTROY: u8 = 0x03;
+/// Presents the pre-shared token configured via `--token`/`--token-file`, required
+/// before any other op on a connection once either flag is given. Payload is the raw
+/// token bytes. Replies `OP_OK` on a match; on a mismatch, replies `OP_ERROR` and the
+/// caller drops the connection rather than letting it retry indefinitely.
+pub(crate) const OP_AUTH: u8 = 0x04;
+/// No payload. Returns every session currently in the registry, including ones nobody
+/// is attached to — what `ptyd ls` (see `ls.rs`) sends to list what a running `ptyd
+/// serve` is hosting. Replies `OP_OK` with `list_sessions`'s encoding. Gated by the same
+/// `OP_AUTH` check as every other op, since session ids, command lines, and byte counts
+/// are exactly what `--token`/`--token-file` are meant to keep from a stray process.
+pub(crate) const OP_LIST: u8 = 0x05;
+/// Writes the same input to several sessions at once — tmux's synchronize-panes, for
+/// driving multiple shells through identical setup steps with one control frame rather
+/// than one `OP_ATTACH`ed relay per shell. Payload is `decode_broadcast_payload`'s
+/// shape: a u32be count of target ids/names, each length-prefixed, then the raw input
+/// bytes filling out the rest of the frame. Name/id resolution genuinely is
+/// all-or-nothing: every target is resolved before anything is written, so an
+/// `OP_ERROR` naming an unresolved id means no session received any bytes. The actual
+/// `write`s to each target's pty are not transactional, though — once writing starts,
+/// an `OP_ERROR` from a write failure partway through means every target resolved and
+/// listed *before* the failing one already has the bytes. There's no rollback for a
+/// pty write, so a caller that retries the whole broadcast on that kind of `OP_ERROR`
+/// will duplicate input on those earlier targets; it should use `OP_LIST`/idle state
+/// to work out what's already been delivered before deciding whether to retry.
+pub(crate) const OP_BROADCAST: u8 = 0x06;
+/// No payload. Returns every `Tombstone` still within `--tombstone-ttl-ms` of being
+/// reaped — sessions that have since ended, whether by exiting on their own,
+/// `OP_DESTROY`, or `--reap-idle-ms` cutting an idle one loose — in `list_tombstones`'s
+/// encoding. Lets a caller that issued an `OP_DESTROY` or was watching an idle session
+/// confirm how it actually ended without racing the sweep that eventually drops the
+/// record. Gated by the same `OP_AUTH` check as `OP_LIST`.
+pub(crate) const OP_LIST_TOMBSTONES: u8 = 0x07;
+/// Out-of-band admin op: applies a window size to a session's pty and `SIGWINCH`s its
+/// child without going through an `OP_ATTACH`ed relay — for an operator (or a wrapper
+/// script) fixing up a session whose actual client is wedged or gone. Payload is
+/// `decode_resize_payload`'s shape: the session id or name, then `u16be` cols and rows.
+/// Replies `OP_OK` or `OP_ERROR` naming why (unknown session, or the `TIOCSWINSZ` ioctl
+/// itself failing).
+pub(crate) const OP_RESIZE: u8 = 0x08;
+/// Out-of-band admin op: delivers an arbitrary signal to a session's child the same way
+/// `pty::signal_child` is used elsewhere in this module, without attaching. Payload is
+/// the session id or name, then a single signal number byte (`OPCODE_SIGNAL`'s encoding
+/// in `protocol.rs`, reused here). Replies `OP_OK` or `OP_ERROR` if the session doesn't
+/// resolve — `kill`ing a session outright is `OP_DESTROY`, not this op with `SIGKILL`,
+/// since `OP_DESTROY` also removes it from the registry.
+pub(crate) const OP_SIGNAL: u8 = 0x09;
+/// Hands a session's pty master fd to the caller directly, via `SCM_RIGHTS` on the
+/// control connection's own Unix socket, instead of proxying every byte of its output
+/// through an `OP_ATTACH`ed relay — for a recorder or debugger that wants to read (or
+/// write) the terminal itself. Payload is the session id or name, UTF-8. Sends the fd
+/// first, as ancillary data on one `sendmsg` (see `send_fd`), then replies `OP_OK` with
+/// an empty payload; `OP_ERROR` if the session doesn't resolve or the
+/// handoff itself fails, which it always does on a transport that isn't a plain
+/// `AF_UNIX` socket — `SCM_RIGHTS` doesn't survive a TLS-wrapped or websocket-framed
+/// connection, so `handle_tls_connection`/`handle_ws_connection`/
+/// `handle_ws_tls_connection` reject it outright rather than let a confusing kernel
+/// error stand in for that explanation. The handed-off fd stays open and owned by this
+/// session's registry entry too — a tap is a second reader/writer on the same pty, not
+/// a transfer, so `ptyd serve` keeps relaying exactly as it did before the tap.
+pub(crate) const OP_TAP: u8 = 0x0A;
+pub(crate) const OP_OK: u8 = 0x10;
+pub(crate) const OP_ERROR: u8 = 0x11;
+
+struct Session {
+    master_fd: c_int,
+    pid: pid_t,
+    /// Every connection currently `OP_ATTACH`ed to this session — zero, one, or many.
+    /// The session's output pump thread (spawned once, in `create_session`, and never
+    /// restarted) fans out to all of them; each attached connection's own relay thread
+    /// (see `relay_until_detached`/`relay_until_detached_ws`) independently writes its
+    /// client's input to `master_fd`, so simultaneous typists interleave rather than
+    /// racing for exclusive ownership.
+    attached: Vec<AttachedClient>,
+    /// The last `--replay-bytes` of this session's output, attached or not, handed to
+    /// whoever's next `OP_ATTACH` succeeds so reattaching doesn't start from blank.
+    replay: ReplayBuffer,
+    /// The argv `OP_CREATE` started this session with, kept around purely so `OP_LIST`
+    /// has a command line to report — nothing else in this module reads it.
+    argv: Vec<String>,
+    /// The human-readable name `OP_CREATE` gave this session, if any. `OP_ATTACH`/
+    /// `OP_DESTROY` accept this interchangeably with the opaque id (`resolve_session_id`
+    /// tries the id first, then falls back to a name match), and `OP_LIST` reports it.
+    name: Option<String>,
+    /// Lifetime byte counters in each direction, for `OP_LIST`. `bytes_in` is
+    /// everything an attached client has sent toward `master_fd`; `bytes_out` is
+    /// everything the pty has produced, whether or not anyone was attached to receive
+    /// it.
+    bytes_in: u64,
+    bytes_out: u64,
+    /// When this session last saw input or produced output, for `OP_LIST`'s idle time.
+    last_active: Instant,
+    /// Set by `sweep_idle_sessions` right after it sends a detached, idle-too-long
+    /// session `SIGTERM`; cleared if a client attaches before the deadline passes
+    /// (the session's no longer idle, so the escalation is called off) or once the
+    /// deadline passes and `SIGKILL` follows. `None` the rest of the time, including
+    /// for every session `--reap-idle-ms` isn't old enough to have touched yet.
+    reap_deadline: Option<Instant>,
+}
+
+/// One reaped session kept around for `OP_LIST_TOMBSTONES`, independent of whether it
+/// ended itself, was `OP_DESTROY`ed, or was cut loose by `sweep_idle_sessions` — the
+/// same final-status record either way. Swept out of `tombstones` once
+/// `--tombstone-ttl-ms` has passed since `reaped_at`, by `sweep_tombstones`.
+struct Tombstone {
+    name: Option<String>,
+    argv: Vec<String>,
+    exit_code: i32,
+    reaped_at: Instant,
+}
+
+type Tombstones = Arc<Mutex<HashMap<String, Tombstone>>>;
+
+/// Where a session's output goes while attached, and how to reach the fd underneath
+/// it for shutdown/equality purposes regardless of which it is. `Raw` covers both the
+/// Unix and TCP listeners, which relay unframed bytes identically; `WebSocket` wraps
+/// each write in the binary-message framing `websocket.rs` defines, since raw pty bytes
+/// written straight to a browser's socket wouldn't parse as WebSocket frames at all.
+/// `Tls`/`WebSocketTls` are their TLS-wrapped counterparts: writing to the raw fd
+/// directly would write unencrypted bytes past the TLS layer, so these go through the
+/// shared `TlsStream` instead, which is also read from the connection's own thread
+/// during the relay phase — hence the `Mutex`, and the `RawFd` kept alongside purely
+/// for `fd()`/`shut_down()` without needing to lock.
+#[derive(Clone)]
+enum AttachedSink {
+    Raw(RawFd),
+    WebSocket(RawFd),
+    Tls(Arc<Mutex<TlsStream>>, RawFd),
+    WebSocketTls(Arc<Mutex<TlsStream>>, RawFd),
+    /// `--sse`: output framed as Server-Sent Events (see `send`) on a plain `TcpStream`
+    /// whose headers `handle_sse_connection` already wrote. Read-only by construction —
+    /// nothing ever spawns a relay thread for one of these, since an `EventSource`
+    /// client has no way to send input anyway.
+    Sse(RawFd),
+}
+
+impl AttachedSink {
+    fn fd(&self) -> RawFd {
+        match self {
+            AttachedSink::Raw(fd) | AttachedSink::WebSocket(fd) | AttachedSink::Sse(fd) => *fd,
+            AttachedSink::Tls(_, fd) | AttachedSink::WebSocketTls(_, fd) => *fd,
+        }
+    }
+
+    fn send(&self, bytes: &[u8]) -> Result<(), ()> {
+        match self {
+            AttachedSink::Raw(fd) => write_all_fd(*fd, bytes),
+            AttachedSink::WebSocket(fd) => websocket::write_message(*fd, bytes),
+            AttachedSink::Tls(stream, _) => stream.lock().unwrap().write_all(bytes).map_err(|_| ()),
+            AttachedSink::WebSocketTls(stream, _) => websocket::write_message_to(&mut *stream.lock().unwrap(), bytes),
+            // `data: <base64>\n\n` — base64 rather than UTF-8-lossy text so a control
+            // sequence or stray non-UTF-8 byte in the pty's output can't corrupt SSE's
+            // own line-oriented framing (a literal `\n` inside `bytes` would otherwise
+            // end the event early). A dashboard decodes one `atob()` call per message.
+            AttachedSink::Sse(fd) => {
+                let frame = format!("data: {}\n\n", websocket::base64_encode(bytes));
+                write_all_fd(*fd, frame.as_bytes())
+            }
+        }
+    }
+
+    fn shut_down(&self) {
+        unsafe { libc::shutdown(self.fd(), libc::SHUT_RDWR) };
+    }
+}
+
+impl PartialEq for AttachedSink {
+    fn eq(&self, other: &Self) -> bool {
+        self.fd() == other.fd()
+    }
+}
+
+impl Eq for AttachedSink {}
+
+/// How many not-yet-delivered output chunks `AttachedClient`'s writer thread will queue
+/// for one client before that client is considered too far behind to catch up.
+const ATTACHED_CLIENT_QUEUE_CAPACITY: usize = 64;
+
+/// One fan-out target in `Session::attached`: `sink` identifies and eventually writes
+/// to the client, `tx` is the bounded channel the pump thread pushes output chunks onto.
+/// A dedicated thread (spawned alongside this in `attach`) drains `tx` and calls
+/// `sink.send`, so that write — which can block for as long as this client's socket
+/// buffer is full — happens off the pump thread and never delays delivery to any other
+/// attached client. `try_send` failing because the queue is full means this client
+/// can't keep up even with `ATTACHED_CLIENT_QUEUE_CAPACITY` chunks of slack, so it's
+/// dropped rather than left to fall further behind.
+#[derive(Clone)]
+struct AttachedClient {
+    sink: AttachedSink,
+    tx: mpsc::SyncSender<Vec<u8>>,
+}
+
+/// Whether `payload` (an `OP_AUTH` frame's contents) matches the configured token.
+/// `true` unconditionally when no token is configured, so `--socket`/`--tcp`/`--ws`
+/// without `--token`/`--token-file` behave exactly as before this existed.
+fn authenticate(token: &Option<Arc<str>>, payload: &[u8]) -> bool {
+    match token {
+        Some(expected) => constant_time_eq(expected.as_bytes(), payload),
+        None => true,
+    }
+}
+
+/// Compares two byte strings without branching on where they first differ, so a token
+/// check's timing doesn't leak how many leading bytes an attacker's guess got right.
+/// Unequal lengths are rejected up front — that comparison is on the lengths involved,
+/// not the token's contents, so it leaks nothing a remote caller doesn't already know
+/// from having to guess a length at all. Used for every token comparison in this module
+/// (`OP_AUTH`'s payload and `--sse`'s `?token=` query parameter) rather than `==`.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff: u8 = 0;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+type Sessions = Arc<Mutex<HashMap<String, Session>>>;
+
+struct ServeArgs {
+    socket_path: Option<String>,
+    tcp_addr: Option<String>,
+    ws_addr: Option<String>,
+    replay_bytes: usize,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_client_ca: Option<String>,
+    token: Option<String>,
+    token_file: Option<String>,
+    daemonize: bool,
+    pidfile: Option<String>,
+    reap_idle_ms: Option<u64>,
+    reap_grace_ms: u64,
+    tombstone_ttl_ms: u64,
+    state_file: Option<String>,
+    sse_addr: Option<String>,
+}
+
+/// Default `SIGTERM`-to-`SIGKILL` escalation grace for `--reap-idle-ms`, used when
+/// `--reap-grace-ms` isn't given. Same value as `main.rs`'s `DEFAULT_CLOSE_GRACE_MS`,
+/// for the same reason: long enough for a shell to notice and exit on its own, short
+/// enough that a session that ignores `SIGTERM` doesn't linger.
+const DEFAULT_REAP_GRACE_MS: u64 = 2000;
+
+/// Default lifetime of a `Tombstone` before `sweep_tombstones` drops it, used when
+/// `--tombstone-ttl-ms` isn't given.
+const DEFAULT_TOMBSTONE_TTL_MS: u64 = 300_000;
+
+/// How often `sweep_idle_sessions` re-scans the registry for newly-idle sessions.
+const REAP_SWEEP_INTERVAL_MS: u64 = 1000;
+
+/// How often `sweep_tombstones` re-scans `tombstones` for ones past their ttl.
+const TOMBSTONE_SWEEP_INTERVAL_MS: u64 = 10_000;
+
+/// How often the `--state-file` snapshot thread rewrites the file.
+const STATE_SNAPSHOT_INTERVAL_MS: u64 = 5000;
+
+fn parse_args(args: &[String]) -> Option<ServeArgs> {
+    let mut idx = 0;
+    let mut socket_path = None;
+    let mut tcp_addr = None;
+    let mut ws_addr = None;
+    let mut replay_bytes = ReplayBuffer::DEFAULT_CAPACITY;
+    let mut tls_cert = None;
+    let mut tls_key = None;
+    let mut tls_client_ca = None;
+    let mut token = None;
+    let mut token_file = None;
+    let mut daemonize = false;
+    let mut pidfile = None;
+    let mut reap_idle_ms = None;
+    let mut reap_grace_ms = DEFAULT_REAP_GRACE_MS;
+    let mut tombstone_ttl_ms = DEFAULT_TOMBSTONE_TTL_MS;
+    let mut state_file = None;
+    let mut sse_addr = None;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--socket" => {
+                socket_path = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--tcp" => {
+                tcp_addr = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--ws" => {
+                ws_addr = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--replay-bytes" => {
+                replay_bytes = args.get(idx + 1)?.parse().ok()?;
+                idx += 2;
+            }
+            "--tls-cert" => {
+                tls_cert = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--tls-key" => {
+                tls_key = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--tls-client-ca" => {
+                tls_client_ca = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--token" => {
+                token = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--token-file" => {
+                token_file = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--daemon" => {
+                daemonize = true;
+                idx += 1;
+            }
+            "--pidfile" => {
+                pidfile = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--reap-idle-ms" => {
+                reap_idle_ms = Some(args.get(idx + 1)?.parse().ok()?);
+                idx += 2;
+            }
+            "--reap-grace-ms" => {
+                reap_grace_ms = args.get(idx + 1)?.parse().ok()?;
+                idx += 2;
+            }
+            "--tombstone-ttl-ms" => {
+                tombstone_ttl_ms = args.get(idx + 1)?.parse().ok()?;
+                idx += 2;
+            }
+            "--state-file" => {
+                state_file = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            "--sse" => {
+                sse_addr = Some(args.get(idx + 1)?.clone());
+                idx += 2;
+            }
+            _ => return None,
+        }
+    }
+    if socket_path.is_none() && tcp_addr.is_none() && ws_addr.is_none() {
+        return None;
+    }
+    if token.is_some() && token_file.is_some() {
+        return None;
+    }
+    Some(ServeArgs {
+        socket_path,
+        tcp_addr,
+        ws_addr,
+        replay_bytes,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+        token,
+        token_file,
+        daemonize,
+        pidfile,
+        reap_idle_ms,
+        reap_grace_ms,
+        tombstone_ttl_ms,
+        state_file,
+        sse_addr,
+    })
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let Some(ServeArgs {
+        socket_path,
+        tcp_addr,
+        ws_addr,
+        replay_bytes,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+        token,
+        token_file,
+        daemonize,
+        pidfile,
+        reap_idle_ms,
+        reap_grace_ms,
+        tombstone_ttl_ms,
+        state_file,
+        sse_addr,
+    }) =
+        parse_args(args)
+    else {
+        eprintln!(
+            "usage: ptyd serve [--socket <path>] [--tcp <host:port>] [--ws <host:port>] [--sse <host:port>] \
+             [--replay-bytes <n>] \
+             [--tls-cert <path> --tls-key <path> [--tls-client-ca <path>]] [--token <token> | --token-file <path>] \
+             [--daemon] [--pidfile <path>] [--reap-idle-ms <n>] [--reap-grace-ms <n>] [--tombstone-ttl-ms <n>] \
+             [--state-file <path>]"
+        );
+        return 2;
+    };
+
+    if let Some(state_file) = &state_file {
+        persist::report_previous_sessions(state_file);
+    }
+
+    if daemonize {
+        if let Err(err) = daemon::daemonize() {
+            eprintln!("ptyd serve: failed to daemonize: {err}");
+            return 1;
+        }
+    }
+    if let Some(pidfile) = &pidfile {
+        if let Err(err) = daemon::write_pidfile(pidfile) {
+            eprintln!("ptyd serve: {err}");
+            return 1;
+        }
+    }
+
+    let tls_config = match (&tls_cert, &tls_key) {
+        (Some(cert), Some(key)) => match tls::load(cert, key, tls_client_ca.as_deref()) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                eprintln!("ptyd serve: {err}");
+                return 1;
+            }
+        },
+        (None, None) => None,
+        _ => {
+            eprintln!("ptyd serve: --tls-cert and --tls-key must be given together");
+            return 2;
+        }
+    };
+
+    let token: Option<Arc<str>> = match (&token, &token_file) {
+        (Some(token), None) => Some(Arc::from(token.as_str())),
+        (None, Some(path)) => match std::fs::read_to_string(path) {
+            Ok(contents) => Some(Arc::from(contents.trim_end_matches('\n'))),
+            Err(err) => {
+                eprintln!("ptyd serve: failed to read {path}: {err}");
+                return 1;
+            }
+        },
+        (None, None) => None,
+        (Some(_), Some(_)) => unreachable!("parse_args rejects --token with --token-file"),
+    };
+
+    let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+    let tombstones: Tombstones = Arc::new(Mutex::new(HashMap::new()));
+    let mut listener_threads = Vec::new();
+
+    if let Some(socket_path) = socket_path {
+        // A stale socket left behind by a prior crashed run would otherwise make `bind`
+        // fail with "address in use"; a daemon that's actually still alive and holding
+        // the path is unaffected, since unlinking the path doesn't disturb a listener
+        // that's already accepting on the fd it opened it with.
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = match UnixListener::bind(&socket_path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("ptyd serve: failed to bind {socket_path}: {err}");
+                return 1;
+            }
+        };
+        let sessions = Arc::clone(&sessions);
+        let tombstones = Arc::clone(&tombstones);
+        let token = token.clone();
+        listener_threads.push(thread::spawn(move || {
+            for incoming in listener.incoming() {
+                let Ok(stream) = incoming else { continue };
+                let sessions = Arc::clone(&sessions);
+                let tombstones = Arc::clone(&tombstones);
+                let token = token.clone();
+                thread::spawn(move || handle_connection(stream, sessions, tombstones, replay_bytes, token, true));
+            }
+        }));
+    }
+
+    if let Some(tcp_addr) = tcp_addr {
+        let listener = match TcpListener::bind(&tcp_addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("ptyd serve: failed to bind {tcp_addr}: {err}");
+                return 1;
+            }
+        };
+        let sessions = Arc::clone(&sessions);
+        let tombstones = Arc::clone(&tombstones);
+        let tls_config = tls_config.clone();
+        let token = token.clone();
+        listener_threads.push(thread::spawn(move || {
+            for incoming in listener.incoming() {
+                let Ok(stream) = incoming else { continue };
+                let sessions = Arc::clone(&sessions);
+                let tombstones = Arc::clone(&tombstones);
+                let token = token.clone();
+                match tls_config.clone() {
+                    Some(tls_config) => {
+                        thread::spawn(move || {
+                            handle_tls_connection(stream, sessions, tombstones, replay_bytes, tls_config, token)
+                        });
+                    }
+                    None => {
+                        thread::spawn(move || {
+                            handle_connection(stream, sessions, tombstones, replay_bytes, token, false)
+                        });
+                    }
+                }
+            }
+        }));
+    }
+
+    if let Some(ws_addr) = ws_addr {
+        let listener = match TcpListener::bind(&ws_addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("ptyd serve: failed to bind {ws_addr}: {err}");
+                return 1;
+            }
+        };
+        let sessions = Arc::clone(&sessions);
+        let tombstones = Arc::clone(&tombstones);
+        let tls_config = tls_config.clone();
+        let token = token.clone();
+        listener_threads.push(thread::spawn(move || {
+            for incoming in listener.incoming() {
+                let Ok(stream) = incoming else { continue };
+                let sessions = Arc::clone(&sessions);
+                let tombstones = Arc::clone(&tombstones);
+                let token = token.clone();
+                match tls_config.clone() {
+                    Some(tls_config) => {
+                        thread::spawn(move || {
+                            handle_ws_tls_connection(stream, sessions, tombstones, replay_bytes, tls_config, token)
+                        });
+                    }
+                    None => {
+                        thread::spawn(move || handle_ws_connection(stream, sessions, tombstones, replay_bytes, token));
+                    }
+                }
+            }
+        }));
+    }
+
+    if let Some(sse_addr) = sse_addr {
+        let listener = match TcpListener::bind(&sse_addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("ptyd serve: failed to bind {sse_addr}: {err}");
+                return 1;
+            }
+        };
+        let sessions = Arc::clone(&sessions);
+        let token = token.clone();
+        listener_threads.push(thread::spawn(move || {
+            for incoming in listener.incoming() {
+                let Ok(stream) = incoming else { continue };
+                let sessions = Arc::clone(&sessions);
+                let token = token.clone();
+                thread::spawn(move || handle_sse_connection(stream, sessions, token));
+            }
+        }));
+    }
+
+    // Every configured listener is bound by this point (each `--socket`/`--tcp`/`--ws`
+    // block above returns early on a bind failure), so this is "ready" in the sense
+    // systemd's `Type=notify` unit cares about: able to accept the connections the unit
+    // promised to be up for.
+    sd_notify::notify("READY=1");
+    if let Some(interval) = sd_notify::watchdog_interval() {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            sd_notify::notify("WATCHDOG=1");
+        });
+    }
+
+    if let Some(reap_idle_ms) = reap_idle_ms {
+        let sessions = Arc::clone(&sessions);
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(REAP_SWEEP_INTERVAL_MS));
+            sweep_idle_sessions(&sessions, reap_idle_ms, reap_grace_ms);
+        });
+    }
+    // Tombstones pile up from `OP_DESTROY` and ordinary exits too, not just
+    // `--reap-idle-ms`, so this sweep runs unconditionally.
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(TOMBSTONE_SWEEP_INTERVAL_MS));
+        sweep_tombstones(&tombstones, tombstone_ttl_ms);
+    });
+
+    if let Some(state_file) = state_file {
+        let sessions = Arc::clone(&sessions);
+        thread::spawn(move || loop {
+            persist::write(&state_file, &snapshot_sessions(&sessions));
+            thread::sleep(Duration::from_millis(STATE_SNAPSHOT_INTERVAL_MS));
+        });
+    }
+
+    for thread in listener_threads {
+        let _ = thread.join();
+    }
+    0
+}
+
+fn handle_connection<S: Read + Write + AsRawFd>(
+    mut stream: S,
+    sessions: Sessions,
+    tombstones: Tombstones,
+    replay_bytes: usize,
+    token: Option<Arc<str>>,
+    supports_fd_handoff: bool,
+) {
+    let mut authenticated = token.is_none();
+    loop {
+        let Some((op, payload)) = read_control_frame(&mut stream) else { return };
+        if op == OP_AUTH {
+            authenticated = authenticate(&token, &payload);
+            if authenticated {
+                write_frame(&mut stream, OP_OK, &[]);
+            } else {
+                write_frame(&mut stream, OP_ERROR, b"bad token");
+                return;
+            }
+            continue;
+        }
+        if !authenticated {
+            write_frame(&mut stream, OP_ERROR, b"authentication required");
+            return;
+        }
+        match op {
+            OP_CREATE => {
+                let Some((name, get_or_create, argv)) = decode_create_payload(&payload) else {
+                    write_frame(&mut stream, OP_ERROR, b"malformed create payload");
+                    continue;
+                };
+                match create_or_get_session(&sessions, &tombstones, name, get_or_create, argv, replay_bytes) {
+                    Ok(id) => write_frame(&mut stream, OP_OK, id.as_bytes()),
+                    Err(reason) => write_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_ATTACH => {
+                let session_id = String::from_utf8_lossy(&payload).into_owned();
+                let sink = AttachedSink::Raw(stream.as_raw_fd());
+                match attach(&sessions, &session_id, sink) {
+                    Ok((id, backlog)) => {
+                        write_frame(&mut stream, OP_OK, &[]);
+                        let _ = write_all_fd(stream.as_raw_fd(), &backlog);
+                        relay_until_detached(&mut stream, &sessions, &id);
+                        return;
+                    }
+                    Err(reason) => write_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_DESTROY => {
+                let session_id = String::from_utf8_lossy(&payload).into_owned();
+                match destroy_session(&sessions, &session_id) {
+                    Ok(()) => write_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_LIST => write_frame(&mut stream, OP_OK, &list_sessions(&sessions)),
+            OP_LIST_TOMBSTONES => write_frame(&mut stream, OP_OK, &list_tombstones(&tombstones)),
+            OP_BROADCAST => {
+                let Some((ids, data)) = decode_broadcast_payload(&payload) else {
+                    write_frame(&mut stream, OP_ERROR, b"malformed broadcast payload");
+                    continue;
+                };
+                match broadcast_input(&sessions, &ids, data) {
+                    Ok(()) => write_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_RESIZE => {
+                let Some((id, cols, rows)) = decode_resize_payload(&payload) else {
+                    write_frame(&mut stream, OP_ERROR, b"malformed resize payload");
+                    continue;
+                };
+                match resize_session(&sessions, &id, cols, rows) {
+                    Ok(()) => write_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_SIGNAL => {
+                let Some((id, signal)) = decode_signal_payload(&payload) else {
+                    write_frame(&mut stream, OP_ERROR, b"malformed signal payload");
+                    continue;
+                };
+                match signal_session(&sessions, &id, signal) {
+                    Ok(()) => write_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_TAP if !supports_fd_handoff => {
+                write_frame(&mut stream, OP_ERROR, b"fd handoff requires a plain --socket connection");
+            }
+            OP_TAP => {
+                let session_id = String::from_utf8_lossy(&payload).into_owned();
+                match tap_session(&sessions, &session_id) {
+                    Ok(master_fd) => match send_fd(stream.as_raw_fd(), master_fd) {
+                        Ok(()) => write_frame(&mut stream, OP_OK, &[]),
+                        Err(err) => {
+                            write_frame(&mut stream, OP_ERROR, format!("fd handoff failed: {err}").as_bytes())
+                        }
+                    },
+                    Err(reason) => write_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            _ => write_frame(&mut stream, OP_ERROR, b"unknown op"),
+        }
+    }
+}
+
+/// Reads one `[op:1][u32be len][len bytes]` control frame, blocking. `None` on EOF or
+/// any read error, which `handle_connection` treats as the connection being done. Also
+/// `ls.rs`'s read half, talking the same protocol from the client side.
+pub(crate) fn read_control_frame<S: Read>(stream: &mut S) -> Option<(u8, Vec<u8>)> {
+    let mut header = [0_u8; 5];
+    stream.read_exact(&mut header).ok()?;
+    let op = header[0];
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    let mut payload = vec![0_u8; len];
+    stream.read_exact(&mut payload).ok()?;
+    Some((op, payload))
+}
+
+pub(crate) fn write_frame<S: Write>(stream: &mut S, op: u8, payload: &[u8]) {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(op);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    let _ = stream.write_all(&frame);
+}
+
+/// Decodes `OP_CREATE`'s argv payload: a u32be argument count, then for each argument a
+/// u32be length and its bytes — `OPCODE_EXEC`'s wire shape in `protocol.rs`, reused here
+/// since the whole payload is already bounded by the outer control frame's length
+/// rather than arriving incrementally.
+fn decode_argv(payload: &[u8]) -> Option<Vec<String>> {
+    if payload.len() < 4 {
+        return None;
+    }
+    let argc = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+    let mut pos = 4;
+    let mut argv = Vec::with_capacity(argc);
+    for _ in 0..argc {
+        if payload.len() < pos + 4 {
+            return None;
+        }
+        let len = u32::from_be_bytes([payload[pos], payload[pos + 1], payload[pos + 2], payload[pos + 3]]) as usize;
+        pos += 4;
+        if payload.len() < pos + len {
+            return None;
+        }
+        argv.push(String::from_utf8(payload[pos..pos + len].to_vec()).ok()?);
+        pos += len;
+    }
+    if argv.is_empty() {
+        return None;
+    }
+    Some(argv)
+}
+
+/// Decodes `OP_CREATE`'s payload: an optional name (`u32be` length then bytes, `0`
+/// meaning anonymous — the whole payload's shape before names existed), a `u8` conflict
+/// policy (`0` = error if the name's already taken, nonzero = hand back the existing
+/// session instead), then the argv in `decode_argv`'s existing wire shape.
+fn decode_create_payload(payload: &[u8]) -> Option<(Option<String>, bool, Vec<String>)> {
+    if payload.len() < 4 {
+        return None;
+    }
+    let name_len = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+    let mut pos = 4;
+    let name = if name_len > 0 {
+        let bytes = payload.get(pos..pos + name_len)?;
+        let name = String::from_utf8(bytes.to_vec()).ok()?;
+        pos += name_len;
+        Some(name)
+    } else {
+        None
+    };
+    let get_or_create = *payload.get(pos)? != 0;
+    pos += 1;
+    let argv = decode_argv(payload.get(pos..)?)?;
+    Some((name, get_or_create, argv))
+}
+
+/// Decodes `OP_BROADCAST`'s payload: a u32be count of target ids/names, each a u32be
+/// length and its bytes (`decode_argv`'s shape, reused for the same reason), then
+/// whatever's left of the payload is the input to write to every one of them verbatim.
+fn decode_broadcast_payload(payload: &[u8]) -> Option<(Vec<String>, &[u8])> {
+    if payload.len() < 4 {
+        return None;
+    }
+    let count = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+    let mut pos = 4;
+    let mut ids = Vec::with_capacity(count);
+    for _ in 0..count {
+        if payload.len() < pos + 4 {
+            return None;
+        }
+        let len = u32::from_be_bytes([payload[pos], payload[pos + 1], payload[pos + 2], payload[pos + 3]]) as usize;
+        pos += 4;
+        if payload.len() < pos + len {
+            return None;
+        }
+        ids.push(String::from_utf8(payload[pos..pos + len].to_vec()).ok()?);
+        pos += len;
+    }
+    if ids.is_empty() {
+        return None;
+    }
+    Some((ids, &payload[pos..]))
+}
+
+/// Writes `data` to every session in `ids` (each resolved via `resolve_session_id`, so
+/// ids and names are interchangeable just like `OP_ATTACH`/`OP_DESTROY`) as pty input.
+/// Resolves every target before writing to any of them, so an unresolved id fails the
+/// whole batch before any session sees the bytes. Once writing starts, though, each
+/// `write_all_fd` happens in order with no rollback: a failure partway through leaves
+/// the earlier targets already written to, so the returned `Err` does not mean nothing
+/// happened — see `OP_BROADCAST`'s doc comment.
+///
+/// A resolved id can still vanish (destroyed, reaped idle, or the child simply exited)
+/// before the fd-collection lock below is taken; like every sibling lookup
+/// (`resize_session`, `signal_session`, `tap_session`, `destroy_session`), that's
+/// handled with `map.get` rather than indexing, since indexing a missing key would
+/// panic while holding the lock and poison the `Mutex` for the rest of the daemon. A
+/// target that vanished between resolution and this lock is simply dropped from the
+/// batch rather than failing it outright — it's already gone, so there's nothing left
+/// to write to or roll back.
+fn broadcast_input(sessions: &Sessions, ids: &[String], data: &[u8]) -> Result<(), String> {
+    let mut resolved = Vec::with_capacity(ids.len());
+    for id in ids {
+        resolved.push(resolve_session_id(sessions, id).ok_or_else(|| format!("no such session: {id}"))?);
+    }
+    let targets: Vec<(String, c_int)> = {
+        let map = sessions.lock().unwrap();
+        resolved.into_iter().filter_map(|id| map.get(&id).map(|s| (id, s.master_fd))).collect()
+    };
+    for (id, master_fd) in &targets {
+        if write_all_fd(*master_fd, data).is_err() {
+            return Err(format!("failed to write to session {id}"));
+        }
+        record_input(sessions, id, data.len() as u64);
+    }
+    Ok(())
+}
+
+/// Decodes `OP_RESIZE`'s payload: the target session id or name (u32be length then
+/// bytes), then `u16be` cols and rows.
+fn decode_resize_payload(payload: &[u8]) -> Option<(String, u16, u16)> {
+    let mut pos = 0;
+    let len = u32::from_be_bytes(payload.get(pos..pos + 4)?.try_into().ok()?) as usize;
+    pos += 4;
+    let id = String::from_utf8(payload.get(pos..pos + len)?.to_vec()).ok()?;
+    pos += len;
+    let cols = u16::from_be_bytes(payload.get(pos..pos + 2)?.try_into().ok()?);
+    pos += 2;
+    let rows = u16::from_be_bytes(payload.get(pos..pos + 2)?.try_into().ok()?);
+    Some((id, cols, rows))
+}
+
+/// Decodes `OP_SIGNAL`'s payload: the target session id or name (u32be length then
+/// bytes), then a single signal number byte.
+fn decode_signal_payload(payload: &[u8]) -> Option<(String, i32)> {
+    let mut pos = 0;
+    let len = u32::from_be_bytes(payload.get(pos..pos + 4)?.try_into().ok()?) as usize;
+    pos += 4;
+    let id = String::from_utf8(payload.get(pos..pos + len)?.to_vec()).ok()?;
+    pos += len;
+    let signal = *payload.get(pos)? as i32;
+    Some((id, signal))
+}
+
+/// Applies `cols`/`rows` to `id_or_name`'s pty directly, the `OP_RESIZE` admin op's
+/// whole job — no debounce, no xpixel/ypixel (unlike `OPCODE_RESIZE`'s in-band path in
+/// `protocol.rs`, which a real terminal client drives continuously during a window
+/// drag), since this is a one-shot fix applied out of band rather than a live resize
+/// stream.
+fn resize_session(sessions: &Sessions, id_or_name: &str, cols: u16, rows: u16) -> Result<(), String> {
+    let id = resolve_session_id(sessions, id_or_name).ok_or_else(|| "no such session".to_string())?;
+    let map = sessions.lock().unwrap();
+    let session = map.get(&id).ok_or_else(|| "no such session".to_string())?;
+    crate::protocol::apply_resize(session.master_fd, session.pid, cols, rows, 0, 0)
+        .map_err(|()| "resize ioctl failed".to_string())
+}
+
+/// Delivers `signal` to `id_or_name`'s child via `pty::signal_child`, the same helper
+/// `OP_DESTROY` uses for its `SIGHUP`. Unlike `OP_DESTROY`, never touches the registry —
+/// a session signaled this way stays put (attached, unattached, running, or exited and
+/// awaiting `spawn_pump`'s reap) until something else removes it.
+fn signal_session(sessions: &Sessions, id_or_name: &str, signal: i32) -> Result<(), String> {
+    let id = resolve_session_id(sessions, id_or_name).ok_or_else(|| "no such session".to_string())?;
+    let map = sessions.lock().unwrap();
+    let session = map.get(&id).ok_or_else(|| "no such session".to_string())?;
+    pty::signal_child(session.pid, signal);
+    Ok(())
+}
+
+/// Looks up `id_or_name`'s pty master fd for `OP_TAP`, without removing or otherwise
+/// disturbing the session — the fd handed back is still owned and used by the
+/// session's pump thread exactly as before; `send_fd` only ever hands the receiver a
+/// second reference to the same underlying file description.
+fn tap_session(sessions: &Sessions, id_or_name: &str) -> Result<c_int, String> {
+    let id = resolve_session_id(sessions, id_or_name).ok_or_else(|| "no such session".to_string())?;
+    let map = sessions.lock().unwrap();
+    let session = map.get(&id).ok_or_else(|| "no such session".to_string())?;
+    Ok(session.master_fd)
+}
+
+/// Sends `fd_to_send` to whoever's on the other end of `socket_fd` via `SCM_RIGHTS`,
+/// the standard way to pass a file descriptor across a Unix domain socket — the
+/// receiving process gets a new fd of its own pointing at the same file description,
+/// so it can read and write the pty master directly without ptyd relaying a byte of
+/// it. A single null byte rides along as the ordinary message payload since `sendmsg`
+/// requires at least one byte of real data for the ancillary data to attach to; the
+/// receiver's `recvmsg` discards it and reads the fd out of the control message.
+/// Returns the raw `sendmsg` error untouched (wrapped in `io::Error::last_os_error`).
+/// It does not check the socket's family itself: `handle_connection` never calls it for
+/// a `--tcp` connection, and the other transports answer `OP_TAP` with `OP_ERROR`
+/// before any handoff is attempted.
+fn send_fd(socket_fd: RawFd, fd_to_send: RawFd) -> std::io::Result<()> {
+    unsafe {
+        let mut iov_byte = [0_u8];
+        let mut iov = libc::iovec { iov_base: iov_byte.as_mut_ptr() as *mut libc::c_void, iov_len: 1 };
+        let cmsg_len = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as usize;
+        let mut cmsg_buf = vec![0_u8; cmsg_len];
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_len as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        (libc::CMSG_DATA(cmsg) as *mut RawFd).write(fd_to_send);
+        if libc::sendmsg(socket_fd, &msg, 0) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Resolves a client-supplied identifier to a registry key for `OP_ATTACH`/
+/// `OP_DESTROY`: an exact id match first (the common case once a session exists), then
+/// a fallback scan for a session whose `name` matches — so either the opaque id
+/// `OP_CREATE` returned or the human-readable name it was given works interchangeably,
+/// with no wire-format distinction between the two. A linear scan rather than a
+/// name-to-id index, since session counts are small and a second index would just be
+/// another thing to keep in sync with the map under the same lock.
+fn resolve_session_id(sessions: &Sessions, id_or_name: &str) -> Option<String> {
+    let map = sessions.lock().unwrap();
+    if map.contains_key(id_or_name) {
+        return Some(id_or_name.to_string());
+    }
+    map.iter().find(|(_, session)| session.name.as_deref() == Some(id_or_name)).map(|(id, _)| id.clone())
+}
+
+/// Encodes one `OP_LIST` entry: the session id, its name if it has one, its full argv
+/// (`decode_argv`'s wire shape, written rather than read), pid, the pty's current
+/// `TIOCGWINSZ` (best-effort, `0x0` if the ioctl fails), how many clients are currently
+/// attached, how long it's been idle, and the lifetime byte counters in each direction.
+fn encode_session_info(id: &str, session: &Session) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(id.len() as u32).to_be_bytes());
+    out.extend_from_slice(id.as_bytes());
+    match &session.name {
+        Some(name) => {
+            out.push(1);
+            out.extend_from_slice(&(name.len() as u32).to_be_bytes());
+            out.extend_from_slice(name.as_bytes());
+        }
+        None => out.push(0),
+    }
+    out.extend_from_slice(&(session.argv.len() as u32).to_be_bytes());
+    for arg in &session.argv {
+        out.extend_from_slice(&(arg.len() as u32).to_be_bytes());
+        out.extend_from_slice(arg.as_bytes());
+    }
+    out.extend_from_slice(&session.pid.to_be_bytes());
+    let (cols, rows) = crate::protocol::read_winsize(session.master_fd)
+        .map(|(cols, rows, _, _)| (cols, rows))
+        .unwrap_or((0, 0));
+    out.extend_from_slice(&cols.to_be_bytes());
+    out.extend_from_slice(&rows.to_be_bytes());
+    out.extend_from_slice(&(session.attached.len() as u32).to_be_bytes());
+    out.extend_from_slice(&(session.last_active.elapsed().as_millis() as u64).to_be_bytes());
+    out.extend_from_slice(&session.bytes_in.to_be_bytes());
+    out.extend_from_slice(&session.bytes_out.to_be_bytes());
+    out
+}
+
+/// Builds the full `OP_LIST` response payload: a u32be count, then each session's
+/// `encode_session_info`, sorted by id for a stable, diffable ordering across calls.
+fn list_sessions(sessions: &Sessions) -> Vec<u8> {
+    let map = sessions.lock().unwrap();
+    let mut ids: Vec<&String> = map.keys().collect();
+    ids.sort();
+    let mut out = Vec::new();
+    out.extend_from_slice(&(ids.len() as u32).to_be_bytes());
+    for id in ids {
+        out.extend_from_slice(&encode_session_info(id, &map[id]));
+    }
+    out
+}
+
+/// `OP_CREATE`'s full get-or-create semantics. An anonymous create (`name` is `None`)
+/// always creates, exactly as before names existed. A named create where the name is
+/// already taken either errors (`get_or_create` false — the default, and the right
+/// choice for a caller that wants to know it's starting something new) or returns the
+/// existing session's id without starting anything (`get_or_create` true — what lets a
+/// script idempotently "get me the `build` session, creating it the first time").
+///
+/// The name-uniqueness check and the registry insert happen under the *same* lock
+/// acquisition, not two separate ones: `pty::open_pty`/`fork_and_exec` run lock-free
+/// (forking while holding the sessions mutex would stall every other connection for
+/// the duration), so the name can only be checked for real right before inserting.
+/// Two concurrent creates for the same name will still both fork a child, but only the
+/// first to reach the insert wins the name — the loser's child is killed and its pty
+/// closed rather than left running as an orphaned, unreachable session.
+fn create_or_get_session(
+    sessions: &Sessions,
+    tombstones: &Tombstones,
+    name: Option<String>,
+    get_or_create: bool,
+    argv: Vec<String>,
+    replay_bytes: usize,
+) -> Result<String, String> {
+    if let Some(name) = &name {
+        if let Some(id) = resolve_session_id(sessions, name) {
+            return if get_or_create { Ok(id) } else { Err(format!("session name {name:?} is already in use")) };
+        }
+    }
+
+    let pair = pty::open_pty().ok_or_else(|| "failed to open pty".to_string())?;
+    let master_fd = pair.master_fd;
+    let pid = pty::fork_and_exec(&argv, pair.master_fd, pair.slave_fd).ok_or_else(|| "failed to fork".to_string())?;
+    let id = SessionContext::new(None).id;
+    let name_for_pump = name.clone();
+    let argv_for_pump = argv.clone();
+
+    {
+        let mut map = sessions.lock().unwrap();
+        if let Some(name) = &name {
+            if let Some(existing_id) = map.iter().find(|(_, s)| s.name.as_deref() == Some(name.as_str())).map(|(id, _)| id.clone()) {
+                pty::signal_child(pid, libc::SIGKILL);
+                unsafe { libc::close(master_fd) };
+                return if get_or_create { Ok(existing_id) } else { Err(format!("session name {name:?} is already in use")) };
+            }
+        }
+        let session = Session {
+            master_fd,
+            pid,
+            attached: Vec::new(),
+            replay: ReplayBuffer::new(replay_bytes),
+            argv,
+            name,
+            bytes_in: 0,
+            bytes_out: 0,
+            last_active: Instant::now(),
+            reap_deadline: None,
+        };
+        map.insert(id.clone(), session);
+    }
+    spawn_pump(Arc::clone(sessions), Arc::clone(tombstones), id.clone(), master_fd, pid, name_for_pump, argv_for_pump);
+    Ok(id)
+}
+
+/// The one thread that ever reads a session's `master_fd`, for the session's whole
+/// lifetime — started once here rather than per-`OP_ATTACH`, so an attach/detach/
+/// reattach cycle never risks two threads racing to read the same fd (see `attached`
+/// on `Session`). Exits once the child does, reaping it, removing the session from the
+/// registry, and recording a `Tombstone` for it — whether the child exited on its own,
+/// was `OP_DESTROY`ed, or was cut loose by `sweep_idle_sessions`, this is the one place
+/// that sees every session's actual end, so it's the one place that records one.
+/// `name`/`argv` are captured at spawn time rather than read back out of the registry
+/// here, since `OP_DESTROY` may have already removed the session by the time this fires.
+fn spawn_pump(
+    sessions: Sessions,
+    tombstones: Tombstones,
+    session_id: String,
+    master_fd: c_int,
+    pid: pid_t,
+    name: Option<String>,
+    argv: Vec<String>,
+) {
+    thread::spawn(move || {
+        let mut buf = [0_u8; 65_536];
+        loop {
+            let n = unsafe { libc::read(master_fd, buf.as_mut_ptr().cast(), buf.len()) };
+            if n <= 0 {
+                // Whoever actually removes the entry from the map owns closing
+                // `master_fd` — if it's already gone, `destroy_session` won the race
+                // and closed it itself, so closing it again here would be a double
+                // close (and a possible fd-reuse hazard if some other open() has
+                // already claimed the number).
+                let removed = sessions.lock().unwrap().remove(&session_id);
+                let removed_here = removed.is_some();
+                let attached = removed.map(|s| s.attached).unwrap_or_default();
+                let (exit_code, _, _) = crate::crash::reap(pid);
+                if removed_here {
+                    unsafe { libc::close(master_fd) };
+                }
+                for client in attached {
+                    client.sink.shut_down();
+                }
+                record_tombstone(&tombstones, session_id, name, argv, exit_code);
+                return;
+            }
+            let mut map = sessions.lock().unwrap();
+            let Some(session) = map.get_mut(&session_id) else { return };
+            session.replay.push(&buf[..n as usize]);
+            session.bytes_out += n as u64;
+            session.last_active = Instant::now();
+            let clients = session.attached.clone();
+            drop(map);
+            let mut lagging = Vec::new();
+            for client in &clients {
+                if client.tx.try_send(buf[..n as usize].to_vec()).is_err() {
+                    lagging.push(client.sink.fd());
+                }
+            }
+            if !lagging.is_empty() {
+                let mut map = sessions.lock().unwrap();
+                if let Some(session) = map.get_mut(&session_id) {
+                    session.attached.retain(|client| !lagging.contains(&client.sink.fd()));
+                }
+            }
+        }
+    });
+}
+
+/// Attaches `sink` to the session, joining whoever's already attached rather than
+/// stealing from them — any number of connections can be attached to the same session
+/// at once, every one of them getting the pump thread's output and able to send input.
+/// `id_or_name` is resolved via `resolve_session_id` first, so a caller can attach by
+/// the opaque id or by the name `OP_CREATE` gave the session; the resolved id is
+/// returned alongside the replay backlog so the caller's subsequent relay loop looks
+/// the session up by id rather than re-resolving the name on every read. Spawns the
+/// per-client writer thread that drains `AttachedClient::tx` (see its doc comment for
+/// why delivery is queued rather than written directly from the pump thread).
+fn attach(sessions: &Sessions, id_or_name: &str, sink: AttachedSink) -> Result<(String, Vec<u8>), String> {
+    let id = resolve_session_id(sessions, id_or_name).ok_or_else(|| "no such session".to_string())?;
+    let mut map = sessions.lock().unwrap();
+    let session = map.get_mut(&id).ok_or_else(|| "no such session".to_string())?;
+    let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(ATTACHED_CLIENT_QUEUE_CAPACITY);
+    let writer_sink = sink.clone();
+    thread::spawn(move || {
+        while let Ok(chunk) = rx.recv() {
+            if writer_sink.send(&chunk).is_err() {
+                break;
+            }
+        }
+    });
+    session.attached.push(AttachedClient { sink, tx });
+    Ok((id.clone(), session.replay.snapshot()))
+}
+
+/// Relays `stream` into the session's pty master until the client disconnects, then
+/// removes just this client from `attached` — any other clients still attached keep
+/// getting output uninterrupted. Does not touch the session's pump thread, which keeps
+/// running regardless, nor any other attached client's own relay thread.
+fn relay_until_detached<S: AsRawFd>(stream: &mut S, sessions: &Sessions, session_id: &str) {
+    let client_fd = stream.as_raw_fd();
+    let master_fd = match sessions.lock().unwrap().get(session_id) {
+        Some(session) => session.master_fd,
+        None => return,
+    };
+
+    let mut buf = [0_u8; 65_536];
+    loop {
+        let n = unsafe { libc::read(client_fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        if write_all_fd(master_fd, &buf[..n as usize]).is_err() {
+            break;
+        }
+        record_input(sessions, session_id, n as u64);
+    }
+
+    clear_attached_if(sessions, session_id, client_fd);
+}
+
+/// `relay_until_detached`'s WebSocket counterpart: client input arrives as whole binary
+/// messages (`websocket::read_message`) rather than raw bytes, since the socket's byte
+/// stream is WebSocket frames, not pty input — decoding each message's payload before
+/// forwarding it to `master_fd` is what makes this a relay and not a way to feed frame
+/// headers into the child's terminal.
+fn relay_until_detached_ws(stream: &mut std::net::TcpStream, sessions: &Sessions, session_id: &str) {
+    let client_fd = stream.as_raw_fd();
+    let master_fd = match sessions.lock().unwrap().get(session_id) {
+        Some(session) => session.master_fd,
+        None => return,
+    };
+
+    while let Some(payload) = websocket::read_message(stream) {
+        if write_all_fd(master_fd, &payload).is_err() {
+            break;
+        }
+        record_input(sessions, session_id, payload.len() as u64);
+    }
+
+    clear_attached_if(sessions, session_id, client_fd);
+}
+
+/// `handle_connection`'s TLS counterpart: the same `OP_*` control protocol, but over a
+/// `TlsStream` shared via `Arc<Mutex<_>>` with the `AttachedSink::Tls` installed on
+/// `OP_ATTACH`, since the session's pump thread needs to reach the same encrypted
+/// stream to send output once attached.
+fn handle_tls_connection(
+    tcp: TcpStream,
+    sessions: Sessions,
+    tombstones: Tombstones,
+    replay_bytes: usize,
+    tls_config: Arc<ServerConfig>,
+    token: Option<Arc<str>>,
+) {
+    let Ok(stream) = TlsStream::accept(tcp, tls_config) else { return };
+    let client_fd = stream.as_raw_fd();
+    let stream = Arc::new(Mutex::new(stream));
+    let mut authenticated = token.is_none();
+    loop {
+        let Some((op, payload)) = read_control_frame(&mut *stream.lock().unwrap()) else { return };
+        if op == OP_AUTH {
+            authenticated = authenticate(&token, &payload);
+            if authenticated {
+                write_frame(&mut *stream.lock().unwrap(), OP_OK, &[]);
+            } else {
+                write_frame(&mut *stream.lock().unwrap(), OP_ERROR, b"bad token");
+                return;
+            }
+            continue;
+        }
+        if !authenticated {
+            write_frame(&mut *stream.lock().unwrap(), OP_ERROR, b"authentication required");
+            return;
+        }
+        match op {
+            OP_CREATE => {
+                let Some((name, get_or_create, argv)) = decode_create_payload(&payload) else {
+                    write_frame(&mut *stream.lock().unwrap(), OP_ERROR, b"malformed create payload");
+                    continue;
+                };
+                match create_or_get_session(&sessions, &tombstones, name, get_or_create, argv, replay_bytes) {
+                    Ok(id) => write_frame(&mut *stream.lock().unwrap(), OP_OK, id.as_bytes()),
+                    Err(reason) => write_frame(&mut *stream.lock().unwrap(), OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_ATTACH => {
+                let session_id = String::from_utf8_lossy(&payload).into_owned();
+                let sink = AttachedSink::Tls(Arc::clone(&stream), client_fd);
+                match attach(&sessions, &session_id, sink) {
+                    Ok((id, backlog)) => {
+                        write_frame(&mut *stream.lock().unwrap(), OP_OK, &[]);
+                        let _ = stream.lock().unwrap().write_all(&backlog);
+                        relay_until_detached_tls(&stream, &sessions, &id);
+                        return;
+                    }
+                    Err(reason) => write_frame(&mut *stream.lock().unwrap(), OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_DESTROY => {
+                let session_id = String::from_utf8_lossy(&payload).into_owned();
+                match destroy_session(&sessions, &session_id) {
+                    Ok(()) => write_frame(&mut *stream.lock().unwrap(), OP_OK, &[]),
+                    Err(reason) => write_frame(&mut *stream.lock().unwrap(), OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_LIST => write_frame(&mut *stream.lock().unwrap(), OP_OK, &list_sessions(&sessions)),
+            OP_LIST_TOMBSTONES => write_frame(&mut *stream.lock().unwrap(), OP_OK, &list_tombstones(&tombstones)),
+            OP_BROADCAST => {
+                let Some((ids, data)) = decode_broadcast_payload(&payload) else {
+                    write_frame(&mut *stream.lock().unwrap(), OP_ERROR, b"malformed broadcast payload");
+                    continue;
+                };
+                match broadcast_input(&sessions, &ids, data) {
+                    Ok(()) => write_frame(&mut *stream.lock().unwrap(), OP_OK, &[]),
+                    Err(reason) => write_frame(&mut *stream.lock().unwrap(), OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_RESIZE => {
+                let Some((id, cols, rows)) = decode_resize_payload(&payload) else {
+                    write_frame(&mut *stream.lock().unwrap(), OP_ERROR, b"malformed resize payload");
+                    continue;
+                };
+                match resize_session(&sessions, &id, cols, rows) {
+                    Ok(()) => write_frame(&mut *stream.lock().unwrap(), OP_OK, &[]),
+                    Err(reason) => write_frame(&mut *stream.lock().unwrap(), OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_SIGNAL => {
+                let Some((id, signal)) = decode_signal_payload(&payload) else {
+                    write_frame(&mut *stream.lock().unwrap(), OP_ERROR, b"malformed signal payload");
+                    continue;
+                };
+                match signal_session(&sessions, &id, signal) {
+                    Ok(()) => write_frame(&mut *stream.lock().unwrap(), OP_OK, &[]),
+                    Err(reason) => write_frame(&mut *stream.lock().unwrap(), OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_TAP => write_frame(
+                &mut *stream.lock().unwrap(),
+                OP_ERROR,
+                b"fd handoff requires a plain --socket connection",
+            ),
+            _ => write_frame(&mut *stream.lock().unwrap(), OP_ERROR, b"unknown op"),
+        }
+    }
+}
+
+/// `relay_until_detached`'s TLS counterpart. The session's pump thread reaches the same
+/// `stream` through `AttachedSink::Tls` to send output, so this can't just block in a
+/// read the way the plain-fd relays do — holding the lock across a blocking read would
+/// starve the pump thread's writes for as long as the client stays quiet. Polling the
+/// raw fd for readability before taking the lock (the same idiom the single-session
+/// relay loop in `main.rs` uses to multiplex several fds) keeps the lock held only for
+/// the read itself.
+fn relay_until_detached_tls(stream: &Arc<Mutex<TlsStream>>, sessions: &Sessions, session_id: &str) {
+    let client_fd = stream.lock().unwrap().as_raw_fd();
+    let master_fd = match sessions.lock().unwrap().get(session_id) {
+        Some(session) => session.master_fd,
+        None => return,
+    };
+
+    let mut buf = [0_u8; 65_536];
+    loop {
+        let mut pfd = libc::pollfd { fd: client_fd, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut pfd, 1, -1) } <= 0 {
+            break;
+        }
+        let n = match stream.lock().unwrap().read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if write_all_fd(master_fd, &buf[..n]).is_err() {
+            break;
+        }
+        record_input(sessions, session_id, n as u64);
+    }
+
+    clear_attached_if(sessions, session_id, client_fd);
+}
+
+/// Records `n` bytes of client input against `session_id`'s `bytes_in`/`last_active`,
+/// for `OP_LIST`. Called from each relay loop after a successful write to `master_fd`.
+fn record_input(sessions: &Sessions, session_id: &str, n: u64) {
+    if let Some(session) = sessions.lock().unwrap().get_mut(session_id) {
+        session.bytes_in += n;
+        session.last_active = Instant::now();
+    }
+}
+
+fn clear_attached_if(sessions: &Sessions, session_id: &str, client_fd: RawFd) {
+    if let Some(session) = sessions.lock().unwrap().get_mut(session_id) {
+        session.attached.retain(|client| client.sink.fd() != client_fd);
+    }
+}
+
+/// Removes `id_or_name` from the registry, signals its child `SIGHUP`, and closes its
+/// pty master fd rather than waiting for `spawn_pump`'s own teardown to get around to
+/// it — a destroyed session shouldn't sit on its fd until the signaled child has
+/// actually finished exiting. `map.remove` here and in `spawn_pump`'s exit branch race
+/// on the same registry entry; whichever one actually removes it is the one that
+/// closes `master_fd`, so the fd is closed exactly once either way.
+fn destroy_session(sessions: &Sessions, id_or_name: &str) -> Result<(), String> {
+    let (pid, master_fd, attached) = {
+        let id = resolve_session_id(sessions, id_or_name).ok_or_else(|| "no such session".to_string())?;
+        let mut map = sessions.lock().unwrap();
+        let session = map.remove(&id).ok_or_else(|| "no such session".to_string())?;
+        (session.pid, session.master_fd, session.attached)
+    };
+    pty::signal_child(pid, libc::SIGHUP);
+    unsafe { libc::close(master_fd) };
+    for client in attached {
+        client.sink.shut_down();
+    }
+    Ok(())
+}
+
+/// Inserts `id`'s final status into `tombstones`, called from `spawn_pump`'s one exit
+/// path regardless of what ended the session.
+fn record_tombstone(tombstones: &Tombstones, id: String, name: Option<String>, argv: Vec<String>, exit_code: i32) {
+    tombstones.lock().unwrap().insert(id, Tombstone { name, argv, exit_code, reaped_at: Instant::now() });
+}
+
+/// Signals `SIGTERM`-then-`SIGKILL` escalation (the same two-step `OPCODE_CLOSE_GRACEFUL`
+/// uses in `main.rs`) at any session that's both unattached and has been idle for at
+/// least `idle_ms`, called periodically from the thread `run` spawns when
+/// `--reap-idle-ms` is given. A session that picks up an `OP_ATTACH` before its
+/// `reap_deadline` arrives has its deadline cleared — attaching is what "no longer
+/// idle" means here, so the escalation already in flight is called off rather than
+/// landing on a session somebody's now watching. The session only actually disappears
+/// from the registry once `spawn_pump` sees the resulting exit; this just starts that
+/// process.
+fn sweep_idle_sessions(sessions: &Sessions, idle_ms: u64, grace_ms: u64) {
+    let mut map = sessions.lock().unwrap();
+    for session in map.values_mut() {
+        if !session.attached.is_empty() {
+            session.reap_deadline = None;
+            continue;
+        }
+        match session.reap_deadline {
+            None => {
+                if session.last_active.elapsed().as_millis() as u64 >= idle_ms {
+                    pty::signal_child(session.pid, libc::SIGTERM);
+                    session.reap_deadline = Some(Instant::now() + Duration::from_millis(grace_ms));
+                }
+            }
+            Some(deadline) if Instant::now() >= deadline => {
+                pty::signal_child(session.pid, libc::SIGKILL);
+                session.reap_deadline = None;
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Drops every `Tombstone` older than `ttl_ms`, called periodically from its own
+/// thread, separate from the one `sweep_idle_sessions` runs on — tombstones accumulate from `OP_DESTROY` and ordinary child exits too,
+/// not just idle reaping, so this runs unconditionally rather than only when
+/// `--reap-idle-ms` is set.
+fn sweep_tombstones(tombstones: &Tombstones, ttl_ms: u64) {
+    let ttl = Duration::from_millis(ttl_ms);
+    tombstones.lock().unwrap().retain(|_, tombstone| tombstone.reaped_at.elapsed() < ttl);
+}
+
+/// Builds the `--state-file` snapshot of the current registry: just enough to report
+/// what was running after a restart, deliberately not `master_fd` — see `persist`'s
+/// module doc comment for why that fd wouldn't survive the trip anyway.
+fn snapshot_sessions(sessions: &Sessions) -> Vec<SessionSnapshot> {
+    sessions
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, session)| SessionSnapshot {
+            id: id.clone(),
+            name: session.name.clone(),
+            argv: session.argv.clone(),
+            pid: session.pid,
+        })
+        .collect()
+}
+
+/// Encodes one `OP_LIST_TOMBSTONES` entry: the session id, its name if it has one, its
+/// argv (same wire shape as `encode_session_info`), the exit code `crash::reap` handed
+/// back, and how long ago it was reaped.
+fn encode_tombstone(id: &str, tombstone: &Tombstone) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(id.len() as u32).to_be_bytes());
+    out.extend_from_slice(id.as_bytes());
+    match &tombstone.name {
+        Some(name) => {
+            out.push(1);
+            out.extend_from_slice(&(name.len() as u32).to_be_bytes());
+            out.extend_from_slice(name.as_bytes());
+        }
+        None => out.push(0),
+    }
+    out.extend_from_slice(&(tombstone.argv.len() as u32).to_be_bytes());
+    for arg in &tombstone.argv {
+        out.extend_from_slice(&(arg.len() as u32).to_be_bytes());
+        out.extend_from_slice(arg.as_bytes());
+    }
+    out.extend_from_slice(&tombstone.exit_code.to_be_bytes());
+    out.extend_from_slice(&(tombstone.reaped_at.elapsed().as_millis() as u64).to_be_bytes());
+    out
+}
+
+/// Builds the full `OP_LIST_TOMBSTONES` response payload: a u32be count, then each
+/// tombstone's `encode_tombstone`, sorted by id like `list_sessions`.
+fn list_tombstones(tombstones: &Tombstones) -> Vec<u8> {
+    let map = tombstones.lock().unwrap();
+    let mut ids: Vec<&String> = map.keys().collect();
+    ids.sort();
+    let mut out = Vec::new();
+    out.extend_from_slice(&(ids.len() as u32).to_be_bytes());
+    for id in ids {
+        out.extend_from_slice(&encode_tombstone(id, &map[id]));
+    }
+    out
+}
+
+/// `--sse`'s connection handler: parses one HTTP `GET /sessions/<id>` request
+/// (`sse::parse_get_request`), authenticates via `?token=` if `--token`/`--token-file`
+/// is set (there's no `OP_AUTH` frame to send on a plain HTTP request), then attaches
+/// with an `AttachedSink::Sse` exactly like `OP_ATTACH` would — same registry, same
+/// replay backlog sent first, same fan-out to whatever else is already attached. Reads
+/// from the connection afterward only to notice it close (an `EventSource` never sends
+/// a request body), never to relay input, since this endpoint is read-only by design.
+fn handle_sse_connection(mut stream: TcpStream, sessions: Sessions, token: Option<Arc<str>>) {
+    let Some((path, query)) = sse::parse_get_request(&mut stream) else { return };
+    let Some(session_id) = path.strip_prefix("/sessions/").map(str::to_string) else {
+        let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nConnection: close\r\n\r\n");
+        return;
+    };
+    if let Some(expected) = &token {
+        let given = sse::query_param(&query, "token").unwrap_or_default();
+        if !constant_time_eq(given.as_bytes(), expected.as_bytes()) {
+            let _ = stream.write_all(b"HTTP/1.1 401 Unauthorized\r\nConnection: close\r\n\r\n");
+            return;
+        }
+    }
+
+    let sink = AttachedSink::Sse(stream.as_raw_fd());
+    let (id, backlog) = match attach(&sessions, &session_id, sink) {
+        Ok(result) => result,
+        Err(reason) => {
+            let body = format!("HTTP/1.1 404 Not Found\r\nConnection: close\r\n\r\n{reason}");
+            let _ = stream.write_all(body.as_bytes());
+            return;
+        }
+    };
+    let headers = "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: keep-alive\r\n\r\n";
+    if stream.write_all(headers.as_bytes()).is_err() {
+        clear_attached_if(&sessions, &id, stream.as_raw_fd());
+        return;
+    }
+    if !backlog.is_empty() {
+        let frame = format!("data: {}\n\n", websocket::base64_encode(&backlog));
+        if stream.write_all(frame.as_bytes()).is_err() {
+            clear_attached_if(&sessions, &id, stream.as_raw_fd());
+            return;
+        }
+    }
+
+    let client_fd = stream.as_raw_fd();
+    let mut discard = [0_u8; 1024];
+    loop {
+        let n = unsafe { libc::read(client_fd, discard.as_mut_ptr().cast(), discard.len()) };
+        if n <= 0 {
+            break;
+        }
+    }
+    clear_attached_if(&sessions, &id, client_fd);
+}
+
+/// `handle_connection`'s WebSocket counterpart: performs the HTTP upgrade handshake,
+/// then speaks the identical `OP_*` control protocol with each message's payload being
+/// `[op:1][payload bytes]` — one WebSocket binary message per frame, with no embedded
+/// length prefix, since the message boundary already is the frame boundary.
+fn handle_ws_connection(
+    mut stream: std::net::TcpStream,
+    sessions: Sessions,
+    tombstones: Tombstones,
+    replay_bytes: usize,
+    token: Option<Arc<str>>,
+) {
+    if !websocket::handshake(&mut stream) {
+        return;
+    }
+    let mut authenticated = token.is_none();
+    loop {
+        let Some(message) = websocket::read_message(&mut stream) else { return };
+        let Some(&op) = message.first() else { continue };
+        let payload = &message[1..];
+        if op == OP_AUTH {
+            authenticated = authenticate(&token, payload);
+            if authenticated {
+                write_ws_frame(&mut stream, OP_OK, &[]);
+            } else {
+                write_ws_frame(&mut stream, OP_ERROR, b"bad token");
+                return;
+            }
+            continue;
+        }
+        if !authenticated {
+            write_ws_frame(&mut stream, OP_ERROR, b"authentication required");
+            return;
+        }
+        match op {
+            OP_CREATE => {
+                let Some((name, get_or_create, argv)) = decode_create_payload(payload) else {
+                    write_ws_frame(&mut stream, OP_ERROR, b"malformed create payload");
+                    continue;
+                };
+                match create_or_get_session(&sessions, &tombstones, name, get_or_create, argv, replay_bytes) {
+                    Ok(id) => write_ws_frame(&mut stream, OP_OK, id.as_bytes()),
+                    Err(reason) => write_ws_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_ATTACH => {
+                let session_id = String::from_utf8_lossy(payload).into_owned();
+                let sink = AttachedSink::WebSocket(stream.as_raw_fd());
+                match attach(&sessions, &session_id, sink) {
+                    Ok((id, backlog)) => {
+                        write_ws_frame(&mut stream, OP_OK, &[]);
+                        if !backlog.is_empty() {
+                            let _ = websocket::write_message(stream.as_raw_fd(), &backlog);
+                        }
+                        relay_until_detached_ws(&mut stream, &sessions, &id);
+                        return;
+                    }
+                    Err(reason) => write_ws_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_DESTROY => {
+                let session_id = String::from_utf8_lossy(payload).into_owned();
+                match destroy_session(&sessions, &session_id) {
+                    Ok(()) => write_ws_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_ws_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_LIST => write_ws_frame(&mut stream, OP_OK, &list_sessions(&sessions)),
+            OP_LIST_TOMBSTONES => write_ws_frame(&mut stream, OP_OK, &list_tombstones(&tombstones)),
+            OP_BROADCAST => {
+                let Some((ids, data)) = decode_broadcast_payload(payload) else {
+                    write_ws_frame(&mut stream, OP_ERROR, b"malformed broadcast payload");
+                    continue;
+                };
+                match broadcast_input(&sessions, &ids, data) {
+                    Ok(()) => write_ws_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_ws_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_RESIZE => {
+                let Some((id, cols, rows)) = decode_resize_payload(payload) else {
+                    write_ws_frame(&mut stream, OP_ERROR, b"malformed resize payload");
+                    continue;
+                };
+                match resize_session(&sessions, &id, cols, rows) {
+                    Ok(()) => write_ws_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_ws_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_SIGNAL => {
+                let Some((id, signal)) = decode_signal_payload(payload) else {
+                    write_ws_frame(&mut stream, OP_ERROR, b"malformed signal payload");
+                    continue;
+                };
+                match signal_session(&sessions, &id, signal) {
+                    Ok(()) => write_ws_frame(&mut stream, OP_OK, &[]),
+                    Err(reason) => write_ws_frame(&mut stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_TAP => write_ws_frame(&mut stream, OP_ERROR, b"fd handoff isn't possible over websocket framing"),
+            _ => write_ws_frame(&mut stream, OP_ERROR, b"unknown op"),
+        }
+    }
+}
+
+fn write_ws_frame(stream: &mut std::net::TcpStream, op: u8, payload: &[u8]) {
+    let mut message = Vec::with_capacity(1 + payload.len());
+    message.push(op);
+    message.extend_from_slice(payload);
+    let _ = websocket::write_message(stream.as_raw_fd(), &message);
+}
+
+/// `handle_ws_connection`'s TLS counterpart, combining both wrappers: the WebSocket
+/// handshake and message framing run over a `TlsStream` in place of the raw
+/// `TcpStream`, and the attached sink is `AttachedSink::WebSocketTls` so the pump
+/// thread's writes go through the same encrypted, WebSocket-framed stream.
+fn handle_ws_tls_connection(
+    tcp: TcpStream,
+    sessions: Sessions,
+    tombstones: Tombstones,
+    replay_bytes: usize,
+    tls_config: Arc<ServerConfig>,
+    token: Option<Arc<str>>,
+) {
+    let Ok(mut stream) = TlsStream::accept(tcp, tls_config) else { return };
+    if !websocket::handshake(&mut stream) {
+        return;
+    }
+    let client_fd = stream.as_raw_fd();
+    let stream = Arc::new(Mutex::new(stream));
+    let mut authenticated = token.is_none();
+    loop {
+        let Some(message) = websocket::read_message(&mut *stream.lock().unwrap()) else { return };
+        let Some(&op) = message.first() else { continue };
+        let payload = &message[1..];
+        if op == OP_AUTH {
+            authenticated = authenticate(&token, payload);
+            if authenticated {
+                write_ws_tls_frame(&stream, OP_OK, &[]);
+            } else {
+                write_ws_tls_frame(&stream, OP_ERROR, b"bad token");
+                return;
+            }
+            continue;
+        }
+        if !authenticated {
+            write_ws_tls_frame(&stream, OP_ERROR, b"authentication required");
+            return;
+        }
+        match op {
+            OP_CREATE => {
+                let Some((name, get_or_create, argv)) = decode_create_payload(payload) else {
+                    write_ws_tls_frame(&stream, OP_ERROR, b"malformed create payload");
+                    continue;
+                };
+                match create_or_get_session(&sessions, &tombstones, name, get_or_create, argv, replay_bytes) {
+                    Ok(id) => write_ws_tls_frame(&stream, OP_OK, id.as_bytes()),
+                    Err(reason) => write_ws_tls_frame(&stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_ATTACH => {
+                let session_id = String::from_utf8_lossy(payload).into_owned();
+                let sink = AttachedSink::WebSocketTls(Arc::clone(&stream), client_fd);
+                match attach(&sessions, &session_id, sink) {
+                    Ok((id, backlog)) => {
+                        write_ws_tls_frame(&stream, OP_OK, &[]);
+                        if !backlog.is_empty() {
+                            let _ = websocket::write_message_to(&mut *stream.lock().unwrap(), &backlog);
+                        }
+                        relay_until_detached_ws_tls(&stream, &sessions, &id);
+                        return;
+                    }
+                    Err(reason) => write_ws_tls_frame(&stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_DESTROY => {
+                let session_id = String::from_utf8_lossy(payload).into_owned();
+                match destroy_session(&sessions, &session_id) {
+                    Ok(()) => write_ws_tls_frame(&stream, OP_OK, &[]),
+                    Err(reason) => write_ws_tls_frame(&stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_LIST => write_ws_tls_frame(&stream, OP_OK, &list_sessions(&sessions)),
+            OP_LIST_TOMBSTONES => write_ws_tls_frame(&stream, OP_OK, &list_tombstones(&tombstones)),
+            OP_BROADCAST => {
+                let Some((ids, data)) = decode_broadcast_payload(payload) else {
+                    write_ws_tls_frame(&stream, OP_ERROR, b"malformed broadcast payload");
+                    continue;
+                };
+                match broadcast_input(&sessions, &ids, data) {
+                    Ok(()) => write_ws_tls_frame(&stream, OP_OK, &[]),
+                    Err(reason) => write_ws_tls_frame(&stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_RESIZE => {
+                let Some((id, cols, rows)) = decode_resize_payload(payload) else {
+                    write_ws_tls_frame(&stream, OP_ERROR, b"malformed resize payload");
+                    continue;
+                };
+                match resize_session(&sessions, &id, cols, rows) {
+                    Ok(()) => write_ws_tls_frame(&stream, OP_OK, &[]),
+                    Err(reason) => write_ws_tls_frame(&stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_SIGNAL => {
+                let Some((id, signal)) = decode_signal_payload(payload) else {
+                    write_ws_tls_frame(&stream, OP_ERROR, b"malformed signal payload");
+                    continue;
+                };
+                match signal_session(&sessions, &id, signal) {
+                    Ok(()) => write_ws_tls_frame(&stream, OP_OK, &[]),
+                    Err(reason) => write_ws_tls_frame(&stream, OP_ERROR, reason.as_bytes()),
+                }
+            }
+            OP_TAP => {
+                write_ws_tls_frame(&stream, OP_ERROR, b"fd handoff isn't possible over websocket framing")
+            }
+            _ => write_ws_tls_frame(&stream, OP_ERROR, b"unknown op"),
+        }
+    }
+}
+
+fn write_ws_tls_frame(stream: &Arc<Mutex<TlsStream>>, op: u8, payload: &[u8]) {
+    let mut message = Vec::with_capacity(1 + payload.len());
+    message.push(op);
+    message.extend_from_slice(payload);
+    let _ = websocket::write_message_to(&mut *stream.lock().unwrap(), &message);
+}
+
+/// `relay_until_detached_ws`'s TLS counterpart, using the same poll-then-lock pattern
+/// as `relay_until_detached_tls` so the session's pump thread isn't starved by a client
+/// that's gone quiet.
+fn relay_until_detached_ws_tls(stream: &Arc<Mutex<TlsStream>>, sessions: &Sessions, session_id: &str) {
+    let client_fd = stream.lock().unwrap().as_raw_fd();
+    let master_fd = match sessions.lock().unwrap().get(session_id) {
+        Some(session) => session.master_fd,
+        None => return,
+    };
+
+    loop {
+        let mut pfd = libc::pollfd { fd: client_fd, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut pfd, 1, -1) } <= 0 {
+            break;
+        }
+        let Some(payload) = websocket::read_message(&mut *stream.lock().unwrap()) else { break };
+        if write_all_fd(master_fd, &payload).is_err() {
+            break;
+        }
+        record_input(sessions, session_id, payload.len() as u64);
+    }
+
+    clear_attached_if(sessions, session_id, client_fd);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_matches_on_equal_bytes() {
+        assert!(constant_time_eq(b"secret-token", b"secret-token"));
+    }
+
+    #[test]
+    fn constant_time_eq_rejects_different_bytes_of_the_same_length() {
+        assert!(!constant_time_eq(b"secret-token", b"secret-tokeX"));
+    }
+
+    #[test]
+    fn constant_time_eq_rejects_different_lengths() {
+        assert!(!constant_time_eq(b"short", b"much longer"));
+    }
+
+    #[test]
+    fn authenticate_accepts_anything_with_no_configured_token() {
+        assert!(authenticate(&None, b"whatever"));
+    }
+
+    #[test]
+    fn authenticate_checks_payload_against_the_configured_token() {
+        let token: Option<Arc<str>> = Some(Arc::from("s3cr3t"));
+        assert!(authenticate(&token, b"s3cr3t"));
+        assert!(!authenticate(&token, b"wrong"));
+    }
+
+    fn encode_len_prefixed(s: &str) -> Vec<u8> {
+        let mut out = (s.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    #[test]
+    fn decode_argv_round_trips_a_simple_command() {
+        let mut payload = 2_u32.to_be_bytes().to_vec();
+        payload.extend(encode_len_prefixed("echo"));
+        payload.extend(encode_len_prefixed("hi"));
+        assert_eq!(decode_argv(&payload), Some(vec!["echo".to_string(), "hi".to_string()]));
+    }
+
+    #[test]
+    fn decode_argv_rejects_an_empty_argv() {
+        let payload = 0_u32.to_be_bytes().to_vec();
+        assert_eq!(decode_argv(&payload), None);
+    }
+
+    #[test]
+    fn decode_argv_rejects_a_truncated_payload() {
+        let mut payload = 1_u32.to_be_bytes().to_vec();
+        payload.extend_from_slice(&10_u32.to_be_bytes());
+        payload.extend_from_slice(b"short");
+        assert_eq!(decode_argv(&payload), None);
+    }
+
+    #[test]
+    fn decode_create_payload_round_trips_a_named_session() {
+        let mut payload = encode_len_prefixed("build");
+        payload.push(1);
+        payload.extend(2_u32.to_be_bytes());
+        payload.extend(encode_len_prefixed("make"));
+        payload.extend(encode_len_prefixed("-j4"));
+        let (name, get_or_create, argv) = decode_create_payload(&payload).unwrap();
+        assert_eq!(name, Some("build".to_string()));
+        assert!(get_or_create);
+        assert_eq!(argv, vec!["make".to_string(), "-j4".to_string()]);
+    }
+
+    #[test]
+    fn decode_create_payload_handles_an_anonymous_session() {
+        let mut payload = 0_u32.to_be_bytes().to_vec();
+        payload.push(0);
+        payload.extend(1_u32.to_be_bytes());
+        payload.extend(encode_len_prefixed("bash"));
+        let (name, get_or_create, argv) = decode_create_payload(&payload).unwrap();
+        assert_eq!(name, None);
+        assert!(!get_or_create);
+        assert_eq!(argv, vec!["bash".to_string()]);
+    }
+
+    #[test]
+    fn decode_broadcast_payload_splits_ids_from_trailing_data() {
+        let mut payload = 2_u32.to_be_bytes().to_vec();
+        payload.extend(encode_len_prefixed("a"));
+        payload.extend(encode_len_prefixed("b"));
+        payload.extend_from_slice(b"hello\n");
+        let (ids, data) = decode_broadcast_payload(&payload).unwrap();
+        assert_eq!(ids, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(data, b"hello\n");
+    }
+
+    #[test]
+    fn decode_broadcast_payload_rejects_an_empty_id_list() {
+        let payload = 0_u32.to_be_bytes().to_vec();
+        assert_eq!(decode_broadcast_payload(&payload), None);
+    }
+
+    #[test]
+    fn decode_resize_payload_round_trips() {
+        let mut payload = encode_len_prefixed("session-1");
+        payload.extend(100_u16.to_be_bytes());
+        payload.extend(40_u16.to_be_bytes());
+        assert_eq!(decode_resize_payload(&payload), Some(("session-1".to_string(), 100, 40)));
+    }
+
+    #[test]
+    fn decode_signal_payload_round_trips() {
+        let mut payload = encode_len_prefixed("session-1");
+        payload.push(libc::SIGTERM as u8);
+        assert_eq!(decode_signal_payload(&payload), Some(("session-1".to_string(), libc::SIGTERM)));
+    }
+
+    #[test]
+    fn decode_signal_payload_rejects_a_missing_signal_byte() {
+        let payload = encode_len_prefixed("session-1");
+        assert_eq!(decode_signal_payload(&payload), None);
+    }
+
+    /// Exercises `send_fd` over a real `AF_UNIX` socketpair and manually `recvmsg`s the
+    /// other end, the same way a real `OP_TAP` client would, to confirm the fd that
+    /// arrives is a distinct descriptor pointing at the same underlying file (same
+    /// device/inode) rather than, say, a copy of the control-message bytes.
+    #[test]
+    fn send_fd_hands_a_real_fd_to_the_other_end_of_a_socketpair() {
+        let to_send = unsafe { libc::open(c"/dev/null".as_ptr(), libc::O_RDONLY) };
+        assert!(to_send >= 0);
+
+        let mut fds = [0_i32; 2];
+        let rc = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+        assert_eq!(rc, 0);
+        let [sender, receiver] = fds;
+
+        send_fd(sender, to_send).unwrap();
+
+        let received = unsafe {
+            let mut iov_byte = [0_u8];
+            let mut iov = libc::iovec { iov_base: iov_byte.as_mut_ptr() as *mut libc::c_void, iov_len: 1 };
+            let cmsg_len = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as usize;
+            let mut cmsg_buf = vec![0_u8; cmsg_len];
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = cmsg_len as _;
+            assert!(libc::recvmsg(receiver, &mut msg, 0) >= 0);
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert!(!cmsg.is_null());
+            assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+            *(libc::CMSG_DATA(cmsg) as *const RawFd)
+        };
+
+        assert_ne!(received, to_send);
+
+        let mut original_stat: libc::stat = unsafe { mem::zeroed() };
+        let mut received_stat: libc::stat = unsafe { mem::zeroed() };
+        assert_eq!(unsafe { libc::fstat(to_send, &mut original_stat) }, 0);
+        assert_eq!(unsafe { libc::fstat(received, &mut received_stat) }, 0);
+        assert_eq!(original_stat.st_dev, received_stat.st_dev);
+        assert_eq!(original_stat.st_ino, received_stat.st_ino);
+
+        unsafe {
+            libc::close(to_send);
+            libc::close(received);
+            libc::close(sender);
+            libc::close(receiver);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! A stable identity for this `ptyd` process's session, stamped onto every event line
+//! it emits (see `fields_json`), so a client juggling several sessions can tell which
+//! one a log/event/recording line came from, and an operator can attach a
+//! human-readable name on top of the opaque UUID.
+
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct SessionContext {
+    pub id: String,
+    pub name: Option<String>,
+}
+
+impl SessionContext {
+    pub fn new(name: Option<String>) -> Self {
+        SessionContext { id: Uuid::new_v4().to_string(), name }
+    }
+
+    /// JSON object fields (no surrounding braces) identifying this session, meant to
+    /// be spliced into the front of every event line's field list.
+    pub fn fields_json(&self) -> String {
+        match &self.name {
+            Some(name) => format!("\"session_id\":\"{}\",\"session_name\":{}", self.id, crate::lifecycle::json_escape(name)),
+            None => format!("\"session_id\":\"{}\"", self.id),
+        }
+    }
+}
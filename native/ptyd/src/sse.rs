@@ -0,0 +1,108 @@
+//! Minimal HTTP/1.1 request-line parsing for `ptyd serve --sse HOST:PORT`: just enough
+//! to pull a path and query string out of a `GET` request, the same "hand-roll it, no
+//! crate" call `websocket.rs` makes for a similarly small piece of protocol surface.
+//! The actual Server-Sent Events framing and session wiring live in `serve.rs`'s
+//! `handle_sse_connection`, since that's where the session registry already is.
+
+use std::io::Read;
+
+/// Reads one HTTP request's header block up to the blank line that ends it, the same
+/// approach `websocket.rs`'s `read_http_headers` uses (byte-by-byte, no buffered
+/// reader, since nothing past the header block should be consumed here either — the
+/// body, if any, isn't this endpoint's concern), then returns just the request line.
+fn read_request_line<S: Read>(stream: &mut S) -> Option<String> {
+    let mut raw = Vec::new();
+    let mut byte = [0_u8; 1];
+    loop {
+        stream.read_exact(&mut byte).ok()?;
+        raw.push(byte[0]);
+        if raw.ends_with(b"\r\n\r\n") {
+            break;
+        }
+        if raw.len() > 64 * 1024 {
+            return None;
+        }
+    }
+    String::from_utf8_lossy(&raw).lines().next().map(str::to_string)
+}
+
+/// Parses `GET <path>[?<query>] HTTP/1.1` into its path and query string (empty if
+/// there's none). `None` for anything else: not a `GET`, or a malformed request line —
+/// `handle_sse_connection` treats either as reason to drop the connection without a
+/// response, the same way `websocket::handshake` treats a non-upgrade request.
+pub(crate) fn parse_get_request<S: Read>(stream: &mut S) -> Option<(String, String)> {
+    let line = read_request_line(stream)?;
+    let mut parts = line.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    let target = parts.next()?;
+    match target.split_once('?') {
+        Some((path, query)) => Some((path.to_string(), query.to_string())),
+        None => Some((target.to_string(), String::new())),
+    }
+}
+
+/// Pulls `name`'s value out of a `key=value&key=value` query string, unescaping only
+/// `+` into a space — good enough for the plain tokens (session ids, names, auth
+/// tokens) this endpoint ever sees in one, not a general
+/// `application/x-www-form-urlencoded` decoder.
+pub(crate) fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key == name {
+            Some(value.replace('+', " "))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn parse_get_request_splits_path_from_query() {
+        let mut stream = Cursor::new(b"GET /stream?session=abc HTTP/1.1\r\nHost: x\r\n\r\n".to_vec());
+        let (path, query) = parse_get_request(&mut stream).unwrap();
+        assert_eq!(path, "/stream");
+        assert_eq!(query, "session=abc");
+    }
+
+    #[test]
+    fn parse_get_request_accepts_a_path_with_no_query() {
+        let mut stream = Cursor::new(b"GET /stream HTTP/1.1\r\n\r\n".to_vec());
+        let (path, query) = parse_get_request(&mut stream).unwrap();
+        assert_eq!(path, "/stream");
+        assert_eq!(query, "");
+    }
+
+    #[test]
+    fn parse_get_request_rejects_non_get_methods() {
+        let mut stream = Cursor::new(b"POST /stream HTTP/1.1\r\n\r\n".to_vec());
+        assert!(parse_get_request(&mut stream).is_none());
+    }
+
+    #[test]
+    fn parse_get_request_rejects_a_truncated_header_block() {
+        let mut stream = Cursor::new(b"GET /stream HTTP/1.1\r\n".to_vec());
+        assert!(parse_get_request(&mut stream).is_none());
+    }
+
+    #[test]
+    fn query_param_finds_the_named_value_among_others() {
+        assert_eq!(query_param("a=1&session=abc&token=xyz", "session"), Some("abc".to_string()));
+    }
+
+    #[test]
+    fn query_param_unescapes_plus_as_a_space() {
+        assert_eq!(query_param("name=foo+bar", "name"), Some("foo bar".to_string()));
+    }
+
+    #[test]
+    fn query_param_returns_none_when_absent() {
+        assert_eq!(query_param("a=1&b=2", "c"), None);
+    }
+}
@@ -0,0 +1,137 @@
+//! `--summary`: a single JSON line emitted on exit with the facts a wrapper script
+//! most often wants without parsing the rest of the event stream — duration, bytes
+//! relayed in each direction, how deep the output backlog ever got, exit reason,
+//! resource usage, and the recording path (if any). Everything in it is already
+//! computed or available elsewhere (`history.rs` records the same session/exit/
+//! resource-usage facts to disk); this just restates the high-value subset as one
+//! line to stderr for a caller that wants it without a history lookup.
+//!
+//! The running counters here (everything but `peak_buffer_depth`) are also what
+//! `OPCODE_STATS` reports mid-session (see `emit_stats` in `main.rs`) — they're
+//! always maintained regardless of `enabled`, which only gates the exit-time line.
+
+use crate::protocol::write_all_fd;
+use crate::rusage::ResourceDelta;
+use crate::session::SessionContext;
+
+pub struct SessionSummary {
+    enabled: bool,
+    bytes_in: u64,
+    bytes_out: u64,
+    peak_buffer_depth: usize,
+    frames_in: u64,
+    frames_out: u64,
+    dropped_opcodes: u64,
+    last_dropped_opcode: Option<u8>,
+}
+
+impl SessionSummary {
+    pub fn new(enabled: bool) -> Self {
+        SessionSummary {
+            enabled,
+            bytes_in: 0,
+            bytes_out: 0,
+            peak_buffer_depth: 0,
+            frames_in: 0,
+            frames_out: 0,
+            dropped_opcodes: 0,
+            last_dropped_opcode: None,
+        }
+    }
+
+    /// Call once per chunk of input relayed to the pty master.
+    pub fn record_input(&mut self, n: usize) {
+        self.bytes_in += n as u64;
+    }
+
+    /// Call once per chunk of master output read, alongside the output buffer's
+    /// depth right after that chunk was pushed onto it.
+    pub fn record_output(&mut self, n: usize, buffer_depth: usize) {
+        self.bytes_out += n as u64;
+        self.peak_buffer_depth = self.peak_buffer_depth.max(buffer_depth);
+    }
+
+    /// Call once per input control frame dispatched (everything `parse_frames`
+    /// produces a `FrameEvent` for). Raw `OPCODE_DATA`/`OPCODE_DATA_CRC32` payloads
+    /// aren't counted here — their volume is already covered by `bytes_in`.
+    pub fn record_frame_in(&mut self) {
+        self.frames_in += 1;
+    }
+
+    /// Call once per output data frame written to stdout.
+    pub fn record_frame_out(&mut self) {
+        self.frames_out += 1;
+    }
+
+    /// Call once per byte whose leading opcode didn't match any known frame kind,
+    /// dropped rather than relayed anywhere (see `FrameEvent::UnknownOpcode`).
+    pub fn record_dropped_opcode(&mut self, opcode: u8) {
+        self.dropped_opcodes += 1;
+        self.last_dropped_opcode = Some(opcode);
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out
+    }
+
+    pub fn frames_in(&self) -> u64 {
+        self.frames_in
+    }
+
+    pub fn frames_out(&self) -> u64 {
+        self.frames_out
+    }
+
+    pub fn dropped_opcodes(&self) -> u64 {
+        self.dropped_opcodes
+    }
+
+    pub fn last_dropped_opcode(&self) -> Option<u8> {
+        self.last_dropped_opcode
+    }
+
+    /// Short description of how the session ended, using the same 128+signum
+    /// convention `pty::child_exit_code` encodes (see there), unless `override_reason`
+    /// gives a more specific one (e.g. `--cpu-budget-ms`'s "cpu-budget-exceeded" kill,
+    /// which is also a signaled exit but not one worth reporting as plain "signaled").
+    fn exit_reason(exit_code: i32, override_reason: Option<&str>) -> &str {
+        override_reason.unwrap_or(if exit_code >= 128 { "signaled" } else { "exited" })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn emit(
+        &self,
+        session: &SessionContext,
+        started_at_unix_ms: i64,
+        ended_at_unix_ms: i64,
+        exit_code: i32,
+        delta: &ResourceDelta,
+        recording_path: Option<&str>,
+        override_reason: Option<&str>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let recording_field = match recording_path {
+            Some(path) => crate::lifecycle::json_escape(path),
+            None => "null".to_string(),
+        };
+        let line = format!(
+            "{{{},\"event\":\"summary\",\"duration_ms\":{},\"bytes_in\":{},\"bytes_out\":{},\"peak_buffer_depth\":{},\"exit_code\":{exit_code},\"exit_reason\":\"{}\",\"cpu_user_ms\":{},\"cpu_sys_ms\":{},\"peak_rss_kb\":{},\"recording_path\":{recording_field}}}\n",
+            session.fields_json(),
+            ended_at_unix_ms - started_at_unix_ms,
+            self.bytes_in,
+            self.bytes_out,
+            self.peak_buffer_depth,
+            Self::exit_reason(exit_code, override_reason),
+            delta.user_ms,
+            delta.sys_ms,
+            delta.max_rss_kb,
+        );
+        let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+    }
+}
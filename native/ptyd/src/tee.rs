@@ -0,0 +1,38 @@
+//! Optional recording of master output to a log file alongside the normal relay to
+//! stdout.
+//!
+//! `copy_file_range`/`sendfile` let the kernel copy bytes between two fds without a
+//! userspace round trip, but both require the source to be a regular file (or, for
+//! `sendfile`, something backed by a page cache); a pty master is a character device,
+//! so there's nothing to hand either syscall here. By the time ptyd has master output
+//! to tee, it's already a userspace buffer it just `read()` out of the pty — recording
+//! it is an ordinary `write()`, not a copy between two fds.
+
+use libc::c_int;
+
+use crate::protocol::write_all_fd;
+
+pub struct TeeFile {
+    fd: c_int,
+}
+
+impl TeeFile {
+    pub fn open(path: &str) -> Option<Self> {
+        let cpath = std::ffi::CString::new(path).ok()?;
+        let fd = unsafe { libc::open(cpath.as_ptr(), libc::O_CREAT | libc::O_WRONLY | libc::O_APPEND, 0o600) };
+        if fd < 0 {
+            return None;
+        }
+        Some(TeeFile { fd })
+    }
+
+    pub fn write(&self, bytes: &[u8]) -> Result<(), ()> {
+        write_all_fd(self.fd, bytes)
+    }
+}
+
+impl Drop for TeeFile {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
@@ -0,0 +1,81 @@
+//! `--max-input-bytes-per-sec N`: a token-bucket cap on how fast client-sent input is
+//! relayed to the child, so a misbehaving automation client spamming the pty can't
+//! lock it up. The bucket holds up to one second's worth of bytes; bytes beyond what
+//! the bucket can currently admit are dropped (not queued — queuing would just move
+//! the flood rather than stop it) and reported via a `throttled` event.
+
+use crate::clock::SharedClock;
+
+pub struct InputRateLimiter {
+    max_bytes_per_sec: f64,
+    tokens: f64,
+    last_refill_ms: i64,
+    clock: SharedClock,
+}
+
+impl InputRateLimiter {
+    pub fn new(max_bytes_per_sec: u64, clock: SharedClock) -> Self {
+        let max_bytes_per_sec = max_bytes_per_sec.max(1) as f64;
+        let last_refill_ms = clock.monotonic_ms();
+        InputRateLimiter {
+            max_bytes_per_sec,
+            tokens: max_bytes_per_sec,
+            last_refill_ms,
+            clock,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now_ms = self.clock.monotonic_ms();
+        let elapsed_secs = now_ms.saturating_sub(self.last_refill_ms).max(0) as f64 / 1_000.0;
+        self.last_refill_ms = now_ms;
+        self.tokens = (self.tokens + elapsed_secs * self.max_bytes_per_sec).min(self.max_bytes_per_sec);
+    }
+
+    /// Admits as many of `n` bytes as the bucket currently allows, returning
+    /// `(admitted, dropped)`.
+    pub fn admit(&mut self, n: usize) -> (usize, usize) {
+        self.refill();
+        let admitted = (self.tokens.floor() as usize).min(n);
+        self.tokens -= admitted as f64;
+        (admitted, n - admitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use std::rc::Rc;
+
+    #[test]
+    fn starts_with_a_full_bucket() {
+        let clock = FakeClock::new(0);
+        let mut limiter = InputRateLimiter::new(100, Rc::new(clock));
+        assert_eq!(limiter.admit(100), (100, 0));
+    }
+
+    #[test]
+    fn drops_bytes_beyond_the_bucket_with_no_elapsed_time() {
+        let clock = FakeClock::new(0);
+        let mut limiter = InputRateLimiter::new(100, Rc::new(clock));
+        assert_eq!(limiter.admit(150), (100, 50));
+        // The bucket is now empty; with no time having passed, nothing else is admitted.
+        assert_eq!(limiter.admit(1), (0, 1));
+    }
+
+    #[test]
+    fn refills_deterministically_as_the_fake_clock_advances() {
+        let clock = FakeClock::new(0);
+        let mut limiter = InputRateLimiter::new(100, Rc::new(clock.clone()));
+        assert_eq!(limiter.admit(100), (100, 0));
+
+        // Half a second at 100 bytes/sec should refill 50 tokens, no more.
+        clock.advance_ms(500);
+        assert_eq!(limiter.admit(100), (50, 50));
+
+        // Advancing well past a full second never overfills past the bucket's cap.
+        clock.advance_ms(10_000);
+        assert_eq!(limiter.admit(1_000), (100, 900));
+    }
+}
@@ -0,0 +1,76 @@
+//! `--child-encoding NAME`: some vendor CLIs still emit a legacy 8-bit or
+//! double-byte encoding (`latin-1`, `shift-jis`, `gbk`) instead of UTF-8, which
+//! renders as mojibake once relayed straight through to a UTF-8 host. This wraps
+//! `encoding_rs`'s incremental decoder/encoder so output is transcoded legacy → UTF-8
+//! before it reaches stdout/tee/scrollback, and input is transcoded UTF-8 → legacy
+//! before it reaches the child — in both directions, one chunk at a time, carrying
+//! any multi-byte sequence split across a chunk boundary over to the next call rather
+//! than losing or mis-decoding it.
+
+use encoding_rs::{Decoder, Encoding};
+
+/// Resolves a user-facing name (`latin-1`, `shift-jis`, `gbk`, ...) to an
+/// `encoding_rs` encoding. Accepts the same labels the Encoding Standard defines,
+/// since that's what `encoding_rs` already parses.
+pub fn lookup(name: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(name.as_bytes())
+}
+
+/// Decodes one chunk of child output from `encoding`'s bytes to UTF-8, buffering any
+/// trailing partial multi-byte sequence for the next chunk.
+pub struct OutputTranscoder {
+    decoder: Decoder,
+}
+
+impl OutputTranscoder {
+    pub fn new(encoding: &'static Encoding) -> Self {
+        OutputTranscoder {
+            decoder: encoding.new_decoder(),
+        }
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        // `decode_to_string` writes only into spare capacity, so reserve the worst case.
+        let capacity = self.decoder.max_utf8_buffer_length(bytes.len()).unwrap_or(bytes.len() * 3);
+        let mut out = String::with_capacity(capacity);
+        let (_result, _read, _had_errors) = self.decoder.decode_to_string(bytes, &mut out, false);
+        out
+    }
+}
+
+/// Encodes one chunk of host input from UTF-8 to `encoding`'s bytes, buffering any
+/// trailing partial UTF-8 sequence for the next chunk.
+pub struct InputTranscoder {
+    encoding: &'static Encoding,
+    pending: Vec<u8>,
+}
+
+impl InputTranscoder {
+    pub fn new(encoding: &'static Encoding) -> Self {
+        InputTranscoder {
+            encoding,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn encode(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(bytes);
+        // An invalid sequence can never complete, so it goes through lossily below; only
+        // a sequence cut off at the very end is held for the next chunk.
+        let mut valid_len = self.pending.len();
+        let mut start = 0;
+        while let Err(err) = std::str::from_utf8(&self.pending[start..]) {
+            match err.error_len() {
+                Some(bad_len) => start += err.valid_up_to() + bad_len,
+                None => {
+                    valid_len = start + err.valid_up_to();
+                    break;
+                }
+            }
+        }
+        let text = String::from_utf8_lossy(&self.pending[..valid_len]).into_owned();
+        let (encoded, _, _) = self.encoding.encode(&text);
+        self.pending.drain(..valid_len);
+        encoded.into_owned()
+    }
+}
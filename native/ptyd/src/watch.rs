@@ -0,0 +1,248 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::clock::{Clock, SystemClock};
+use crate::pty::{child_exit_code, fork_and_exec, open_pty, signal_child};
+use crate::protocol::{apply_resize, parse_and_apply_frames, write_all_fd, FrameEvent};
+
+const DEFAULT_DEBOUNCE_MS: u64 = 200;
+const POLL_INTERVAL_MS: i32 = 100;
+
+struct WatchArgs {
+    paths: Vec<PathBuf>,
+    debounce_ms: u64,
+    command: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Option<WatchArgs> {
+    let mut paths = Vec::new();
+    let mut debounce_ms = DEFAULT_DEBOUNCE_MS;
+    let mut idx = 0;
+
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--path" => {
+                let value = args.get(idx + 1)?;
+                paths.push(PathBuf::from(value));
+                idx += 2;
+            }
+            "--debounce-ms" => {
+                let value = args.get(idx + 1)?;
+                debounce_ms = value.parse().ok()?;
+                idx += 2;
+            }
+            "--" => {
+                idx += 1;
+                break;
+            }
+            _ => return None,
+        }
+    }
+
+    let command = args[idx..].to_vec();
+    if paths.is_empty() || command.is_empty() {
+        return None;
+    }
+
+    Some(WatchArgs { paths, debounce_ms, command })
+}
+
+fn newest_mtime_under(path: &Path, newest: &mut SystemTime) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+
+    if let Ok(modified) = metadata.modified() {
+        if modified > *newest {
+            *newest = modified;
+        }
+    }
+
+    if metadata.is_dir() {
+        let entries = match fs::read_dir(path) {
+            Ok(e) => e,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            newest_mtime_under(&entry.path(), newest);
+        }
+    }
+}
+
+fn snapshot_mtime(paths: &[PathBuf]) -> SystemTime {
+    let mut newest = UNIX_EPOCH;
+    for path in paths {
+        newest_mtime_under(path, &mut newest);
+    }
+    newest
+}
+
+fn emit_event(name: &str, run_seq: u64, extra: &str) {
+    let line = if extra.is_empty() {
+        format!("{{\"event\":\"{name}\",\"run\":{run_seq}}}\n")
+    } else {
+        format!("{{\"event\":\"{name}\",\"run\":{run_seq},{extra}}}\n")
+    };
+    let _ = write_all_fd(libc::STDERR_FILENO, line.as_bytes());
+}
+
+/// Whether `debounce_ms` has elapsed since the most recent watched-path change was seen
+/// (`last_change_seen_ms`), i.e. whether edits have settled enough to fire a restart.
+/// Factored out of `run_once`'s loop so this decision — not the pty/process plumbing
+/// around it — can be driven deterministically with a `FakeClock` in tests.
+fn quiescence_reached(last_change_seen_ms: Option<i64>, now_ms: i64, debounce_ms: i64) -> bool {
+    matches!(last_change_seen_ms, Some(seen_ms) if now_ms - seen_ms >= debounce_ms)
+}
+
+/// Runs `command` to completion inside a fresh pty, relaying stdin/stdout like the default
+/// mode, but bailing out early (returning `None`) if a watched path changes mid-run.
+///
+/// Quiescence (has it been `debounce_ms` since the last watched-path change, so the
+/// restart can fire once edits settle rather than on every single write) is decided
+/// from `clock`, not directly from the wall clock, so that decision is unit-testable
+/// with a `FakeClock` and a controlled sequence of `advance_ms` calls instead of a real
+/// sleep. The mtime comparisons themselves stay on real `SystemTime`, since faking
+/// those would mean faking the filesystem too.
+fn run_once(command: &[String], watch_paths: &[PathBuf], debounce_ms: u64, baseline: SystemTime, clock: &dyn Clock) -> Option<i32> {
+    let pair = open_pty()?;
+    let pid = fork_and_exec(command, pair.master_fd, pair.slave_fd)?;
+
+    let mut incoming: Vec<u8> = Vec::with_capacity(8192);
+    let mut io_buf = vec![0_u8; 65_536];
+    let mut stdin_open = true;
+    let mut last_change_seen_ms: Option<i64> = None;
+    let mut newest_seen = baseline;
+    let debounce_ms = debounce_ms as i64;
+    let mut frame_events: Vec<FrameEvent> = Vec::new();
+
+    loop {
+        let mut status: libc::c_int = 0;
+        let waited = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+        if waited == pid {
+            unsafe { libc::close(pair.master_fd) };
+            return Some(child_exit_code(status));
+        }
+
+        let current = snapshot_mtime(watch_paths);
+        if current > newest_seen {
+            newest_seen = current;
+            last_change_seen_ms = Some(clock.unix_ms());
+        }
+        if quiescence_reached(last_change_seen_ms, clock.unix_ms(), debounce_ms) {
+            signal_child(pid, libc::SIGTERM);
+            let mut status2: libc::c_int = 0;
+            unsafe { libc::waitpid(pid, &mut status2, 0) };
+            unsafe { libc::close(pair.master_fd) };
+            return None;
+        }
+
+        let stdin_fd = if stdin_open { libc::STDIN_FILENO } else { -1 };
+        let mut pfds = [
+            libc::pollfd { fd: stdin_fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: pair.master_fd, events: libc::POLLIN, revents: 0 },
+        ];
+
+        let poll_rc = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as _, POLL_INTERVAL_MS) };
+        if poll_rc < 0 {
+            continue;
+        }
+
+        if stdin_open && (pfds[0].revents & libc::POLLIN) != 0 {
+            let n = unsafe { libc::read(libc::STDIN_FILENO, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+            if n == 0 {
+                stdin_open = false;
+            } else if n > 0 {
+                incoming.extend_from_slice(&io_buf[..n as usize]);
+                if parse_and_apply_frames(
+                    &mut incoming,
+                    pair.master_fd,
+                    pid,
+                    &mut frame_events,
+                    None,
+                    &crate::protocol::ResizeBounds::DEFAULT,
+                    None,
+                    None,
+                )
+                .is_err()
+                {
+                    unsafe { libc::close(pair.master_fd) };
+                    return Some(1);
+                }
+                for event in frame_events.drain(..) {
+                    if let FrameEvent::Resize { cols, rows, .. } = event {
+                        let _ = apply_resize(pair.master_fd, pid, cols, rows, 0, 0);
+                    }
+                }
+            }
+        }
+
+        if (pfds[1].revents & libc::POLLIN) != 0 {
+            let n = unsafe { libc::read(pair.master_fd, io_buf.as_mut_ptr().cast(), io_buf.len()) };
+            if n == 0 {
+                let mut status2: libc::c_int = 0;
+                unsafe { libc::waitpid(pid, &mut status2, 0) };
+                unsafe { libc::close(pair.master_fd) };
+                return Some(child_exit_code(status2));
+            }
+            if n > 0 && write_all_fd(libc::STDOUT_FILENO, &io_buf[..n as usize]).is_err() {
+                unsafe { libc::close(pair.master_fd) };
+                return Some(1);
+            }
+        }
+    }
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let watch_args = match parse_args(args) {
+        Some(w) => w,
+        None => {
+            eprintln!("usage: ptyd watch --path <path> [--debounce-ms <ms>] -- <command> [args...]");
+            return 2;
+        }
+    };
+
+    let clock = SystemClock;
+    let mut run_seq: u64 = 0;
+    loop {
+        run_seq += 1;
+        let baseline = snapshot_mtime(&watch_args.paths);
+        emit_event("run-start", run_seq, "");
+
+        match run_once(&watch_args.command, &watch_args.paths, watch_args.debounce_ms, baseline, &clock) {
+            Some(code) => {
+                emit_event("run-end", run_seq, &format!("\"exit_code\":{code}"));
+                return code;
+            }
+            None => {
+                emit_event("run-end", run_seq, "\"exit_code\":null,\"reason\":\"restarted\"");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_reached_with_no_change_seen_yet() {
+        assert!(!quiescence_reached(None, 10_000, 200));
+    }
+
+    #[test]
+    fn not_reached_before_the_debounce_window_elapses() {
+        assert!(!quiescence_reached(Some(1_000), 1_199, 200));
+    }
+
+    #[test]
+    fn reached_exactly_at_the_debounce_window() {
+        assert!(quiescence_reached(Some(1_000), 1_200, 200));
+    }
+
+    #[test]
+    fn reached_well_past_the_debounce_window() {
+        assert!(quiescence_reached(Some(1_000), 5_000, 200));
+    }
+}
@@ -0,0 +1,353 @@
+//! Minimal RFC 6455 WebSocket framing for `ptyd serve --ws`, just enough to let a
+//! browser speak the same control protocol `serve.rs` already speaks over Unix/TCP:
+//! the opening HTTP upgrade handshake, and binary message framing in both directions.
+//! No text frames, no fragmentation, no extensions/subprotocols — a browser's
+//! `WebSocket` API sends and receives whole binary messages already, so there's
+//! nothing here that needs them. Hand-rolled rather than pulling in a crate, the same
+//! call `crc32.rs` makes for a similarly small, rarely-hot piece of wire format.
+
+use std::io::{Read, Write};
+use std::os::unix::io::RawFd;
+
+use crate::protocol::write_all_fd;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Reads the client's HTTP upgrade request line-by-line up to the blank line that ends
+/// it, pulls `Sec-WebSocket-Key` out of the headers, and writes back the `101 Switching
+/// Protocols` response with the matching `Sec-WebSocket-Accept`. Returns `false` (and
+/// writes nothing useful) on a malformed or non-upgrade request, which the caller
+/// treats as reason to drop the connection.
+pub fn handshake<S: Read + Write>(stream: &mut S) -> bool {
+    let Some(request) = read_http_headers(stream) else { return false };
+    let Some(key) = find_header(&request, "sec-websocket-key") else { return false };
+
+    let mut accept_input = key.into_bytes();
+    accept_input.extend_from_slice(GUID.as_bytes());
+    let accept = base64_encode(&sha1(&accept_input));
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    );
+    stream.write_all(response.as_bytes()).is_ok()
+}
+
+/// Reads one HTTP request's header lines (request line plus `Name: value` lines) up to
+/// and including the blank line that terminates them, byte by byte since there's no
+/// buffered reader here and nothing past the header block should be consumed.
+fn read_http_headers<S: Read>(stream: &mut S) -> Option<Vec<String>> {
+    let mut raw = Vec::new();
+    let mut byte = [0_u8; 1];
+    loop {
+        stream.read_exact(&mut byte).ok()?;
+        raw.push(byte[0]);
+        if raw.ends_with(b"\r\n\r\n") {
+            break;
+        }
+        if raw.len() > 64 * 1024 {
+            return None;
+        }
+    }
+    let text = String::from_utf8_lossy(&raw);
+    Some(text.lines().map(str::to_string).collect())
+}
+
+fn find_header(lines: &[String], name: &str) -> Option<String> {
+    for line in lines {
+        if let Some((key, value)) = line.split_once(':') {
+            if key.trim().eq_ignore_ascii_case(name) {
+                return Some(value.trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Reads one WebSocket message from a client, transparently answering pings with a
+/// pong and looping past them rather than handing one back as data. `None` once a
+/// close frame, EOF, or a malformed frame is seen — same "connection is done" meaning
+/// `read_control_frame` gives its callers in `serve.rs`. Fragmented messages (`FIN` 0)
+/// aren't supported, since neither a browser's `send(ArrayBuffer)` nor anything in this
+/// crate ever produces one.
+pub fn read_message<S: Read + Write>(stream: &mut S) -> Option<Vec<u8>> {
+    loop {
+        let mut header = [0_u8; 2];
+        stream.read_exact(&mut header).ok()?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+        let len_bits = header[1] & 0x7F;
+        if !fin {
+            return None;
+        }
+
+        let len = match len_bits {
+            126 => {
+                let mut ext = [0_u8; 2];
+                stream.read_exact(&mut ext).ok()?;
+                u16::from_be_bytes(ext) as u64
+            }
+            127 => {
+                let mut ext = [0_u8; 8];
+                stream.read_exact(&mut ext).ok()?;
+                u64::from_be_bytes(ext)
+            }
+            n => n as u64,
+        };
+
+        let mask = if masked {
+            let mut mask = [0_u8; 4];
+            stream.read_exact(&mut mask).ok()?;
+            Some(mask)
+        } else {
+            None
+        };
+
+        let mut payload = vec![0_u8; len as usize];
+        stream.read_exact(&mut payload).ok()?;
+        if let Some(mask) = mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        match opcode {
+            0x2 => return Some(payload),
+            0x8 => return None,
+            0x9 => write_frame_fd(&mut *stream, 0xA, &payload).ok()?,
+            0xA => {}
+            _ => return None,
+        }
+    }
+}
+
+/// Writes one unmasked binary frame — server-to-client WebSocket frames are never
+/// masked, only client-to-server ones (RFC 6455 §5.1).
+pub fn write_message(fd: RawFd, data: &[u8]) -> Result<(), ()> {
+    let mut frame = frame_header(0x2, data.len());
+    frame.extend_from_slice(data);
+    write_all_fd(fd, &frame)
+}
+
+/// `write_message`'s counterpart for a stream reached through `Write` rather than a raw
+/// fd, such as a TLS connection where writing the fd directly would bypass encryption.
+pub fn write_message_to<S: Write>(stream: &mut S, data: &[u8]) -> Result<(), ()> {
+    write_frame_fd(stream, 0x2, data)
+}
+
+fn write_frame_fd<S: Write>(stream: &mut S, opcode: u8, data: &[u8]) -> Result<(), ()> {
+    let mut frame = frame_header(opcode, data.len());
+    frame.extend_from_slice(data);
+    stream.write_all(&frame).map_err(|_| ())
+}
+
+fn frame_header(opcode: u8, len: usize) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    frame
+}
+
+/// SHA-1 of `data`, used only for the handshake's `Sec-WebSocket-Accept` digest —
+/// nowhere near enough of a hot path, or an adversarial-input surface, to justify a
+/// crate for what RFC 3174 spells out in about thirty lines.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+
+    let bit_len = (data.len() as u64) * 8;
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&bit_len.to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0_u32; 80];
+        for (i, word) in w.iter_mut().take(16).enumerate() {
+            *word = u32::from_be_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | ((!b) & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0_u8; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b0 = chunk[0];
+        let b1 = chunk.get(1).copied();
+        let b2 = chunk.get(2).copied();
+
+        out.push(BASE64_ALPHABET[(b0 >> 2) as usize] as char);
+        out.push(BASE64_ALPHABET[((b0 & 0x03) << 4 | b1.unwrap_or(0) >> 4) as usize] as char);
+        out.push(match b1 {
+            Some(b1) => BASE64_ALPHABET[((b1 & 0x0F) << 2 | b2.unwrap_or(0) >> 6) as usize] as char,
+            None => '=',
+        });
+        out.push(match b2 {
+            Some(b2) => BASE64_ALPHABET[(b2 & 0x3F) as usize] as char,
+            None => '=',
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A `Read + Write` double backed by two in-memory buffers, standing in for a
+    /// socket so `handshake`/`read_message`/`write_message_to` can be exercised
+    /// without a real connection.
+    struct TestStream {
+        incoming: Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+    }
+
+    impl TestStream {
+        fn new(incoming: Vec<u8>) -> Self {
+            TestStream { incoming: Cursor::new(incoming), outgoing: Vec::new() }
+        }
+    }
+
+    impl Read for TestStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    impl Write for TestStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // RFC 6455 §1.3's own worked example.
+    #[test]
+    fn handshake_computes_the_accept_header_from_rfc6455s_example() {
+        let request = "GET /chat HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let mut stream = TestStream::new(request.as_bytes().to_vec());
+        assert!(handshake(&mut stream));
+        let response = String::from_utf8(stream.outgoing).unwrap();
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+    }
+
+    #[test]
+    fn handshake_rejects_a_request_with_no_key() {
+        let mut stream = TestStream::new(b"GET /chat HTTP/1.1\r\n\r\n".to_vec());
+        assert!(!handshake(&mut stream));
+    }
+
+    #[test]
+    fn read_message_unmasks_a_client_binary_frame() {
+        let mask = [0x11, 0x22, 0x33, 0x44];
+        let payload = b"hello";
+        let mut frame = vec![0x82, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        let mut stream = TestStream::new(frame);
+        assert_eq!(read_message(&mut stream), Some(payload.to_vec()));
+    }
+
+    #[test]
+    fn read_message_answers_a_ping_with_a_pong_then_returns_the_next_data_frame() {
+        let ping = vec![0x89, 0x00];
+        let payload = b"hi";
+        let mut data_frame = vec![0x82, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+        data_frame.extend_from_slice(payload);
+        let mut frame = ping;
+        frame.extend(data_frame);
+        let mut stream = TestStream::new(frame);
+        assert_eq!(read_message(&mut stream), Some(payload.to_vec()));
+        assert_eq!(stream.outgoing, vec![0x8A, 0x00]);
+    }
+
+    #[test]
+    fn read_message_returns_none_on_a_close_frame() {
+        let mut stream = TestStream::new(vec![0x88, 0x00]);
+        assert_eq!(read_message(&mut stream), None);
+    }
+
+    #[test]
+    fn write_message_to_frames_a_small_payload_with_a_single_length_byte() {
+        let mut stream = TestStream::new(Vec::new());
+        write_message_to(&mut stream, b"hi").unwrap();
+        assert_eq!(stream.outgoing, vec![0x82, 0x02, b'h', b'i']);
+    }
+
+    #[test]
+    fn frame_header_uses_the_16_bit_extended_length_above_125_bytes() {
+        let header = frame_header(0x2, 200);
+        assert_eq!(header, vec![0x82, 126, 0, 200]);
+    }
+
+    #[test]
+    fn sha1_matches_known_test_vectors() {
+        assert_eq!(sha1(b""), hex("da39a3ee5e6b4b0d3255bfef95601890afd80709"));
+        assert_eq!(sha1(b"abc"), hex("a9993e364706816aba3e25717850c26c9cd0d89d"));
+    }
+
+    fn hex(s: &str) -> [u8; 20] {
+        let mut out = [0_u8; 20];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn base64_encode_matches_rfc_4648_test_vectors() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+        assert_eq!(base64_encode(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+}